@@ -0,0 +1,368 @@
+use std::error::Error;
+use std::fs::File;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use rusqlite::Connection;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{CODEC_TYPE_NULL, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::default::{get_codecs, get_probe};
+
+use crate::classify::{Category, Features, classify};
+use crate::db::{load_unanalyzed_samples, open_db, store_analysis};
+use crate::duplicates::{audio_hash, fingerprint};
+use crate::key::detect_key;
+use crate::loudness::{Loudness, measure_loudness};
+use crate::pitch::detect_pitch;
+use crate::sample::Sample;
+use crate::similarity::feature_vector;
+use crate::tempo::{detect_tempo, tempo_from_filename};
+
+/// Stored with each analyzed sample. Bump it when an analyzer changes so the
+/// next pass analyzes every sample again.
+pub const ANALYSIS_VERSION: u32 = 7;
+
+/// Only the start of long files is decoded; it's plenty for tempo and key,
+/// and loudness of longer files is measured over it too.
+const MAX_ANALYSIS_SECONDS: u64 = 120;
+
+/// What the analysis pass learns about a sample from its audio.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Analysis {
+    pub bpm: Option<f64>,
+    pub bpm_confidence: Option<f32>,
+    pub key: Option<String>,
+    pub key_confidence: Option<f32>,
+    pub loudness: Option<Loudness>,
+    pub pitch: Option<f64>,
+    pub pitch_confidence: Option<f32>,
+    pub category: Option<(Category, f32)>,
+    /// What similar-sound search compares, see `similarity::feature_vector`
+    pub feature_vector: Option<Vec<f32>>,
+    /// Hash and fingerprint of the decoded audio, see `duplicates`
+    pub audio_hash: Option<String>,
+    pub fingerprint: Option<Vec<u32>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum AnalysisEvent {
+    Started {
+        total: usize,
+    },
+    Analyzed {
+        sample_id: isize,
+        analysis: Analysis,
+    },
+    Error {
+        path: PathBuf,
+        message: String,
+    },
+    Finished {
+        cancelled: bool,
+    },
+}
+
+/// Progress of the running analysis pass, as shown in the UI.
+#[derive(Debug, Clone, Default)]
+pub struct AnalysisProgress {
+    pub total: usize,
+    pub done: usize,
+}
+
+impl AnalysisProgress {
+    pub fn apply(&mut self, event: &AnalysisEvent) {
+        match event {
+            AnalysisEvent::Started { total } => {
+                *self = Self {
+                    total: *total,
+                    done: 0,
+                }
+            }
+            AnalysisEvent::Analyzed { .. } => self.done += 1,
+            AnalysisEvent::Error { .. } | AnalysisEvent::Finished { .. } => {}
+        }
+    }
+}
+
+/// Runs `analyze_library` on a background thread with its own database
+/// connection and streams the results back to the UI thread.
+pub struct AnalysisWorker {
+    receiver: Receiver<AnalysisEvent>,
+    cancel: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl AnalysisWorker {
+    pub fn spawn(db_path: &str) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let db_path = db_path.to_string();
+        let cancel_worker = Arc::clone(&cancel);
+        let handle = thread::spawn(move || {
+            let run = || {
+                open_db(&db_path)
+                    .and_then(|conn| {
+                        // The importer and the filesystem watcher may be writing at the same time
+                        conn.busy_timeout(Duration::from_secs(5))?;
+                        Ok(conn)
+                    })
+                    .map_err(|e| e.into())
+                    .and_then(|conn| {
+                        analyze_library(&conn, &cancel_worker, &mut |event| {
+                            let _ = sender.send(event);
+                        })
+                    })
+            };
+            // The UI waits for `Finished` before it starts another pass
+            let result = panic::catch_unwind(AssertUnwindSafe(run))
+                .unwrap_or_else(|_| Err("analysis failed unexpectedly".into()));
+
+            if let Err(error) = result {
+                let _ = sender.send(AnalysisEvent::Error {
+                    path: PathBuf::from(&db_path),
+                    message: error.to_string(),
+                });
+                let _ = sender.send(AnalysisEvent::Finished { cancelled: true });
+            }
+        });
+
+        Self {
+            receiver,
+            cancel,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns every event that arrived since the last call without blocking.
+    pub fn poll_events(&self) -> Vec<AnalysisEvent> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl Drop for AnalysisWorker {
+    fn drop(&mut self) {
+        self.cancel();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Analyzes every sample that hasn't been analyzed by the current
+/// `ANALYSIS_VERSION` yet, storing the results as it goes.
+pub fn analyze_library(
+    conn: &Connection,
+    cancel: &AtomicBool,
+    report: &mut dyn FnMut(AnalysisEvent),
+) -> Result<(), Box<dyn Error>> {
+    let pending = load_unanalyzed_samples(conn, ANALYSIS_VERSION)?;
+    report(AnalysisEvent::Started {
+        total: pending.len(),
+    });
+
+    for sample in pending {
+        if cancel.load(Ordering::Relaxed) {
+            report(AnalysisEvent::Finished { cancelled: true });
+            return Ok(());
+        }
+
+        // Files that fail to decode still get what their tags and name tell
+        // us, and are marked analyzed so they aren't retried on every pass.
+        // An analyzer panicking on one file mustn't end the pass either.
+        let analysis = match panic::catch_unwind(|| analyze_file(&sample)) {
+            Ok(Ok(analysis)) => analysis,
+            Ok(Err(error)) => {
+                report(AnalysisEvent::Error {
+                    path: PathBuf::from(&sample.path),
+                    message: error.to_string(),
+                });
+                panic::catch_unwind(|| known_analysis(&sample)).unwrap_or_default()
+            }
+            Err(_) => {
+                report(AnalysisEvent::Error {
+                    path: PathBuf::from(&sample.path),
+                    message: "analysis failed unexpectedly".to_string(),
+                });
+                Analysis::default()
+            }
+        };
+        store_analysis(conn, sample.id, &analysis, ANALYSIS_VERSION)?;
+        report(AnalysisEvent::Analyzed {
+            sample_id: sample.id,
+            analysis,
+        });
+    }
+
+    report(AnalysisEvent::Finished { cancelled: false });
+    Ok(())
+}
+
+/// Analyzes one sample's audio.
+pub fn analyze_file(sample: &Sample) -> Result<Analysis, Box<dyn Error>> {
+    let mut analysis = known_analysis(sample);
+
+    let audio = decode(Path::new(&sample.path))?;
+    let sample_rate = audio.sample_rate;
+    analysis.loudness = measure_loudness(&audio.channels, sample_rate);
+    analysis.audio_hash = Some(audio_hash(&audio, sample.frames));
+
+    let samples = audio.mono();
+    analysis.fingerprint = fingerprint(&samples, sample_rate);
+    if analysis.bpm.is_none() {
+        let loop_frames = sample
+            .loop_start
+            .zip(sample.loop_end)
+            .map(|(start, end)| end.saturating_sub(start));
+        if let Some((bpm, confidence)) = detect_tempo(&samples, sample_rate, loop_frames) {
+            analysis.bpm = Some(bpm);
+            analysis.bpm_confidence = Some(confidence);
+        }
+    }
+    if analysis.key.is_none()
+        && let Some((key, confidence)) = detect_key(&samples, sample_rate)
+    {
+        analysis.key = Some(key);
+        analysis.key_confidence = Some(confidence);
+    }
+    if let Some((frequency, confidence)) = detect_pitch(&samples, sample_rate) {
+        analysis.pitch = Some(frequency);
+        analysis.pitch_confidence = Some(confidence);
+    }
+    if let Some(mut features) = Features::extract(&samples, sample_rate) {
+        features.pitch = analysis.pitch;
+        features.pitch_confidence = analysis.pitch_confidence.unwrap_or(0.0);
+        features.tempo_confidence = analysis.bpm_confidence.unwrap_or(0.0);
+        analysis.category = classify(&features);
+        analysis.feature_vector =
+            feature_vector(&samples, sample_rate, &features, analysis.loudness);
+    }
+
+    Ok(analysis)
+}
+
+/// The tempo and key stated by the file itself or by its name, which beat
+/// anything detection could come up with.
+fn known_analysis(sample: &Sample) -> Analysis {
+    let bpm = sample.tempo.or_else(|| tempo_from_filename(&sample.name));
+    let key = sample.musical_key.clone();
+    Analysis {
+        bpm_confidence: bpm.map(|_| 1.0),
+        bpm,
+        key_confidence: key.as_ref().map(|_| 1.0),
+        key,
+        loudness: None,
+        pitch: None,
+        pitch_confidence: None,
+        category: None,
+        feature_vector: None,
+        audio_hash: None,
+        fingerprint: None,
+    }
+}
+
+/// Decoded audio, one `Vec` of samples per channel.
+pub struct DecodedAudio {
+    pub channels: Vec<Vec<f32>>,
+    pub sample_rate: u32,
+}
+
+impl DecodedAudio {
+    /// The channels mixed down to one.
+    pub fn mono(&self) -> Vec<f32> {
+        let frames = self.channels.iter().map(Vec::len).min().unwrap_or(0);
+        (0..frames)
+            .map(|i| {
+                self.channels.iter().map(|channel| channel[i]).sum::<f32>()
+                    / self.channels.len() as f32
+            })
+            .collect()
+    }
+}
+
+/// Decodes up to `MAX_ANALYSIS_SECONDS` of a file.
+pub fn decode(path: &Path) -> Result<DecodedAudio, Box<dyn Error>> {
+    let file = File::open(path)?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let probed = get_probe().format(
+        &Default::default(),
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("no supported audio tracks found")?;
+    let track_id = track.id;
+    let sample_rate = track
+        .codec_params
+        .sample_rate
+        .ok_or("unknown sample rate")?;
+    let mut decoder = get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let max_frames = (sample_rate as u64 * MAX_ANALYSIS_SECONDS) as usize;
+    let mut channels: Vec<Vec<f32>> = Vec::new();
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+
+    while channels.first().map_or(0, Vec::len) < max_frames {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            // End of stream
+            Err(SymphoniaError::IoError(_)) => break,
+            Err(error) => return Err(error.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt packet only costs us that packet
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(error) => return Err(error.into()),
+        };
+
+        let count = decoded.spec().channels.count().max(1);
+        let buffer = buffer
+            .get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, *decoded.spec()));
+        if buffer.capacity() < decoded.capacity() * count {
+            *buffer = SampleBuffer::new(decoded.capacity() as u64, *decoded.spec());
+        }
+        buffer.copy_interleaved_ref(decoded);
+
+        channels.resize_with(count.max(channels.len()), Vec::new);
+        for frame in buffer.samples().chunks_exact(count) {
+            for (channel, &sample) in channels.iter_mut().zip(frame) {
+                channel.push(sample);
+            }
+        }
+    }
+
+    if channels.first().is_none_or(Vec::is_empty) {
+        return Err("no audio samples decoded".into());
+    }
+    for channel in &mut channels {
+        channel.truncate(max_frames);
+    }
+    Ok(DecodedAudio {
+        channels,
+        sample_rate,
+    })
+}
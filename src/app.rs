@@ -1,37 +1,1267 @@
+use std::collections::{HashMap, HashSet};
+
 use rusqlite::Connection;
 
-use crate::{audio_player::AudioPlayer, db::{init_db, load_samples}, import_samples_from_dir, sample::Sample};
+use crate::{
+    analysis::{AnalysisEvent, AnalysisProgress, AnalysisWorker},
+    audio_player::AudioPlayer,
+    db::{
+        SampleMark, add_samples_to_collection, add_tag_to_samples, create_collection,
+        delete_collection, delete_samples, delete_tag, get_setting, init_db, insert_library_root,
+        load_collections, load_duplicate_candidates, load_feature_vectors, load_library_roots,
+        load_sample_cues, load_sample_metadata, load_sample_tags, load_samples_by_ids, load_tags,
+        move_collection, open_db, refresh_smart_collections, remove_samples_from_collection,
+        remove_tag_from_samples, rename_collection, rename_tag, search_samples,
+        set_collection_query, set_sample_marks, set_setting, swap_collection_positions,
+    },
+    duplicates::{DuplicateSet, group_duplicates},
+    importer::{ImportProgress, ImportWorker},
+    metadata::MetadataTag,
+    query::{LibraryScope, SearchQuery},
+    resample::ResampleQuality,
+    sample::{Collection, CueMarker, LibraryRoot, Sample, SampleColumn, Tag},
+    scanner::{ImportEvent, ScanOptions, ScanSummary},
+    similarity::rank_by_similarity,
+    watcher::{LibraryWatcher, WatchEvent},
+};
+
+pub const DB_PATH: &str = "samples.db";
+const DEFAULT_LIBRARY_ROOT: &str = "./demo/samples";
+pub const HASH_CONTENTS_SETTING: &str = "scan.hash_contents";
+const SORT_COLUMN_SETTING: &str = "table.sort_column";
+const SORT_ASCENDING_SETTING: &str = "table.sort_ascending";
+const NORMALIZE_PLAYBACK_SETTING: &str = "playback.normalize";
+const FAST_RESAMPLING_SETTING: &str = "playback.fast_resampling";
+
+/// Normalized playback brings every sample to this loudness, in LUFS...
+const PLAYBACK_TARGET_LUFS: f32 = -16.0;
+/// ...but boosts quiet ones by at most this many dB, and never so far that
+/// their true peak goes over the ceiling, in dBTP.
+const MAX_PLAYBACK_BOOST: f32 = 12.0;
+const PLAYBACK_PEAK_CEILING: f32 = -1.0;
+
+/// How many of the nearest samples "find similar" shows, the sample itself
+/// included.
+const SIMILAR_RESULTS: usize = 50;
+
+/// A "find similar" search: the name of the sample it started from and how
+/// far every analyzed sample sounds from it.
+pub struct SimilarSearch {
+    pub name: String,
+    pub distances: HashMap<isize, f32>,
+    /// Shows the results nearest first instead of sorting by `sort_column`
+    pub ordered: bool,
+}
+
+impl SimilarSearch {
+    fn distance(&self, sample_id: isize) -> f32 {
+        self.distances
+            .get(&sample_id)
+            .copied()
+            .unwrap_or(f32::INFINITY)
+    }
+}
+
+/// A set of duplicates as shown in the review window.
+pub struct DuplicateReview {
+    pub set: DuplicateSet,
+    pub samples: Vec<Sample>,
+    /// The sample to keep when hiding or deleting the others
+    pub keep: isize,
+    /// The file each sample's path leads to, where it could be read
+    pub files: HashMap<isize, FileIdentity>,
+}
+
+impl DuplicateReview {
+    /// Whether at most one of the samples is still shown in the browser.
+    pub fn is_resolved(&self) -> bool {
+        self.samples.iter().filter(|s| !s.hidden).count() <= 1
+    }
+
+    /// Another sample whose path leads to the same file as `sample`'s, through
+    /// a symlinked folder or a hardlink: the one to keep if it does, else the
+    /// first such sample listed. Deleting an alias would delete that sample.
+    pub fn alias_of(&self, sample: &Sample) -> Option<&Sample> {
+        let file = self.files.get(&sample.id)?;
+        let same_file = |other: &&Sample| self.files.get(&other.id) == Some(file);
+        if sample.id == self.keep {
+            return None;
+        }
+        self.samples
+            .iter()
+            .find(|other| other.id == self.keep)
+            .filter(same_file)
+            .or_else(|| {
+                self.samples
+                    .iter()
+                    .take_while(|other| other.id != sample.id)
+                    .filter(|other| other.id != self.keep)
+                    .find(same_file)
+            })
+    }
+
+    /// The samples whose files "Delete others" removes.
+    pub fn deletable(&self) -> impl Iterator<Item = &Sample> {
+        self.samples
+            .iter()
+            .filter(|s| s.id != self.keep && self.alias_of(s).is_none())
+    }
+}
+
+/// What tells two paths to the same file apart from two copies of it: the
+/// device and inode where there are any, the canonical path elsewhere.
+#[cfg(unix)]
+pub type FileIdentity = (u64, u64);
+#[cfg(not(unix))]
+pub type FileIdentity = std::path::PathBuf;
+
+#[cfg(unix)]
+fn file_identity(path: &str) -> Option<FileIdentity> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_identity(path: &str) -> Option<FileIdentity> {
+    std::fs::canonicalize(path).ok()
+}
+
+fn file_identities(samples: &[Sample]) -> HashMap<isize, FileIdentity> {
+    samples
+        .iter()
+        .filter_map(|s| Some((s.id, file_identity(&s.path)?)))
+        .collect()
+}
 
 pub struct SampleDuckApp {
     pub conn: Connection,
     pub audio_player: AudioPlayer,
     pub samples: Vec<Sample>,
-    pub selected_sample: Sample,
-    pub selected_sample_idx: usize,
+    /// Index into `samples` of each sample, by id, so background events
+    /// don't have to search the whole list
+    pub sample_index: HashMap<isize, usize>,
+    /// Indices into `samples` of the rows shown in the table, in display order
+    pub visible_rows: Vec<usize>,
+    pub column_filters: HashMap<SampleColumn, String>,
+    /// Lowercased cell text the column filters are matched against, by sample
+    /// id; cleared whenever `samples` is re-sorted after a change
+    pub filter_text: HashMap<(isize, SampleColumn), String>,
+    /// Set when background events changed `samples`, so the table is
+    /// re-sorted once per frame rather than once per batch of events
+    pub samples_changed: bool,
+    /// Set when keyboard navigation moved the selection off screen
+    pub scroll_to_selected: bool,
+    pub selected_sample: Option<Sample>,
+    /// Row of the selected sample in the table, if it passes the filters
+    pub selected_sample_idx: Option<usize>,
+    pub selected_metadata: Vec<MetadataTag>,
+    pub selected_cues: Vec<CueMarker>,
+    pub selected_tags: Vec<Tag>,
+    /// Every selected sample; `selected_sample` is the one being auditioned
+    pub selected_ids: HashSet<isize>,
+    pub loop_playback: bool,
+    /// Whether playback gain levels out the loudness of samples
+    pub normalize_playback: bool,
+    /// Whether playback trades resampling quality for speed
+    pub fast_resampling: bool,
+    pub library_roots: Vec<LibraryRoot>,
+    pub new_root_path: String,
+    pub scan_options: ScanOptions,
+    pub last_scan: Option<ScanSummary>,
+    pub import_worker: Option<ImportWorker>,
+    pub import_progress: ImportProgress,
+    pub watcher: Option<LibraryWatcher>,
+    pub analysis_worker: Option<AnalysisWorker>,
+    pub analysis_progress: AnalysisProgress,
+    /// Set when samples changed during an analysis pass that had already
+    /// picked what to analyze, so another pass follows it
+    pub analysis_queued: bool,
+    pub sort_column: SampleColumn,
+    pub sort_ascending: bool,
+    pub search_text: String,
+    pub search_query: SearchQuery,
+    pub scope: LibraryScope,
+    /// All tags with their sample counts, for the sidebar
+    pub tags: Vec<(Tag, usize)>,
+    pub tag_input: String,
+    pub tag_editor_open: bool,
+    pub renaming_tag: Option<(isize, String)>,
+    /// All collections and folders with their sample counts, for the sidebar
+    pub collections: Vec<(Collection, usize)>,
+    pub renaming_collection: Option<(isize, String)>,
+    /// Smart collection whose query is being edited in the sidebar
+    pub editing_query: Option<(isize, String)>,
+    /// Shows a browsed collection in its own order instead of sorting by `sort_column`
+    pub collection_order: bool,
+    /// Narrows the table down to the samples nearest to one sample
+    pub similar: Option<SimilarSearch>,
+    pub duplicates: Vec<DuplicateReview>,
+    pub duplicates_open: bool,
+    /// Also lists sets whose duplicates have all been hidden
+    pub show_resolved_duplicates: bool,
+    /// Duplicate set whose files are about to be deleted, pending confirmation
+    pub deleting_duplicates: Option<usize>,
 }
 
 impl SampleDuckApp {
     pub fn new() -> Self {
-        let conn = Connection::open("samples.db").expect("failed to open db");
+        let conn = open_db(DB_PATH).expect("failed to open db");
         init_db(&conn).expect("failed to init db");
 
-        // For now, scan a hardcoded folder
-        import_samples_from_dir(&conn, "./demo/samples").unwrap();
+        // Fall back to the bundled demo folder until the user configures their own roots
+        let mut library_roots = load_library_roots(&conn).expect("failed to load library roots");
+        if library_roots.is_empty() {
+            insert_library_root(&conn, DEFAULT_LIBRARY_ROOT, true, &[])
+                .expect("failed to add default library root");
+            library_roots = load_library_roots(&conn).expect("failed to load library roots");
+        }
+
+        let scan_options = ScanOptions {
+            hash_contents: get_setting(&conn, HASH_CONTENTS_SETTING)
+                .unwrap_or_default()
+                .is_some_and(|value| value == "1"),
+        };
 
         let mut audio_player = AudioPlayer::new().unwrap();
-        let samples = load_samples(&conn).unwrap();
 
-        let selected_sample_idx = 0;
-        let selected_sample = samples[selected_sample_idx].clone();
+        // Show what is already indexed right away; the rescan streams in the rest
+        let samples = search_samples(&conn, LibraryScope::All, &SearchQuery::default()).unwrap();
 
-        audio_player.load(&selected_sample.path);
+        let selected_sample = samples.first().cloned();
 
-        Self {
+        let sort_column = get_setting(&conn, SORT_COLUMN_SETTING)
+            .unwrap_or_default()
+            .and_then(|key| SampleColumn::from_key(&key))
+            .unwrap_or(SampleColumn::Name);
+        let sort_ascending = get_setting(&conn, SORT_ASCENDING_SETTING)
+            .unwrap_or_default()
+            .is_none_or(|value| value == "1");
+        let normalize_playback = get_setting(&conn, NORMALIZE_PLAYBACK_SETTING)
+            .unwrap_or_default()
+            .is_some_and(|value| value == "1");
+        let fast_resampling = get_setting(&conn, FAST_RESAMPLING_SETTING)
+            .unwrap_or_default()
+            .is_some_and(|value| value == "1");
+        if fast_resampling {
+            audio_player.set_resample_quality(ResampleQuality::Fast);
+        }
+
+        if let Some(sample) = &selected_sample
+            && let Err(error) = audio_player.load(&sample.path)
+        {
+            println!("Error: {}", error);
+        }
+
+        let mut app = Self {
             conn,
             audio_player,
             samples,
+            sample_index: HashMap::new(),
+            visible_rows: Vec::new(),
+            column_filters: HashMap::new(),
+            filter_text: HashMap::new(),
+            samples_changed: false,
+            scroll_to_selected: false,
             selected_sample,
-            selected_sample_idx,
+            selected_sample_idx: None,
+            selected_metadata: Vec::new(),
+            selected_cues: Vec::new(),
+            selected_tags: Vec::new(),
+            selected_ids: HashSet::new(),
+            loop_playback: false,
+            normalize_playback,
+            fast_resampling,
+            library_roots,
+            new_root_path: String::new(),
+            scan_options,
+            last_scan: None,
+            import_worker: None,
+            import_progress: ImportProgress::default(),
+            watcher: None,
+            analysis_worker: None,
+            analysis_progress: AnalysisProgress::default(),
+            analysis_queued: false,
+            sort_column,
+            sort_ascending,
+            search_text: String::new(),
+            search_query: SearchQuery::default(),
+            scope: LibraryScope::All,
+            tags: Vec::new(),
+            tag_input: String::new(),
+            tag_editor_open: false,
+            renaming_tag: None,
+            collections: Vec::new(),
+            renaming_collection: None,
+            editing_query: None,
+            collection_order: false,
+            similar: None,
+            duplicates: Vec::new(),
+            duplicates_open: false,
+            show_resolved_duplicates: false,
+            deleting_duplicates: None,
+        };
+        app.reload_tags();
+        app.reload_collections();
+        app.sort_samples();
+        app.load_selected_details();
+        app.rescan_library();
+        app
+    }
+
+    /// Starts a background rescan of all library roots, cancelling any scan
+    /// that is still running.
+    pub fn rescan_library(&mut self) {
+        match load_library_roots(&self.conn) {
+            Ok(roots) => self.library_roots = roots,
+            Err(error) => {
+                println!("Error: {}", error);
+                return;
+            }
+        }
+
+        // Dropping the old workers and watcher stops them and waits for them to
+        // finish; analysis starts over once the import is done
+        self.import_worker = None;
+        self.watcher = None;
+        self.analysis_worker = None;
+        self.analysis_queued = false;
+        match LibraryWatcher::spawn(DB_PATH, self.library_roots.clone(), self.scan_options) {
+            Ok(watcher) => self.watcher = Some(watcher),
+            Err(error) => println!("Error: failed to watch library folders: {}", error),
+        }
+
+        self.import_progress = ImportProgress::default();
+        self.import_worker = Some(ImportWorker::spawn(
+            DB_PATH,
+            self.library_roots.clone(),
+            self.scan_options,
+        ));
+    }
+
+    pub fn cancel_import(&self) {
+        if let Some(worker) = &self.import_worker {
+            worker.cancel();
+        }
+    }
+
+    pub fn is_importing(&self) -> bool {
+        self.import_worker.is_some()
+    }
+
+    /// Applies events from the background import to the in-memory sample list.
+    pub fn poll_import(&mut self) {
+        let Some(worker) = &self.import_worker else {
+            return;
+        };
+
+        let events = worker.poll_events();
+        if events.is_empty() {
+            return;
+        }
+
+        let mut finished = false;
+        for event in events {
+            self.import_progress.apply(&event);
+            if let ImportEvent::Finished { summary, error, .. } = &event {
+                if error.is_none() {
+                    self.last_scan = Some(*summary);
+                }
+                finished = true;
+                // Before the finished event reloads a filtered table
+                self.refresh_smart_collections();
+            }
+            self.apply_import_event(event);
+        }
+
+        if finished {
+            self.import_worker = None;
+            self.start_analysis();
+        }
+    }
+
+    /// Starts analyzing samples that haven't been analyzed yet, or queues
+    /// another pass if one is already running.
+    pub fn start_analysis(&mut self) {
+        if self.analysis_worker.is_some() {
+            self.analysis_queued = true;
+            return;
+        }
+        self.analysis_queued = false;
+        self.analysis_progress = AnalysisProgress::default();
+        self.analysis_worker = Some(AnalysisWorker::spawn(DB_PATH));
+    }
+
+    /// Applies results from the background analysis to the in-memory sample list.
+    pub fn poll_analysis(&mut self) {
+        let Some(worker) = &self.analysis_worker else {
+            return;
+        };
+
+        let events = worker.poll_events();
+        if events.is_empty() {
+            return;
+        }
+
+        let mut finished = false;
+        for event in events {
+            self.analysis_progress.apply(&event);
+            match event {
+                AnalysisEvent::Analyzed {
+                    sample_id,
+                    analysis,
+                } => {
+                    let apply = |sample: &mut Sample| {
+                        sample.bpm = analysis.bpm;
+                        sample.bpm_confidence = analysis.bpm_confidence;
+                        sample.estimated_key = analysis.key.clone();
+                        sample.key_confidence = analysis.key_confidence;
+                        sample.loudness = analysis.loudness;
+                        sample.pitch = analysis.pitch;
+                        sample.pitch_confidence = analysis.pitch_confidence;
+                    };
+                    if let Some(&idx) = self.sample_index.get(&sample_id) {
+                        apply(&mut self.samples[idx]);
+                        self.samples_changed = true;
+                    }
+                    if let Some(sample) = &mut self.selected_sample
+                        && sample.id == sample_id
+                    {
+                        apply(sample);
+                        // Reloads its auto-tag and playback gain
+                        self.load_selected_details();
+                    }
+                }
+                AnalysisEvent::Error { path, message } => {
+                    println!("Error: failed to analyze {}: {}", path.display(), message);
+                }
+                AnalysisEvent::Started { .. } => {}
+                AnalysisEvent::Finished { cancelled } => {
+                    finished = true;
+                    // Stopped by the user, so don't start right back up
+                    if cancelled {
+                        self.analysis_queued = false;
+                    }
+                }
+            }
+        }
+
+        if finished {
+            self.analysis_worker = None;
+            // New results may change which samples match searches and smart
+            // collections, and auto-tags change tag counts
+            self.reload_tags();
+            self.refresh_smart_collections();
+            if self.is_filtered() {
+                self.reload_samples();
+            } else {
+                self.sort_samples();
+            }
+            if self.analysis_queued {
+                self.start_analysis();
+            }
+        }
+    }
+
+    /// Applies changes picked up by the filesystem watcher.
+    pub fn poll_watcher(&mut self) {
+        let Some(watcher) = &self.watcher else {
+            return;
+        };
+
+        let events = watcher.poll_events();
+        if events.is_empty() {
+            return;
+        }
+
+        let mut rescan = false;
+        for event in events {
+            match event {
+                WatchEvent::Import(event) => {
+                    if let ImportEvent::Error { path, message } = &event {
+                        self.import_progress
+                            .errors
+                            .push((path.clone(), message.clone()));
+                    }
+                    self.apply_import_event(event);
+                }
+                WatchEvent::RescanNeeded => rescan = true,
+            }
+        }
+
+        self.refresh_smart_collections();
+        if self.is_browsing_smart_collection() {
+            self.reload_samples();
+        }
+        self.start_analysis();
+
+        if rescan {
+            self.rescan_library();
+        }
+    }
+
+    /// Re-runs the search after the search box changed.
+    pub fn set_search_text(&mut self, text: &str) {
+        self.search_query = SearchQuery::parse(text);
+        self.reload_samples();
+    }
+
+    /// Shows only the samples in `scope`, e.g. after clicking a tag in the sidebar.
+    pub fn set_scope(&mut self, scope: LibraryScope) {
+        self.scope = scope;
+        // Smart collections have no order of their own, so they sort like everything else
+        self.collection_order =
+            matches!(scope, LibraryScope::Collection(_)) && !self.is_browsing_smart_collection();
+        self.reload_samples();
+    }
+
+    /// Whether the table shows a subset of the library rather than every sample.
+    pub fn is_filtered(&self) -> bool {
+        self.scope != LibraryScope::All || !self.search_query.is_empty() || self.similar.is_some()
+    }
+
+    /// Reloads the sample list from the database, applying the scope and search.
+    pub fn reload_samples(&mut self) {
+        match search_samples(&self.conn, self.scope, &self.search_query) {
+            Ok(samples) => self.samples = samples,
+            Err(error) => {
+                println!("Error: {}", error);
+                return;
+            }
+        }
+        if let Some(similar) = &self.similar {
+            self.samples
+                .retain(|sample| similar.distances.contains_key(&sample.id));
+            self.samples
+                .sort_by(|a, b| similar.distance(a.id).total_cmp(&similar.distance(b.id)));
+            self.samples.truncate(SIMILAR_RESULTS);
+        }
+        self.sort_samples();
+    }
+
+    /// Shows the samples that sound most like the selected one, nearest
+    /// first, within the current scope and search.
+    pub fn find_similar(&mut self) {
+        let Some(sample) = &self.selected_sample else {
+            return;
+        };
+        let vectors = match load_feature_vectors(&self.conn) {
+            Ok(vectors) => vectors,
+            Err(error) => {
+                println!("Error: {}", error);
+                return;
+            }
+        };
+        let Some((_, reference)) = vectors.iter().find(|(id, _)| *id == sample.id) else {
+            println!("Error: {} hasn't been analyzed yet", sample.name);
+            return;
+        };
+
+        self.similar = Some(SimilarSearch {
+            name: sample.name.clone(),
+            distances: rank_by_similarity(reference, &vectors)
+                .into_iter()
+                .collect(),
+            ordered: true,
+        });
+        self.collection_order = false;
+        self.reload_samples();
+    }
+
+    /// Goes back from similar samples to the whole scope and search.
+    pub fn clear_similar(&mut self) {
+        self.similar = None;
+        self.reload_samples();
+    }
+
+    /// Whether the table shows similar samples nearest first rather than
+    /// sorted by a column.
+    pub fn is_similarity_ordered(&self) -> bool {
+        self.similar.as_ref().is_some_and(|similar| similar.ordered)
+    }
+
+    /// Switches similar samples back from column sorting to nearest first.
+    pub fn show_similarity_order(&mut self) {
+        if let Some(similar) = &mut self.similar {
+            similar.ordered = true;
+        }
+        self.sort_samples();
+    }
+
+    /// Sorts by clicked column header, flipping the direction on repeated clicks.
+    pub fn sort_by(&mut self, column: SampleColumn) {
+        if self.is_collection_ordered() || self.is_similarity_ordered() {
+            self.collection_order = false;
+            if let Some(similar) = &mut self.similar {
+                similar.ordered = false;
+            }
+            self.sort_column = column;
+            self.sort_ascending = true;
+        } else if self.sort_column == column {
+            self.sort_ascending = !self.sort_ascending;
+        } else {
+            self.sort_column = column;
+            self.sort_ascending = true;
+        }
+        self.sort_samples();
+
+        let ascending = if self.sort_ascending { "1" } else { "0" };
+        if let Err(error) = set_setting(&self.conn, SORT_COLUMN_SETTING, column.key())
+            .and_then(|_| set_setting(&self.conn, SORT_ASCENDING_SETTING, ascending))
+        {
+            println!("Error: {}", error);
+        }
+    }
+
+    /// Re-sorts the table if background events changed `samples` since the
+    /// last sort.
+    pub fn sort_changed_samples(&mut self) {
+        if self.samples_changed {
+            self.sort_samples();
+        }
+    }
+
+    /// Re-sorts `samples` and rebuilds the visible rows.
+    pub fn sort_samples(&mut self) {
+        self.samples_changed = false;
+        self.filter_text.clear();
+        if self.is_collection_ordered() {
+            // `search_samples` already returned them in collection order
+        } else if let Some(similar) = &self.similar
+            && similar.ordered
+        {
+            self.samples
+                .sort_by(|a, b| similar.distance(a.id).total_cmp(&similar.distance(b.id)));
+        } else {
+            let column = self.sort_column;
+            let ascending = self.sort_ascending;
+            self.samples.sort_by(|a, b| {
+                let ordering = column.compare(a, b);
+                if ascending {
+                    ordering
+                } else {
+                    ordering.reverse()
+                }
+            });
+        }
+        self.sample_index = self
+            .samples
+            .iter()
+            .enumerate()
+            .map(|(idx, sample)| (sample.id, idx))
+            .collect();
+        self.refresh_visible_rows();
+    }
+
+    /// Whether the table shows a collection in the order its samples were
+    /// arranged in, rather than sorted by a column.
+    pub fn is_collection_ordered(&self) -> bool {
+        self.collection_order && matches!(self.scope, LibraryScope::Collection(_))
+    }
+
+    /// Switches a browsed collection back from column sorting to its own order.
+    pub fn show_collection_order(&mut self) {
+        self.collection_order = true;
+        self.reload_samples();
+    }
+
+    /// Applies the column filters to `samples`, keeping the selected sample
+    /// selected if it still passes them.
+    pub fn refresh_visible_rows(&mut self) {
+        let filters: Vec<(SampleColumn, String)> = self
+            .column_filters
+            .iter()
+            .filter(|(_, filter)| !filter.trim().is_empty())
+            .map(|(column, filter)| (*column, filter.trim().to_lowercase()))
+            .collect();
+
+        let filter_text = &mut self.filter_text;
+        self.visible_rows = self
+            .samples
+            .iter()
+            .enumerate()
+            .filter(|(_, sample)| {
+                filters.iter().all(|(column, filter)| {
+                    filter_text
+                        .entry((sample.id, *column))
+                        .or_insert_with(|| column.cell_text(sample).to_lowercase())
+                        .contains(filter)
+                })
+            })
+            .map(|(idx, _)| idx)
+            .collect();
+
+        let visible_ids: HashSet<isize> = self
+            .visible_rows
+            .iter()
+            .map(|&idx| self.samples[idx].id)
+            .collect();
+        self.selected_ids.retain(|id| visible_ids.contains(id));
+
+        self.selected_sample_idx = self.selected_sample.as_ref().and_then(|selected| {
+            self.visible_rows
+                .iter()
+                .position(|&idx| self.samples[idx].id == selected.id)
+        });
+    }
+
+    fn apply_import_event(&mut self, event: ImportEvent) {
+        match event {
+            ImportEvent::Indexed(sample) => {
+                self.upsert_sample(*sample);
+                self.samples_changed = true;
+            }
+            ImportEvent::Missing(sample_id) => {
+                if let Some(&idx) = self.sample_index.get(&sample_id) {
+                    self.samples[idx].missing = true;
+                    self.samples_changed = true;
+                }
+            }
+            // New and changed files may have started or stopped matching the search
+            ImportEvent::Finished { .. } if self.is_filtered() => {
+                self.reload_samples();
+            }
+            _ => {}
+        }
+    }
+
+    /// Reloads the embedded metadata and cue markers shown in the details view
+    /// and points looped playback at the sample's embedded loop, if any.
+    pub fn load_selected_details(&mut self) {
+        self.selected_metadata = match &self.selected_sample {
+            Some(sample) => load_sample_metadata(&self.conn, sample.id).unwrap_or_else(|error| {
+                println!("Error: {}", error);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        self.selected_cues = match &self.selected_sample {
+            Some(sample) => load_sample_cues(&self.conn, sample.id).unwrap_or_else(|error| {
+                println!("Error: {}", error);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        self.selected_tags = match &self.selected_sample {
+            Some(sample) => load_sample_tags(&self.conn, sample.id).unwrap_or_else(|error| {
+                println!("Error: {}", error);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+
+        let loop_region = self
+            .selected_sample
+            .as_ref()
+            .and_then(|sample| sample.loop_start.zip(sample.loop_end));
+        self.audio_player.set_loop_region(loop_region);
+        self.apply_playback_gain();
+    }
+
+    pub fn set_normalize_playback(&mut self, enabled: bool) {
+        self.normalize_playback = enabled;
+        self.apply_playback_gain();
+        let value = if enabled { "1" } else { "0" };
+        if let Err(error) = set_setting(&self.conn, NORMALIZE_PLAYBACK_SETTING, value) {
+            println!("Error: {}", error);
+        }
+    }
+
+    /// Switches between quick and high-quality sample-rate conversion for the
+    /// samples played from now on.
+    pub fn set_fast_resampling(&mut self, enabled: bool) {
+        self.fast_resampling = enabled;
+        self.audio_player.set_resample_quality(if enabled {
+            ResampleQuality::Fast
+        } else {
+            ResampleQuality::High
+        });
+        let value = if enabled { "1" } else { "0" };
+        if let Err(error) = set_setting(&self.conn, FAST_RESAMPLING_SETTING, value) {
+            println!("Error: {}", error);
+        }
+    }
+
+    /// Sets the player's gain for the selected sample: unity, or its
+    /// loudness-normalized gain if that is enabled and its loudness is known.
+    pub fn apply_playback_gain(&self) {
+        let loudness = self.selected_sample.as_ref().and_then(|s| s.loudness);
+        let gain_db = match loudness {
+            Some(loudness) if self.normalize_playback => (PLAYBACK_TARGET_LUFS
+                - loudness.integrated)
+                .min(MAX_PLAYBACK_BOOST)
+                .min(PLAYBACK_PEAK_CEILING - loudness.true_peak),
+            _ => 0.0,
+        };
+        self.audio_player.set_gain(10f32.powf(gain_db / 20.0));
+    }
+
+    fn upsert_sample(&mut self, sample: Sample) {
+        if self.selected_sample.as_ref().map(|s| s.id) == Some(sample.id) {
+            self.selected_sample = Some(sample.clone());
+            self.load_selected_details();
+        }
+
+        let existing = self.sample_index.get(&sample.id).copied();
+        if sample.hidden {
+            // The order is restored by the re-sort after this batch of events
+            if let Some(idx) = existing {
+                self.sample_index.remove(&sample.id);
+                self.samples.swap_remove(idx);
+                if let Some(moved) = self.samples.get(idx) {
+                    self.sample_index.insert(moved.id, idx);
+                }
+            }
+            return;
+        }
+        match existing {
+            Some(idx) => self.samples[idx] = sample,
+            // With a search active, new samples show up once the import finishes
+            None if !self.is_filtered() => {
+                self.sample_index.insert(sample.id, self.samples.len());
+                self.samples.push(sample);
+            }
+            None => {}
+        }
+    }
+
+    pub fn reload_tags(&mut self) {
+        match load_tags(&self.conn) {
+            Ok(tags) => self.tags = tags,
+            Err(error) => println!("Error: {}", error),
+        }
+    }
+
+    /// The samples tag edits apply to: the multi-selection, or else the
+    /// sample being auditioned.
+    pub fn selected_sample_ids(&self) -> Vec<isize> {
+        if self.selected_ids.is_empty() {
+            self.selected_sample.iter().map(|s| s.id).collect()
+        } else {
+            self.selected_ids.iter().copied().collect()
+        }
+    }
+
+    pub fn tag_selection(&mut self, name: &str) {
+        let name = name.trim();
+        if name.is_empty() {
+            return;
+        }
+        let result = add_tag_to_samples(&self.conn, name, &self.selected_sample_ids());
+        self.after_tags_changed(result);
+    }
+
+    pub fn untag_selection(&mut self, tag_id: isize) {
+        let result = remove_tag_from_samples(&self.conn, tag_id, &self.selected_sample_ids());
+        self.after_tags_changed(result);
+    }
+
+    pub fn rename_tag(&mut self, tag_id: isize, new_name: &str) {
+        let new_name = new_name.trim();
+        if new_name.is_empty() {
+            return;
+        }
+        let result = rename_tag(&self.conn, tag_id, new_name);
+        self.after_tags_changed(result);
+    }
+
+    pub fn delete_tag(&mut self, tag_id: isize) {
+        let result = delete_tag(&self.conn, tag_id);
+        self.after_tags_changed(result);
+    }
+
+    fn after_tags_changed(&mut self, result: rusqlite::Result<()>) {
+        if let Err(error) = result {
+            println!("Error: {}", error);
+        }
+        self.reload_tags();
+        self.load_selected_details();
+        self.refresh_smart_collections();
+
+        // A merged or deleted tag can't be browsed anymore
+        if let LibraryScope::Tag(tag_id) = self.scope
+            && !self.tags.iter().any(|(tag, _)| tag.id == tag_id)
+        {
+            self.scope = LibraryScope::All;
+        }
+        if self.scope != LibraryScope::All {
+            self.reload_samples();
+        }
+    }
+
+    /// Sets a favorite, rating or color label on every selected sample.
+    pub fn mark_selection(&mut self, mark: SampleMark) {
+        let ids = self.selected_sample_ids();
+        if let Err(error) = set_sample_marks(&self.conn, &ids, mark) {
+            println!("Error: {}", error);
+            return;
+        }
+
+        let apply = |sample: &mut Sample| match mark {
+            SampleMark::Favorite(favorite) => sample.favorite = favorite,
+            SampleMark::Rating(rating) => sample.rating = rating.min(5),
+            SampleMark::ColorLabel(label) => sample.color_label = label,
+            SampleMark::Hidden(hidden) => sample.hidden = hidden,
+        };
+        for sample in self.samples.iter_mut().filter(|s| ids.contains(&s.id)) {
+            apply(sample);
+        }
+        if let Some(sample) = &mut self.selected_sample
+            && ids.contains(&sample.id)
+        {
+            apply(sample);
+        }
+
+        // The change may affect which rows match the search and where they sort
+        self.refresh_smart_collections();
+        if self.is_filtered() || matches!(mark, SampleMark::Hidden(_)) {
+            self.reload_samples();
+        } else {
+            self.sort_samples();
+        }
+    }
+
+    /// Looks for duplicates among the analyzed samples and lists them for
+    /// review, suggesting to keep favorites and well-rated samples.
+    pub fn find_duplicates(&mut self) {
+        let sets = match load_duplicate_candidates(&self.conn) {
+            Ok(candidates) => group_duplicates(&candidates),
+            Err(error) => {
+                println!("Error: {}", error);
+                return;
+            }
+        };
+        let ids: Vec<isize> = sets.iter().flat_map(|set| set.sample_ids.clone()).collect();
+        let samples: HashMap<isize, Sample> = match load_samples_by_ids(&self.conn, &ids) {
+            Ok(samples) => samples.into_iter().map(|s| (s.id, s)).collect(),
+            Err(error) => {
+                println!("Error: {}", error);
+                return;
+            }
+        };
+
+        self.duplicates = sets
+            .into_iter()
+            .filter_map(|set| {
+                let mut members: Vec<Sample> = set
+                    .sample_ids
+                    .iter()
+                    .filter_map(|id| samples.get(id).cloned())
+                    .collect();
+                members.sort_by(|a, b| {
+                    b.favorite
+                        .cmp(&a.favorite)
+                        .then(b.rating.cmp(&a.rating))
+                        .then(a.hidden.cmp(&b.hidden))
+                        .then(a.id.cmp(&b.id))
+                });
+                let keep = members.first()?.id;
+                Some(DuplicateReview {
+                    set,
+                    files: file_identities(&members),
+                    samples: members,
+                    keep,
+                })
+            })
+            .filter(|review| self.show_resolved_duplicates || !review.is_resolved())
+            .collect();
+        self.deleting_duplicates = None;
+    }
+
+    /// Selects and plays a sample that may not be in the table, such as one
+    /// from the duplicates review.
+    pub fn audition(&mut self, sample: Sample) {
+        let path = sample.path.clone();
+        self.selected_ids.clear();
+        self.selected_sample = Some(sample);
+        self.refresh_visible_rows();
+        self.load_selected_details();
+        match self.audio_player.load(&path) {
+            Ok(_) => self.audio_player.play(),
+            Err(error) => println!("Error: {}", error),
+        }
+    }
+
+    /// Hides every sample of a duplicate set but the one picked to keep,
+    /// which is shown again if it was hidden.
+    pub fn hide_duplicates(&mut self, index: usize) {
+        let Some(review) = self.duplicates.get_mut(index) else {
+            return;
+        };
+        let (keep, others): (Vec<isize>, Vec<isize>) = review
+            .samples
+            .iter()
+            .map(|s| s.id)
+            .partition(|&id| id == review.keep);
+        if let Err(error) = set_sample_marks(&self.conn, &others, SampleMark::Hidden(true))
+            .and_then(|_| set_sample_marks(&self.conn, &keep, SampleMark::Hidden(false)))
+        {
+            println!("Error: {}", error);
+            return;
+        }
+        for sample in &mut review.samples {
+            sample.hidden = sample.id != review.keep;
+        }
+
+        if !self.show_resolved_duplicates {
+            self.duplicates.remove(index);
+            self.deleting_duplicates = None;
+        }
+        self.refresh_smart_collections();
+        self.reload_samples();
+    }
+
+    /// Shows a hidden sample in the browser again.
+    pub fn unhide_sample(&mut self, sample_id: isize) {
+        if let Err(error) = set_sample_marks(&self.conn, &[sample_id], SampleMark::Hidden(false)) {
+            println!("Error: {}", error);
+            return;
+        }
+        for sample in self.duplicates.iter_mut().flat_map(|r| &mut r.samples) {
+            if sample.id == sample_id {
+                sample.hidden = false;
+            }
+        }
+        self.refresh_smart_collections();
+        self.reload_samples();
+    }
+
+    /// Deletes the files of every sample of a duplicate set but the one
+    /// picked to keep, and removes them from the library.
+    pub fn delete_duplicates(&mut self, index: usize) {
+        let Some(review) = self.duplicates.get_mut(index) else {
+            return;
+        };
+        // Look again right before deleting, in case files moved since the review
+        review.files = file_identities(&review.samples);
+        let deletable: Vec<isize> = review.deletable().map(|s| s.id).collect();
+        let mut deleted = Vec::new();
+        for sample in review.samples.iter().filter(|s| s.id != review.keep) {
+            if !deletable.contains(&sample.id) {
+                // Another path to the kept file or one deleted here; drop the
+                // library entry only once the path no longer leads anywhere
+                if !std::path::Path::new(&sample.path).exists()
+                    && review.alias_of(sample).is_some_and(|s| s.id != review.keep)
+                {
+                    deleted.push(sample.id);
+                }
+                continue;
+            }
+            match std::fs::remove_file(&sample.path) {
+                Ok(()) => deleted.push(sample.id),
+                // Already gone, so only the library entry is left to remove
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                    deleted.push(sample.id)
+                }
+                Err(error) => println!("Error: failed to delete {}: {}", sample.path, error),
+            }
+        }
+        if let Err(error) = delete_samples(&self.conn, &deleted) {
+            println!("Error: {}", error);
+        }
+
+        review.samples.retain(|s| !deleted.contains(&s.id));
+        if review.samples.len() <= 1 {
+            self.duplicates.remove(index);
+        }
+        self.deleting_duplicates = None;
+        if self
+            .selected_sample
+            .as_ref()
+            .is_some_and(|s| deleted.contains(&s.id))
+        {
+            self.selected_sample = None;
+            self.load_selected_details();
+        }
+        self.reload_tags();
+        self.reload_collections();
+        self.refresh_smart_collections();
+        self.reload_samples();
+    }
+
+    pub fn reload_collections(&mut self) {
+        match load_collections(&self.conn) {
+            Ok(collections) => self.collections = collections,
+            Err(error) => println!("Error: {}", error),
+        }
+    }
+
+    /// Re-evaluates smart collections after samples, tags or marks changed.
+    /// Callers reload the table themselves if it may show one.
+    pub fn refresh_smart_collections(&mut self) {
+        if let Err(error) = refresh_smart_collections(&self.conn) {
+            println!("Error: {}", error);
+        }
+        self.reload_collections();
+    }
+
+    pub fn is_browsing_smart_collection(&self) -> bool {
+        let LibraryScope::Collection(collection_id) = self.scope else {
+            return false;
+        };
+        self.collections
+            .iter()
+            .any(|(collection, _)| collection.id == collection_id && collection.is_smart())
+    }
+
+    /// The collection's name prefixed with the folders it is in, e.g.
+    /// `Drums / Kicks / Punchy`.
+    pub fn collection_path(&self, collection_id: isize) -> String {
+        let mut names = Vec::new();
+        let mut next = Some(collection_id);
+        while let Some(id) = next {
+            let Some((collection, _)) = self.collections.iter().find(|(c, _)| c.id == id) else {
+                break;
+            };
+            names.push(collection.name.as_str());
+            next = collection.parent_id;
+            // Guard against a parent cycle in a hand-edited database
+            if names.len() > self.collections.len() {
+                break;
+            }
+        }
+        names.reverse();
+        names.join(" / ")
+    }
+
+    /// Whether `collection_id` is `folder_id` or nested somewhere inside it.
+    pub fn is_within_folder(&self, collection_id: isize, folder_id: isize) -> bool {
+        let mut next = Some(collection_id);
+        for _ in 0..=self.collections.len() {
+            match next {
+                Some(id) if id == folder_id => return true,
+                Some(id) => {
+                    next = self
+                        .collections
+                        .iter()
+                        .find(|(c, _)| c.id == id)
+                        .and_then(|(c, _)| c.parent_id);
+                }
+                None => break,
+            }
+        }
+        false
+    }
+
+    /// Creates a collection or folder with a placeholder name and starts
+    /// renaming it in the sidebar. Returns its id.
+    pub fn create_collection(
+        &mut self,
+        parent_id: Option<isize>,
+        is_folder: bool,
+    ) -> Option<isize> {
+        let name = if is_folder {
+            "New folder"
+        } else {
+            "New collection"
+        };
+        match create_collection(&self.conn, name, parent_id, is_folder) {
+            Ok(collection_id) => {
+                self.reload_collections();
+                self.renaming_collection = Some((collection_id, name.to_string()));
+                Some(collection_id)
+            }
+            Err(error) => {
+                println!("Error: {}", error);
+                None
+            }
+        }
+    }
+
+    /// Creates a smart collection for `query` and starts renaming it.
+    pub fn create_smart_collection(&mut self, parent_id: Option<isize>, query: &str) {
+        if let Some(collection_id) = self.create_collection(parent_id, false) {
+            self.set_collection_query(collection_id, query);
+        }
+    }
+
+    pub fn set_collection_query(&mut self, collection_id: isize, query: &str) {
+        let result = set_collection_query(&self.conn, collection_id, query.trim());
+        self.after_collections_changed(result);
+    }
+
+    pub fn rename_collection(&mut self, collection_id: isize, new_name: &str) {
+        let new_name = new_name.trim();
+        if new_name.is_empty() {
+            return;
+        }
+        let result = rename_collection(&self.conn, collection_id, new_name);
+        self.after_collections_changed(result);
+    }
+
+    pub fn move_collection(&mut self, collection_id: isize, parent_id: Option<isize>) {
+        let result = move_collection(&self.conn, collection_id, parent_id);
+        self.after_collections_changed(result);
+    }
+
+    pub fn delete_collection(&mut self, collection_id: isize) {
+        let result = delete_collection(&self.conn, collection_id);
+        self.after_collections_changed(result);
+    }
+
+    pub fn add_selection_to_collection(&mut self, collection_id: isize) {
+        let result =
+            add_samples_to_collection(&self.conn, collection_id, &self.selected_sample_ids());
+        self.after_collections_changed(result);
+    }
+
+    pub fn remove_selection_from_collection(&mut self, collection_id: isize) {
+        let result =
+            remove_samples_from_collection(&self.conn, collection_id, &self.selected_sample_ids());
+        self.after_collections_changed(result);
+    }
+
+    /// Moves the selected sample one row up or down within the browsed
+    /// collection, swapping it with the neighbouring visible row.
+    pub fn move_selection_in_collection(&mut self, up: bool) {
+        let LibraryScope::Collection(collection_id) = self.scope else {
+            return;
+        };
+        if !self.is_collection_ordered() {
+            return;
+        }
+        let Some(row) = self.selected_sample_idx else {
+            return;
+        };
+        let neighbour = if up {
+            row.checked_sub(1)
+        } else {
+            Some(row + 1).filter(|&next| next < self.visible_rows.len())
+        };
+        let Some(neighbour) = neighbour else {
+            return;
+        };
+
+        let (idx, neighbour_idx) = (self.visible_rows[row], self.visible_rows[neighbour]);
+        let (sample_id, neighbour_id) = (self.samples[idx].id, self.samples[neighbour_idx].id);
+        if let Err(error) =
+            swap_collection_positions(&self.conn, collection_id, sample_id, neighbour_id)
+        {
+            println!("Error: {}", error);
+            return;
+        }
+        self.samples.swap(idx, neighbour_idx);
+        self.sample_index.insert(sample_id, neighbour_idx);
+        self.sample_index.insert(neighbour_id, idx);
+        self.refresh_visible_rows();
+        self.scroll_to_selected = true;
+    }
+
+    fn after_collections_changed(&mut self, result: rusqlite::Result<()>) {
+        if let Err(error) = result {
+            println!("Error: {}", error);
+        }
+        self.reload_collections();
+
+        // A deleted collection can't be browsed anymore
+        if let LibraryScope::Collection(collection_id) = self.scope
+            && !self.collections.iter().any(|(c, _)| c.id == collection_id)
+        {
+            self.scope = LibraryScope::All;
+            self.collection_order = false;
+        }
+        if self.scope != LibraryScope::All {
+            self.reload_samples();
         }
     }
 }
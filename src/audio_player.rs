@@ -1,18 +1,15 @@
 use std::error::Error;
 use std::fmt;
-use std::fs::File;
 use std::path::Path;
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU8, AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use symphonia::core::audio::{AudioBufferRef, Signal, SignalSpec};
-use symphonia::core::codecs::{CODEC_TYPE_NULL, DecoderOptions};
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
-use symphonia::default::{get_codecs, get_probe};
+use rtrb::{Consumer, Producer, RingBuffer};
+
+use crate::decoder::{StreamControl, StreamDecoder, open_track};
+use crate::mixing::{ChannelState, SourceLayout, mix_matrix};
+use crate::resample::ResampleQuality;
 
 #[derive(Debug)]
 pub enum AudioPlayerError {
@@ -80,16 +77,188 @@ pub enum PlaybackState {
     Paused,
 }
 
+impl PlaybackState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => PlaybackState::Playing,
+            2 => PlaybackState::Paused,
+            _ => PlaybackState::Stopped,
+        }
+    }
+}
+
+/// How much decoded audio is buffered ahead of playback, in seconds.
+const BUFFER_SECONDS: f32 = 0.25;
+/// Commands that can wait for the audio callback at once.
+const COMMAND_CAPACITY: usize = 64;
+
+/// The audio callback's end of the loaded file: the buffer the decoder
+/// thread fills with frames in the file's own channels, and how to mix them
+/// into device frames.
+struct PlaybackStream {
+    consumer: Consumer<f32>,
+    in_channels: usize,
+    /// One row of gains per output channel, see `mixing::mix_matrix`
+    matrix: Vec<f32>,
+    control: Arc<StreamControl>,
+}
+
+/// Changes the audio callback picks up at the start of its next buffer. It
+/// hands each command back once done with it, carrying whatever it replaced,
+/// so that memory is freed on the UI thread rather than the audio thread.
+enum Command {
+    /// Plays from a newly loaded file from now on
+    Load(PlaybackStream),
+    /// Mixes the loaded file with another matrix, for solo and mute
+    SetMatrix(Vec<f32>),
+}
+
+/// Playback settings the audio callback reads without locking.
+struct SharedState {
+    state: AtomicU8,
+    /// Linear output gain, as `f32` bits
+    gain: AtomicU32,
+    /// Buffers the callback couldn't fill with decoded audio mid-playback
+    xruns: AtomicU64,
+}
+
+/// Everything the audio callback owns. It never locks, allocates, frees or
+/// prints, so the UI thread can't hold it up.
+struct AudioCallback {
+    commands: Consumer<Command>,
+    done: Producer<Command>,
+    playback: Option<PlaybackStream>,
+    shared: Arc<SharedState>,
+    out_channels: usize,
+    /// Whether audio has come through since the last load or seek; until it
+    /// has, running dry is the decoder starting up, not a dropout
+    primed: bool,
+}
+
+impl AudioCallback {
+    fn process(&mut self, data: &mut [f32]) {
+        // Clear output buffer first
+        data.fill(0.0);
+
+        while let Ok(command) = self.commands.pop() {
+            let done = match (command, self.playback.as_mut()) {
+                (Command::Load(playback), _) => {
+                    self.primed = false;
+                    self.playback.replace(playback).map(Command::Load)
+                }
+                (Command::SetMatrix(matrix), Some(playback)) => Some(Command::SetMatrix(
+                    std::mem::replace(&mut playback.matrix, matrix),
+                )),
+                (command, None) => Some(command),
+            };
+            // Only dropped here if the UI thread stopped collecting, which
+            // it doesn't while it sends commands
+            if let Some(done) = done {
+                let _ = self.done.push(done);
+            }
+        }
+
+        let Some(playback) = self.playback.as_mut() else {
+            return;
+        };
+        let control = &playback.control;
+        let in_channels = playback.in_channels;
+
+        // Drop what's buffered when the decoder has seeked elsewhere
+        let requested = control.flush_requested.load(Ordering::Relaxed);
+        if requested != control.flushed.load(Ordering::Relaxed) {
+            let buffered = playback.consumer.slots();
+            if let Ok(chunk) = playback.consumer.read_chunk(buffered) {
+                chunk.commit_all();
+            }
+            control
+                .frames_read
+                .fetch_add((buffered / in_channels) as u64, Ordering::Relaxed);
+            control.flushed.store(requested, Ordering::Release);
+            self.primed = false;
+        }
+
+        let state = &self.shared.state;
+        if PlaybackState::from_u8(state.load(Ordering::Relaxed)) != PlaybackState::Playing {
+            return;
+        }
+
+        let wanted = data.len() / self.out_channels;
+        let available = playback.consumer.slots() / in_channels;
+        let frames = wanted.min(available);
+        if let Ok(chunk) = playback.consumer.read_chunk(frames * in_channels) {
+            let (first, second) = chunk.as_slices();
+            let input = first
+                .chunks_exact(in_channels)
+                .chain(second.chunks_exact(in_channels));
+            for (frame, input) in data.chunks_mut(self.out_channels).zip(input) {
+                mix_frame(input, &playback.matrix, frame);
+            }
+            chunk.commit_all();
+        }
+        control
+            .frames_read
+            .fetch_add(frames as u64, Ordering::Relaxed);
+
+        if frames < wanted {
+            if control.finished.load(Ordering::Relaxed) && playback.consumer.is_empty() {
+                // End of playback
+                control.ended.store(true, Ordering::Relaxed);
+                let _ = state.compare_exchange(
+                    PlaybackState::Playing as u8,
+                    PlaybackState::Stopped as u8,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                );
+            } else if self.primed {
+                // The decoder fell behind and playback dropped out
+                self.shared.xruns.fetch_add(1, Ordering::Relaxed);
+                self.primed = false;
+            }
+        }
+        if frames > 0 {
+            self.primed = true;
+        }
+
+        let gain = f32::from_bits(self.shared.gain.load(Ordering::Relaxed));
+        if gain != 1.0 {
+            data.iter_mut().for_each(|sample| *sample *= gain);
+        }
+    }
+}
+
+fn mix_frame(input: &[f32], matrix: &[f32], output: &mut [f32]) {
+    for (value, gains) in output.iter_mut().zip(matrix.chunks_exact(input.len())) {
+        *value = gains
+            .iter()
+            .zip(input)
+            .map(|(gain, sample)| gain * sample)
+            .sum();
+    }
+}
+
 pub struct AudioPlayer {
-    samples: Arc<Mutex<Vec<f32>>>,
-    pub samples_count: usize,
-    pub peak_samples: Vec<(f32, f32)>,
-    play_pos: Arc<AtomicUsize>,
-    state: Arc<Mutex<PlaybackState>>,
+    /// Commands on their way to the audio callback, and back from it
+    commands: Producer<Command>,
+    done: Consumer<Command>,
+    shared: Arc<SharedState>,
     _stream: cpal::Stream,
     out_channels: usize,
+    /// Output stream rate; loaded files are resampled to it
     sample_rate: u32,
-    loop_enabled: Arc<Mutex<bool>>,
+    /// Decoder threads of the loaded file and what they share with playback
+    decoder: Option<StreamDecoder>,
+    control: Arc<StreamControl>,
+    peaks: Arc<Mutex<Vec<(f32, f32)>>>,
+    /// Channel layout of the loaded file and how each channel is auditioned
+    pub source_layout: SourceLayout,
+    pub channel_states: Vec<ChannelState>,
+    /// Sample rate of the loaded file as stored
+    source_rate: u32,
+    resample_quality: ResampleQuality,
+    loop_enabled: bool,
+    /// Loop start and end in frames of the loaded file, as last set
+    loop_frames: Option<(u64, u64)>,
 }
 
 impl AudioPlayer {
@@ -103,380 +272,150 @@ impl AudioPlayer {
         let out_channels = config.channels as usize;
         let sample_rate = config.sample_rate.0;
 
-        let samples = Arc::new(Mutex::new(Vec::new()));
-        let play_pos = Arc::new(AtomicUsize::new(0));
-        let state = Arc::new(Mutex::new(PlaybackState::Stopped));
-        let loop_enabled = Arc::new(Mutex::new(false));
-
-        // Clone for callback
-        let samples_cb = Arc::clone(&samples);
-        let play_pos_cb = Arc::clone(&play_pos);
-        let state_cb = Arc::clone(&state);
-        let loop_enabled_cb = Arc::clone(&loop_enabled);
-
+        let shared = Arc::new(SharedState {
+            state: AtomicU8::new(PlaybackState::Stopped as u8),
+            gain: AtomicU32::new(1.0f32.to_bits()),
+            xruns: AtomicU64::new(0),
+        });
+        let (commands, commands_cb) = RingBuffer::new(COMMAND_CAPACITY);
+        // Every command waiting can come back at once
+        let (done_cb, done) = RingBuffer::new(COMMAND_CAPACITY);
+
+        let mut callback = AudioCallback {
+            commands: commands_cb,
+            done: done_cb,
+            playback: None,
+            shared: Arc::clone(&shared),
+            out_channels,
+            primed: false,
+        };
         let stream = device.build_output_stream(
             &config,
-            move |data: &mut [f32], _| {
-                Self::audio_callback(
-                    data,
-                    &samples_cb,
-                    &play_pos_cb,
-                    &state_cb,
-                    &loop_enabled_cb,
-                    out_channels,
-                );
-            },
+            move |data: &mut [f32], _| callback.process(data),
             move |err| eprintln!("Audio stream error: {}", err),
             None,
         )?;
 
         stream.play()?;
 
-        let peak_samples: Vec<(f32, f32)> = vec![];
-
         Ok(Self {
-            samples,
-            samples_count: 0,
-            peak_samples,
-            play_pos,
-            state,
+            commands,
+            done,
+            shared,
             _stream: stream,
             out_channels,
             sample_rate,
-            loop_enabled,
+            decoder: None,
+            control: Arc::new(StreamControl::default()),
+            peaks: Arc::new(Mutex::new(Vec::new())),
+            source_layout: SourceLayout::Speakers(Vec::new()),
+            channel_states: Vec::new(),
+            source_rate: sample_rate,
+            resample_quality: ResampleQuality::High,
+            loop_enabled: false,
+            loop_frames: None,
         })
     }
 
-    fn audio_callback(
-        data: &mut [f32],
-        samples: &Arc<Mutex<Vec<f32>>>,
-        play_pos: &Arc<AtomicUsize>,
-        state: &Arc<Mutex<PlaybackState>>,
-        loop_enabled: &Arc<Mutex<bool>>,
-        out_channels: usize,
-    ) {
-        let samples_guard = samples.lock().unwrap();
-        let current_state = *state.lock().unwrap();
-        let is_looping = *loop_enabled.lock().unwrap();
-
-        // Clear output buffer first
-        data.fill(0.0);
-
-        if current_state != PlaybackState::Playing || samples_guard.is_empty() {
-            return;
+    /// Queues a command for the audio callback, first freeing what it has
+    /// handed back.
+    fn send(&mut self, command: Command) {
+        while self.done.pop().is_ok() {}
+        if self.commands.push(command).is_err() {
+            println!("Error: audio output isn't taking commands");
         }
-
-        let mut pos = play_pos.load(Ordering::Relaxed);
-
-        for frame in data.chunks_mut(out_channels) {
-            if pos + out_channels <= samples_guard.len() {
-                frame.copy_from_slice(&samples_guard[pos..pos + out_channels]);
-                pos += out_channels;
-            } else if is_looping {
-                // Loop back to beginning
-                pos = 0;
-                if out_channels <= samples_guard.len() {
-                    frame.copy_from_slice(&samples_guard[0..out_channels]);
-                    pos += out_channels;
-                }
-            } else {
-                // End of playback
-                *state.lock().unwrap() = PlaybackState::Stopped;
-                break;
-            }
-        }
-
-        // Write back updated position
-        play_pos.store(pos, Ordering::Relaxed);
     }
 
+    /// Opens a file and starts decoding it in the background; playback can
+    /// start right away and picks up as soon as the first packet is in.
     pub fn load(&mut self, path: &str) -> Result<(), AudioPlayerError> {
         println!("Loading audio file: {}", path);
 
-        let file = File::open(Path::new(path))?;
-
-        let mss = MediaSourceStream::new(Box::new(file), Default::default());
-
-        // Probe the file format
-        let probed = get_probe()
-            .format(
-                &Default::default(),
-                mss,
-                &FormatOptions::default(),
-                &MetadataOptions::default(),
-            )
-            .map_err(|e| AudioPlayerError::SymphoniaError(Box::new(e)))?;
-
-        let mut format = probed.format;
-
-        // Find the first audio track
-        let track = format
-            .tracks()
-            .iter()
-            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-            .ok_or_else(|| {
-                AudioPlayerError::UnsupportedFormat("No supported audio tracks found".to_string())
-            })?;
-
+        let track = open_track(Path::new(path))?;
         println!(
-            "Track info: codec={:?}, channels={:?}, sample_rate={:?}",
-            track.codec_params.codec, track.codec_params.channels, track.codec_params.sample_rate
+            "Track info: channels={:?}, sample_rate={:?}, frames={:?}",
+            track.channels, track.sample_rate, track.frames
         );
-
-        // Create decoder
-        let mut decoder = get_codecs()
-            .make(&track.codec_params, &DecoderOptions::default())
-            .map_err(|e| AudioPlayerError::SymphoniaError(Box::new(e)))?;
-
-        // Stop current playback
-        self.stop();
-
-        let mut new_samples = Vec::new();
-        let mut packet_count = 0;
-
-        // Decode all packets
-        while let Ok(packet) = format.next_packet() {
-            packet_count += 1;
-
-            let decoded = decoder.decode(&packet).map_err(|e| {
-                AudioPlayerError::DecodingError(format!(
-                    "Failed to decode packet {}: {}",
-                    packet_count, e
-                ))
-            })?;
-
-            let before_len = new_samples.len();
-            self.process_audio_buffer(decoded, &mut new_samples)?;
-            let added_samples = new_samples.len() - before_len;
-
-            if packet_count <= 5 || packet_count % 100 == 0 {
-                println!(
-                    "Processed packet {}: added {} samples (total: {})",
-                    packet_count,
-                    added_samples,
-                    new_samples.len()
-                );
-            }
-        }
-
-        if new_samples.is_empty() {
-            return Err(AudioPlayerError::DecodingError(
-                "No audio samples decoded".to_string(),
-            ));
+        let channels = track.channels.ok_or_else(|| {
+            AudioPlayerError::UnsupportedFormat("Unknown channel layout".to_string())
+        })?;
+
+        // Silence the current file; its decoder goes away, so there's no
+        // point seeking it back to the start
+        self.pause();
+        self.decoder = None;
+
+        // Files of unknown rate are played as if they matched the output
+        let source_rate = track.sample_rate.unwrap_or(self.sample_rate);
+        let source_layout = SourceLayout::detect(Path::new(path), channels);
+        let in_channels = source_layout.channel_count();
+        println!("Channel layout: {:?}", source_layout);
+        if source_rate != self.sample_rate {
+            println!(
+                "Resampling from {} Hz to {} Hz ({:?})",
+                source_rate, self.sample_rate, self.resample_quality
+            );
         }
 
-        println!(
-            "Successfully loaded {} samples from {} packets",
-            new_samples.len(),
-            packet_count
-        );
-
-        // Update player state
-        self.samples_count = new_samples.len();
-        *self.samples.lock().unwrap() = new_samples;
-        self.play_pos.store(0, Ordering::Relaxed);
-        *self.state.lock().unwrap() = PlaybackState::Stopped;
-
-        self.peak_samples = Self::compute_peaks(&*self.samples.lock().unwrap());
+        let control = Arc::new(StreamControl::default());
+        control
+            .total_frames
+            .store(track.frames.unwrap_or(0), Ordering::Relaxed);
+        let buffer_frames = (self.sample_rate as f32 * BUFFER_SECONDS) as usize;
+        let (producer, consumer) = RingBuffer::new(buffer_frames.max(1) * in_channels);
+
+        self.source_rate = source_rate;
+        self.channel_states = vec![ChannelState::Normal; in_channels];
+        self.source_layout = source_layout;
+        self.control = Arc::clone(&control);
+        self.apply_loop();
+        self.peaks = Arc::new(Mutex::new(Vec::new()));
+        self.send(Command::Load(PlaybackStream {
+            consumer,
+            in_channels,
+            matrix: mix_matrix(&self.source_layout, self.out_channels, &self.channel_states),
+            control: Arc::clone(&control),
+        }));
+        self.decoder = Some(StreamDecoder::spawn(
+            Path::new(path),
+            track,
+            self.sample_rate,
+            self.resample_quality,
+            producer,
+            control,
+            Arc::clone(&self.peaks),
+        ));
 
         Ok(())
     }
 
-    fn process_audio_buffer(
-        &self,
-        decoded: AudioBufferRef,
-        output: &mut Vec<f32>,
-    ) -> Result<(), AudioPlayerError> {
-        match decoded {
-            AudioBufferRef::F32(buf) => {
-                let ch1 = if buf.spec().channels.count() > 1 {
-                    buf.chan(1)
-                } else {
-                    &[]
-                };
-                self.convert_buffer(buf.chan(0), ch1, *buf.spec(), output);
-            }
-            AudioBufferRef::F64(buf) => {
-                let ch0: Vec<f32> = buf.chan(0).iter().map(|&s| s as f32).collect();
-                let ch1: Vec<f32> = if buf.spec().channels.count() > 1 {
-                    buf.chan(1).iter().map(|&s| s as f32).collect()
-                } else {
-                    Vec::new()
-                };
-                self.convert_buffer(&ch0, &ch1, *buf.spec(), output);
-            }
-            AudioBufferRef::S16(buf) => {
-                let ch0: Vec<f32> = buf
-                    .chan(0)
-                    .iter()
-                    .map(|&s| s as f32 / i16::MAX as f32)
-                    .collect();
-                let ch1: Vec<f32> = if buf.spec().channels.count() > 1 {
-                    buf.chan(1)
-                        .iter()
-                        .map(|&s| s as f32 / i16::MAX as f32)
-                        .collect()
-                } else {
-                    Vec::new()
-                };
-                self.convert_buffer(&ch0, &ch1, *buf.spec(), output);
-            }
-            AudioBufferRef::S32(buf) => {
-                let ch0: Vec<f32> = buf
-                    .chan(0)
-                    .iter()
-                    .map(|&s| s as f32 / i32::MAX as f32)
-                    .collect();
-                let ch1: Vec<f32> = if buf.spec().channels.count() > 1 {
-                    buf.chan(1)
-                        .iter()
-                        .map(|&s| s as f32 / i32::MAX as f32)
-                        .collect()
-                } else {
-                    Vec::new()
-                };
-                self.convert_buffer(&ch0, &ch1, *buf.spec(), output);
-            }
-            AudioBufferRef::S24(buf) => {
-                // Fixed S24 normalization
-                let ch0: Vec<f32> = buf
-                    .chan(0)
-                    .iter()
-                    .map(|&s| {
-                        let i32_val = s.inner();
-                        // Proper S24 normalization: signed 24-bit has range [-2^23, 2^23-1]
-                        if i32_val >= 0 {
-                            i32_val as f32 / 8_388_607.0 // 2^23 - 1
-                        } else {
-                            i32_val as f32 / 8_388_608.0 // 2^23
-                        }
-                    })
-                    .collect();
-                let ch1: Vec<f32> = if buf.spec().channels.count() > 1 {
-                    buf.chan(1)
-                        .iter()
-                        .map(|&s| {
-                            let i32_val = s.inner();
-                            if i32_val >= 0 {
-                                i32_val as f32 / 8_388_607.0
-                            } else {
-                                i32_val as f32 / 8_388_608.0
-                            }
-                        })
-                        .collect()
-                } else {
-                    Vec::new()
-                };
-                self.convert_buffer(&ch0, &ch1, *buf.spec(), output);
-            }
-            AudioBufferRef::U8(buf) => {
-                let ch0: Vec<f32> = buf
-                    .chan(0)
-                    .iter()
-                    .map(|&s| (s as f32 - 128.0) / 128.0)
-                    .collect();
-                let ch1: Vec<f32> = if buf.spec().channels.count() > 1 {
-                    buf.chan(1)
-                        .iter()
-                        .map(|&s| (s as f32 - 128.0) / 128.0)
-                        .collect()
-                } else {
-                    Vec::new()
-                };
-                self.convert_buffer(&ch0, &ch1, *buf.spec(), output);
-            }
-            _ => {
-                return Err(AudioPlayerError::UnsupportedFormat(
-                    "Unsupported audio buffer format".to_string(),
-                ));
-            }
-        }
-        Ok(())
+    /// Min/max pairs outlining the loaded file, filled in as it's scanned.
+    pub fn peak_samples(&self) -> Vec<(f32, f32)> {
+        self.peaks.lock().unwrap().clone()
     }
 
-    fn compute_peaks(samples: &[f32]) -> Vec<(f32, f32)> {
-        // Create peak samples for efficient visualization
-        // We'll downsample to have ~2000 points for display
-        let target_points = 2000;
-        let samples_per_point = samples.len().max(target_points) / target_points;
-        let mut peak_samples = Vec::with_capacity(target_points);
-
-        for chunk in samples.chunks(samples_per_point) {
-            if !chunk.is_empty() {
-                let min = *chunk
-                    .iter()
-                    .min_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
-                    .unwrap_or(&0.0);
-                let max = *chunk
-                    .iter()
-                    .max_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
-                    .unwrap_or(&0.0);
-                peak_samples.push((min, max));
-            }
-        }
-
-        peak_samples
-    }
-
-    fn convert_buffer(&self, ch0: &[f32], ch1: &[f32], spec: SignalSpec, output: &mut Vec<f32>) {
-        let in_channels = spec.channels.count();
-        let out_channels = self.out_channels;
-
-        match (in_channels, out_channels) {
-            (1, 1) => {
-                output.extend_from_slice(ch0);
-            }
-            (1, 2) => {
-                // Mono to stereo: duplicate mono channel
-                for &sample in ch0 {
-                    output.push(sample);
-                    output.push(sample);
-                }
-            }
-            (2, 1) => {
-                // Stereo to mono: mix both channels
-                for i in 0..ch0.len() {
-                    let left = ch0[i];
-                    let right = ch1.get(i).copied().unwrap_or(0.0);
-                    output.push((left + right) * 0.5);
-                }
-            }
-            (2, 2) => {
-                // Stereo to stereo: direct copy
-                for i in 0..ch0.len() {
-                    output.push(ch0[i]);
-                    output.push(ch1.get(i).copied().unwrap_or(0.0));
-                }
-            }
-            (n, m) => {
-                println!(
-                    "Warning: Unusual channel configuration {} -> {}, using fallback",
-                    n, m
-                );
-                // Fallback: duplicate first channel for all output channels
-                for &sample in ch0 {
-                    for _ in 0..out_channels {
-                        output.push(sample);
-                    }
-                }
-            }
-        }
+    pub fn is_loaded(&self) -> bool {
+        self.decoder.is_some()
     }
 
     // Playback control methods
     pub fn play(&self) {
-        *self.state.lock().unwrap() = PlaybackState::Playing;
+        // Playing again after the end starts over
+        if self.control.ended.load(Ordering::Relaxed) {
+            self.seek_to_frame(0);
+        }
+        self.set_state(PlaybackState::Playing);
         println!("Playback started");
     }
 
     pub fn pause(&self) {
-        *self.state.lock().unwrap() = PlaybackState::Paused;
+        self.set_state(PlaybackState::Paused);
         println!("Playback paused");
     }
 
     pub fn stop(&self) {
-        *self.state.lock().unwrap() = PlaybackState::Stopped;
-        self.play_pos.store(0, Ordering::Relaxed);
+        self.set_state(PlaybackState::Stopped);
+        self.seek_to_frame(0);
         println!("Playback stopped");
     }
 
@@ -494,37 +433,94 @@ impl AudioPlayer {
         }
     }
 
-    pub fn set_loop(&self, enabled: bool) {
-        *self.loop_enabled.lock().unwrap() = enabled;
+    pub fn set_loop(&mut self, enabled: bool) {
+        self.loop_enabled = enabled;
+        self.apply_loop();
         println!("Loop {}", if enabled { "enabled" } else { "disabled" });
     }
 
+    /// Restricts looped playback to the frames `start..end` of the loaded
+    /// file, or to the whole file if `None`. The region is kept for the next
+    /// file loaded too.
+    pub fn set_loop_region(&mut self, region: Option<(u64, u64)>) {
+        self.loop_frames = region.filter(|(start, end)| start < end);
+        self.apply_loop();
+    }
+
+    fn apply_loop(&self) {
+        let (start, end) = self.loop_frames.unwrap_or((0, 0));
+        self.control.loop_start.store(start, Ordering::Relaxed);
+        self.control.loop_end.store(end, Ordering::Relaxed);
+        self.control
+            .loop_enabled
+            .store(self.loop_enabled, Ordering::Relaxed);
+    }
+
+    /// Mutes or solos a channel of the loaded file, until the next file loads.
+    pub fn set_channel_state(&mut self, channel: usize, state: ChannelState) {
+        let Some(current) = self.channel_states.get_mut(channel) else {
+            return;
+        };
+        *current = state;
+        let matrix = mix_matrix(&self.source_layout, self.out_channels, &self.channel_states);
+        self.send(Command::SetMatrix(matrix));
+    }
+
+    /// Sets how files loaded from now on are resampled to the output rate.
+    pub fn set_resample_quality(&mut self, quality: ResampleQuality) {
+        self.resample_quality = quality;
+    }
+
+    /// Scales playback by `gain` (linear), e.g. to level out loudness.
+    pub fn set_gain(&self, gain: f32) {
+        self.shared.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    fn set_state(&self, state: PlaybackState) {
+        self.shared.state.store(state as u8, Ordering::Relaxed);
+    }
+
     pub fn get_state(&self) -> PlaybackState {
-        *self.state.lock().unwrap()
+        PlaybackState::from_u8(self.shared.state.load(Ordering::Relaxed))
+    }
+
+    /// How often playback has dropped out since the player started, because
+    /// decoding couldn't keep up.
+    pub fn xrun_count(&self) -> u64 {
+        self.shared.xruns.load(Ordering::Relaxed)
     }
 
-    pub fn get_position_index(&self) -> usize {
-        self.play_pos.load(Ordering::Relaxed)
+    /// The frame of the loaded file being heard.
+    pub fn get_position_frame(&self) -> u64 {
+        self.control.position(self.source_rate, self.sample_rate)
     }
 
     pub fn get_position_percentage(&self) -> f32 {
-        self.get_position_index() as f32 / self.samples_count as f32
+        self.frame_to_percentage(self.get_position_frame())
+    }
+
+    /// Converts a frame of the loaded file to a fraction of its length.
+    pub fn frame_to_percentage(&self, frame: u64) -> f32 {
+        match self.control.total_frames.load(Ordering::Relaxed) {
+            0 => 0.0,
+            total => frame as f32 / total as f32,
+        }
     }
 
     pub fn seek_to_position_percentage(&self, sample_pos_percent: f32) {
-        self.seek_to_position((self.samples_count as f32 * sample_pos_percent) as usize);
+        let total = self.control.total_frames.load(Ordering::Relaxed);
+        self.seek_to_frame((total as f32 * sample_pos_percent.clamp(0.0, 1.0)) as u64);
     }
 
-    pub fn seek_to_position(&self, sample_pos: usize) {
-        let total_samples = self.samples.lock().unwrap().len();
-        let clamped_pos = sample_pos.min(total_samples);
-        self.play_pos.store(clamped_pos, Ordering::Relaxed);
-        println!("Position set to sample {}/{}", clamped_pos, total_samples);
+    pub fn seek_to_frame(&self, frame: u64) {
+        if let Some(decoder) = &self.decoder {
+            decoder.seek(frame);
+            println!("Position set to frame {}", frame);
+        }
     }
 
+    /// Length of the loaded file, in seconds.
     pub fn get_duration_seconds(&self) -> f32 {
-        let total_samples = self.samples.lock().unwrap().len();
-        let frames = total_samples / self.out_channels;
-        frames as f32 / self.sample_rate as f32
+        self.control.total_frames.load(Ordering::Relaxed) as f32 / self.source_rate as f32
     }
 }
@@ -0,0 +1,334 @@
+use crate::spectrum::{Stft, full_frame_starts};
+
+/// Instrument categories the classifier sorts samples into. Their names are
+/// used as tag names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Kick,
+    Snare,
+    Hat,
+    Clap,
+    Perc,
+    Bass,
+    Synth,
+    Vocal,
+    Fx,
+    Loop,
+}
+
+impl Category {
+    pub const ALL: [Category; 10] = [
+        Category::Kick,
+        Category::Snare,
+        Category::Hat,
+        Category::Clap,
+        Category::Perc,
+        Category::Bass,
+        Category::Synth,
+        Category::Vocal,
+        Category::Fx,
+        Category::Loop,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Kick => "kick",
+            Category::Snare => "snare",
+            Category::Hat => "hat",
+            Category::Clap => "clap",
+            Category::Perc => "perc",
+            Category::Bass => "bass",
+            Category::Synth => "synth",
+            Category::Vocal => "vocal",
+            Category::Fx => "fx",
+            Category::Loop => "loop",
+        }
+    }
+}
+
+/// Only the start of long samples is looked at.
+const MAX_SECONDS: f32 = 10.0;
+/// Spectral frame length and hop, in samples.
+const FRAME_SIZE: usize = 2048;
+const FRAME_HOP: usize = 1024;
+/// Amplitude envelope resolution, in seconds.
+const ENVELOPE_SECONDS: f32 = 0.005;
+/// Frequencies below this count as low end, in Hz.
+const LOW_END: f32 = 150.0;
+/// Results less confident than this are left unclassified.
+const MIN_CONFIDENCE: f32 = 0.25;
+
+/// What the classifier looks at. Spectral values are averages over the
+/// audible frames, weighted by their energy.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Features {
+    pub duration: f32,
+    /// Brightness: the spectrum's center of mass, in Hz
+    pub centroid: f32,
+    /// Frequency below which 85% of the energy lies, in Hz
+    pub rolloff: f32,
+    /// 0 for a pure tone, towards 1 for white noise
+    pub flatness: f32,
+    /// Share of the energy below `LOW_END`
+    pub low_ratio: f32,
+    /// Sign changes per second
+    pub zero_crossing_rate: f32,
+    /// Seconds from 10% to 90% of the peak level
+    pub attack_time: f32,
+    /// Seconds from the peak until the level stays below 10% of it
+    pub decay_time: f32,
+    /// Distinct hits per second, counting anything under a second as one
+    pub onset_rate: f32,
+    /// Filled in from the pitch and tempo analysis, 0 if there's none
+    pub pitch: Option<f64>,
+    pub pitch_confidence: f32,
+    pub tempo_confidence: f32,
+}
+
+impl Features {
+    /// Extracts the spectral and temporal features of mono audio. `None` for
+    /// silence.
+    pub fn extract(samples: &[f32], sample_rate: u32) -> Option<Self> {
+        if sample_rate == 0 || samples.is_empty() {
+            return None;
+        }
+        let rate = sample_rate as f32;
+        let duration = samples.len() as f32 / rate;
+        let samples = &samples[..samples.len().min((rate * MAX_SECONDS) as usize)];
+
+        let mut features = Self {
+            duration,
+            ..Self::default()
+        };
+        features.add_spectral(samples, rate)?;
+        features.add_temporal(samples, rate);
+        Some(features)
+    }
+
+    fn add_spectral(&mut self, samples: &[f32], rate: f32) -> Option<()> {
+        let mut stft = Stft::new(FRAME_SIZE);
+        let bin_width = rate / FRAME_SIZE as f32;
+        let bins = FRAME_SIZE / 2;
+
+        let mut power = vec![0.0f32; bins];
+        let (mut centroid, mut rolloff, mut flatness, mut low_ratio) = (0.0, 0.0, 0.0, 0.0);
+        let mut total_weight = 0.0f32;
+
+        for start in full_frame_starts(samples, FRAME_HOP) {
+            let spectrum = stft.spectrum(samples, start);
+            for (p, value) in power.iter_mut().zip(&spectrum[..bins]) {
+                *p = value.norm_sqr();
+            }
+
+            let energy: f32 = power[1..].iter().sum();
+            if energy > 1e-9 {
+                let frame_centroid = power[1..]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| (i + 1) as f32 * bin_width * p)
+                    .sum::<f32>()
+                    / energy;
+
+                let mut cumulative = 0.0;
+                let rolloff_bin = power[1..]
+                    .iter()
+                    .position(|p| {
+                        cumulative += p;
+                        cumulative >= 0.85 * energy
+                    })
+                    .unwrap_or(bins - 2)
+                    + 1;
+
+                let log_mean =
+                    power[1..].iter().map(|p| (p + 1e-12).ln()).sum::<f32>() / (bins - 1) as f32;
+                let frame_flatness = log_mean.exp() / (energy / (bins - 1) as f32);
+
+                let low_bins = ((LOW_END / bin_width) as usize).clamp(1, bins - 1);
+                let frame_low = power[1..=low_bins].iter().sum::<f32>() / energy;
+
+                centroid += frame_centroid * energy;
+                rolloff += rolloff_bin as f32 * bin_width * energy;
+                flatness += frame_flatness * energy;
+                low_ratio += frame_low * energy;
+                total_weight += energy;
+            }
+        }
+
+        if total_weight <= 0.0 {
+            return None;
+        }
+        self.centroid = centroid / total_weight;
+        self.rolloff = rolloff / total_weight;
+        self.flatness = (flatness / total_weight).clamp(0.0, 1.0);
+        self.low_ratio = (low_ratio / total_weight).clamp(0.0, 1.0);
+        Some(())
+    }
+
+    fn add_temporal(&mut self, samples: &[f32], rate: f32) {
+        let crossings = samples
+            .windows(2)
+            .filter(|pair| (pair[0] >= 0.0) != (pair[1] >= 0.0))
+            .count();
+        self.zero_crossing_rate = crossings as f32 / (samples.len() as f32 / rate);
+
+        let hop = ((rate * ENVELOPE_SECONDS) as usize).max(1);
+        let envelope: Vec<f32> = samples
+            .chunks(hop)
+            .map(|chunk| (chunk.iter().map(|s| s * s).sum::<f32>() / chunk.len() as f32).sqrt())
+            .collect();
+        let Some((peak_index, &peak)) = envelope
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+        else {
+            return;
+        };
+        if peak <= 0.0 {
+            return;
+        }
+
+        let first_above = |level: f32| envelope.iter().position(|&e| e >= level * peak);
+        let attack_frames = match (first_above(0.1), first_above(0.9)) {
+            (Some(start), Some(end)) => end.saturating_sub(start),
+            _ => 0,
+        };
+        self.attack_time = attack_frames as f32 * ENVELOPE_SECONDS;
+
+        let last_loud = envelope
+            .iter()
+            .rposition(|&e| e >= 0.1 * peak)
+            .unwrap_or(peak_index);
+        self.decay_time = (last_loud - peak_index) as f32 * ENVELOPE_SECONDS;
+
+        // A rise of 6 dB within 20 ms from a quieter level is a new hit;
+        // hits closer than 50 ms are one flam
+        let lookback = 4;
+        let refractory = (0.05 / ENVELOPE_SECONDS) as usize;
+        let mut onsets = 0;
+        let mut last_onset: Option<usize> = None;
+        for i in lookback..envelope.len() {
+            let rising = envelope[i] >= 0.1 * peak && envelope[i] > 2.0 * envelope[i - lookback];
+            if rising && last_onset.is_none_or(|last| i - last > refractory) {
+                onsets += 1;
+                last_onset = Some(i);
+            }
+        }
+        self.onset_rate = onsets.max(1) as f32 / (samples.len() as f32 / rate).max(1.0);
+    }
+}
+
+/// Sorts a sample into a category by hand-tuned rules over its features.
+/// Returns the category and a confidence from 0 to 1, or `None` if no rule
+/// fits well enough.
+pub fn classify(features: &Features) -> Option<(Category, f32)> {
+    let mut scores: Vec<(Category, f32)> = Category::ALL
+        .into_iter()
+        .map(|category| (category, score(category, features)))
+        .collect();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    // Confident when the best rule fits well and clearly beats the runner-up
+    let (category, best) = scores[0];
+    let runner_up = scores[1].1;
+    let confidence = (best - 0.5 * runner_up).clamp(0.0, 1.0);
+    (confidence >= MIN_CONFIDENCE).then_some((category, confidence))
+}
+
+/// How well the features fit a category, from 0 to 1.
+fn score(category: Category, f: &Features) -> f32 {
+    let one_shot = falling(f.duration, 1.5, 4.0) * falling(f.onset_rate, 2.0, 4.0);
+    let punchy = falling(f.attack_time, 0.01, 0.04);
+    let pitched = f.pitch_confidence;
+    let noisy = rising(f.flatness, 0.02, 0.15);
+    let pitch = f.pitch.unwrap_or(0.0) as f32;
+
+    match category {
+        Category::Kick => {
+            one_shot
+                * punchy
+                * rising(f.low_ratio, 0.3, 0.6)
+                * falling(f.centroid, 400.0, 1500.0)
+                * falling(f.decay_time, 0.4, 0.9)
+        }
+        Category::Snare => {
+            one_shot
+                * punchy
+                * noisy
+                * bump(f.centroid, 800.0, 1500.0, 5000.0, 7000.0)
+                * falling(f.decay_time, 0.4, 1.0)
+                * rising(f.low_ratio, 0.0, 0.05).max(0.6)
+        }
+        Category::Clap => {
+            // Claps are a few noise bursts in a row, so the attack smears
+            one_shot
+                * noisy
+                * bump(f.centroid, 700.0, 1000.0, 3500.0, 5000.0)
+                * bump(f.attack_time, 0.003, 0.01, 0.04, 0.06)
+                * falling(f.low_ratio, 0.05, 0.2)
+                * falling(f.decay_time, 0.3, 0.8)
+        }
+        Category::Hat => {
+            one_shot
+                * punchy
+                * rising(f.centroid, 4000.0, 7000.0)
+                * rising(f.rolloff, 7000.0, 11000.0)
+                * rising(f.zero_crossing_rate, 3000.0, 6000.0)
+        }
+        Category::Perc => {
+            // The catch-all for short hits that aren't clearly anything else
+            0.45 * one_shot * punchy * falling(f.decay_time, 0.5, 1.2)
+        }
+        Category::Bass => {
+            // Sustained low notes; a kick's pitched body dies away sooner
+            pitched
+                * bump(pitch, 25.0, 30.0, 180.0, 300.0)
+                * rising(f.low_ratio, 0.15, 0.4).max(falling(f.centroid, 300.0, 800.0))
+                * rising(f.decay_time, 0.3, 0.6)
+                * falling(f.tempo_confidence, 0.5, 0.9).max(one_shot)
+        }
+        Category::Synth => {
+            // Leads, plucks and pads: pitched, above the bass and sustained
+            pitched
+                * falling(f.low_ratio, 0.3, 0.6)
+                * falling(f.flatness, 0.1, 0.3)
+                * rising(f.decay_time, 0.3, 0.8)
+                * falling(f.tempo_confidence, 0.5, 0.9).max(one_shot)
+        }
+        Category::Vocal => {
+            // Voices are pitched in the singing range but breathier than synths
+            0.8 * pitched
+                * bump(pitch, 80.0, 100.0, 800.0, 1100.0)
+                * bump(f.flatness, 0.02, 0.08, 0.25, 0.4)
+                * bump(f.centroid, 400.0, 800.0, 3000.0, 4500.0)
+                * rising(f.attack_time, 0.01, 0.04)
+        }
+        Category::Fx => {
+            // Risers, sweeps and noise: long, unrhythmic, unpitched, and
+            // slow to build or noisy
+            rising(f.duration, 1.0, 2.5)
+                * falling(f.tempo_confidence, 0.3, 0.6)
+                * (1.0 - pitched)
+                * rising(f.attack_time, 0.1, 0.5).max(noisy)
+        }
+        Category::Loop => {
+            rising(f.duration, 1.5, 3.0)
+                * rising(f.onset_rate, 1.0, 2.5)
+                * rising(f.tempo_confidence, 0.2, 0.5).max(0.6)
+        }
+    }
+}
+
+/// 0 below `low`, 1 above `high`, linear in between.
+fn rising(x: f32, low: f32, high: f32) -> f32 {
+    ((x - low) / (high - low)).clamp(0.0, 1.0)
+}
+
+/// 1 below `low`, 0 above `high`, linear in between.
+fn falling(x: f32, low: f32, high: f32) -> f32 {
+    1.0 - rising(x, low, high)
+}
+
+/// 1 between `b` and `c`, sloping to 0 at `a` and `d`.
+fn bump(x: f32, a: f32, b: f32, c: f32, d: f32) -> f32 {
+    rising(x, a, b).min(falling(x, c, d))
+}
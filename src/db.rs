@@ -1,54 +1,1030 @@
-use rusqlite::{params, Connection};
-
-use crate::sample::Sample;
-
-pub fn init_db(conn: &Connection) -> rusqlite::Result<()> {
-    conn.execute_batch(
-        "
-        CREATE TABLE IF NOT EXISTS samples (
-            id INTEGER PRIMARY KEY,
-            path TEXT UNIQUE NOT NULL,
-            name TEXT NOT NULL,
-            format TEXT,
-            sample_rate INTEGER,
-            size INTEGER
-        );
-        ",
+use rusqlite::types::Value;
+use rusqlite::{Connection, OptionalExtension, params, params_from_iter};
+
+use crate::analysis::Analysis;
+use crate::duplicates::DuplicateCandidate;
+use crate::loudness::Loudness;
+use crate::metadata::MetadataTag;
+use crate::migrations::{MigrationError, migrate};
+use crate::query::{LibraryScope, SearchQuery};
+use crate::sample::{Collection, ColorLabel, CueMarker, LibraryRoot, Sample, Tag};
+
+/// Opens the database at `path`. Every connection needs foreign keys turned
+/// on, or the schema's `ON DELETE` clauses don't fire.
+pub fn open_db(path: &str) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.pragma_update(None, "foreign_keys", true)?;
+    Ok(conn)
+}
+
+/// Opens the schema for use, upgrading older databases and refusing ones
+/// written by a newer version.
+pub fn init_db(conn: &Connection) -> Result<(), MigrationError> {
+    migrate(conn)
+}
+
+pub fn get_setting(conn: &Connection, key: &str) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT value FROM settings WHERE key = ?1",
+        params![key],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+pub fn set_setting(conn: &Connection, key: &str, value: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO settings (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        params![key, value],
     )?;
     Ok(())
 }
 
-pub fn insert_sample(conn: &Connection, meta: &Sample) -> rusqlite::Result<()> {
+const SAMPLE_COLUMNS: &str = "id, root_id, path, name, format, sample_rate, size, modified,
+    content_hash, missing, duration, channels, channel_layout, bit_depth, frames, bitrate,
+    container, loop_start, loop_end, root_note, tempo, musical_key, favorite, rating, color_label,
+    bpm, bpm_confidence, estimated_key, key_confidence, true_peak, rms, lufs, short_term_lufs,
+    pitch, pitch_confidence, hidden";
+
+fn sample_from_row(row: &rusqlite::Row) -> rusqlite::Result<Sample> {
+    Ok(Sample {
+        id: row.get(0)?,
+        root_id: row.get(1)?,
+        path: row.get(2)?,
+        name: row.get(3)?,
+        format: row.get(4)?,
+        sample_rate: row.get(5)?,
+        size: row.get(6)?,
+        modified: row.get(7)?,
+        content_hash: row.get(8)?,
+        missing: row.get(9)?,
+        duration: row.get(10)?,
+        channels: row.get(11)?,
+        channel_layout: row.get(12)?,
+        bit_depth: row.get(13)?,
+        frames: row.get(14)?,
+        bitrate: row.get(15)?,
+        container: row.get(16)?,
+        loop_start: row.get(17)?,
+        loop_end: row.get(18)?,
+        root_note: row.get(19)?,
+        tempo: row.get(20)?,
+        musical_key: row.get(21)?,
+        favorite: row.get(22)?,
+        rating: row.get(23)?,
+        color_label: row
+            .get::<_, Option<String>>(24)?
+            .and_then(|key| ColorLabel::from_key(&key)),
+        bpm: row.get(25)?,
+        bpm_confidence: row.get(26)?,
+        estimated_key: row.get(27)?,
+        key_confidence: row.get(28)?,
+        loudness: match (row.get(29)?, row.get(30)?, row.get(31)?, row.get(32)?) {
+            (Some(true_peak), Some(rms), Some(integrated), Some(short_term)) => Some(Loudness {
+                true_peak,
+                rms,
+                integrated,
+                short_term,
+            }),
+            _ => None,
+        },
+        pitch: row.get(33)?,
+        pitch_confidence: row.get(34)?,
+        hidden: row.get(35)?,
+    })
+}
+
+/// Inserts a new sample row and returns its id. If a row for the path already
+/// exists, e.g. because the watcher and an import both got to it, that row is
+/// left as is and its id returned.
+pub fn insert_sample(conn: &Connection, meta: &Sample) -> rusqlite::Result<isize> {
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO samples
+            (root_id, path, name, format, sample_rate, size, modified, content_hash, missing,
+             duration, channels, channel_layout, bit_depth, frames, bitrate, container,
+             loop_start, loop_end, root_note, tempo, musical_key)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
+                 ?18, ?19, ?20, ?21)",
+        params![
+            meta.root_id,
+            meta.path,
+            meta.name,
+            meta.format,
+            meta.sample_rate,
+            meta.size as i64,
+            meta.modified,
+            meta.content_hash,
+            meta.missing,
+            meta.duration,
+            meta.channels,
+            meta.channel_layout,
+            meta.bit_depth,
+            meta.frames.map(|frames| frames as i64),
+            meta.bitrate,
+            meta.container,
+            meta.loop_start.map(|frame| frame as i64),
+            meta.loop_end.map(|frame| frame as i64),
+            meta.root_note,
+            meta.tempo,
+            meta.musical_key,
+        ],
+    )?;
+    if inserted == 0 {
+        return conn.query_row(
+            "SELECT id FROM samples WHERE path = ?1",
+            params![meta.path],
+            |row| row.get(0),
+        );
+    }
+    Ok(conn.last_insert_rowid() as isize)
+}
+
+/// Overwrites every stored field of an existing sample row, keeping its id so
+/// anything referencing the sample survives edits and moves. Favorites,
+/// ratings and color labels are left alone; see `set_sample_marks`.
+pub fn update_sample(conn: &Connection, meta: &Sample) -> rusqlite::Result<()> {
     conn.execute(
-        "INSERT OR IGNORE INTO samples (path, name, format, sample_rate, size)
-         VALUES (?1, ?2, ?3, ?4, ?5)",
+        "UPDATE samples SET root_id = ?1, path = ?2, name = ?3, format = ?4, sample_rate = ?5,
+            size = ?6, modified = ?7, content_hash = ?8, missing = ?9, duration = ?10,
+            channels = ?11, channel_layout = ?12, bit_depth = ?13, frames = ?14, bitrate = ?15,
+            container = ?16, loop_start = ?17, loop_end = ?18, root_note = ?19, tempo = ?20,
+            musical_key = ?21
+         WHERE id = ?22",
         params![
+            meta.root_id,
             meta.path,
             meta.name,
             meta.format,
             meta.sample_rate,
             meta.size as i64,
+            meta.modified,
+            meta.content_hash,
+            meta.missing,
+            meta.duration,
+            meta.channels,
+            meta.channel_layout,
+            meta.bit_depth,
+            meta.frames.map(|frames| frames as i64),
+            meta.bitrate,
+            meta.container,
+            meta.loop_start.map(|frame| frame as i64),
+            meta.loop_end.map(|frame| frame as i64),
+            meta.root_note,
+            meta.tempo,
+            meta.musical_key,
+            meta.id,
         ],
     )?;
     Ok(())
 }
 
+/// The favorite, rating and color label fields the user sets from the browser.
+#[derive(Debug, Clone, Copy)]
+pub enum SampleMark {
+    Favorite(bool),
+    Rating(u8),
+    ColorLabel(Option<ColorLabel>),
+    Hidden(bool),
+}
+
+pub fn set_sample_marks(
+    conn: &Connection,
+    sample_ids: &[isize],
+    mark: SampleMark,
+) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for &sample_id in sample_ids {
+        match mark {
+            SampleMark::Favorite(favorite) => tx.execute(
+                "UPDATE samples SET favorite = ?1 WHERE id = ?2",
+                params![favorite, sample_id],
+            )?,
+            SampleMark::Rating(rating) => tx.execute(
+                "UPDATE samples SET rating = ?1 WHERE id = ?2",
+                params![rating.min(5), sample_id],
+            )?,
+            SampleMark::ColorLabel(label) => tx.execute(
+                "UPDATE samples SET color_label = ?1 WHERE id = ?2",
+                params![label.map(ColorLabel::key), sample_id],
+            )?,
+            SampleMark::Hidden(hidden) => tx.execute(
+                "UPDATE samples SET hidden = ?1 WHERE id = ?2",
+                params![hidden, sample_id],
+            )?,
+        };
+    }
+    tx.commit()
+}
+
+pub fn set_sample_missing(
+    conn: &Connection,
+    sample_id: isize,
+    missing: bool,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE samples SET missing = ?1 WHERE id = ?2",
+        params![missing, sample_id],
+    )?;
+    Ok(())
+}
+
 pub fn load_samples(conn: &Connection) -> rusqlite::Result<Vec<Sample>> {
-    let mut stmt = conn.prepare("SELECT id, path, name, format, sample_rate, size FROM samples")?;
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM samples", SAMPLE_COLUMNS))?;
+    let rows = stmt.query_map([], sample_from_row)?;
+
+    let mut samples = Vec::new();
+    for row in rows {
+        samples.push(row?);
+    }
+    Ok(samples)
+}
+
+pub fn load_sample_by_path(conn: &Connection, path: &str) -> rusqlite::Result<Option<Sample>> {
+    conn.query_row(
+        &format!("SELECT {} FROM samples WHERE path = ?1", SAMPLE_COLUMNS),
+        params![path],
+        sample_from_row,
+    )
+    .optional()
+}
+
+/// Loads the samples in `scope` matching a search box query, leaving out
+/// hidden ones.
+pub fn search_samples(
+    conn: &Connection,
+    scope: LibraryScope,
+    query: &SearchQuery,
+) -> rusqlite::Result<Vec<Sample>> {
+    let (scope_clause, mut values) = scope.to_sql();
+    let (query_clause, query_values) = query.to_sql();
+    values.extend(query_values);
+    let mut sql = format!(
+        "SELECT {} FROM samples WHERE hidden = 0 AND {} AND {}",
+        SAMPLE_COLUMNS, scope_clause, query_clause
+    );
+    if let Some((order, order_values)) = scope.order_sql() {
+        sql.push_str(&format!(" ORDER BY {}", order));
+        values.extend(order_values);
+    }
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(params_from_iter(values), sample_from_row)?;
+
+    let mut samples = Vec::new();
+    for row in rows {
+        samples.push(row?);
+    }
+    Ok(samples)
+}
+
+/// Rewrites a sample's full-text index entry from its row, metadata and tags.
+pub fn update_search_index(conn: &Connection, sample_id: isize) -> rusqlite::Result<()> {
+    conn.execute(
+        "DELETE FROM samples_fts WHERE rowid = ?1",
+        params![sample_id],
+    )?;
+    conn.execute(
+        "INSERT INTO samples_fts (rowid, name, title, artist, path, tags, comments)
+         SELECT id, name,
+             (SELECT group_concat(value, ' ') FROM sample_metadata
+              WHERE sample_id = samples.id AND key = 'title'),
+             (SELECT group_concat(value, ' ') FROM sample_metadata
+              WHERE sample_id = samples.id AND key IN ('artist', 'album_artist')),
+             path,
+             (SELECT group_concat(value, ' ') FROM (
+                 SELECT value FROM sample_metadata
+                 WHERE sample_id = samples.id
+                 AND key IN ('genre', 'keywords', 'mood', 'subject', 'style', 'grouping')
+                 UNION ALL
+                 SELECT tags.name FROM sample_tags JOIN tags ON tags.id = sample_tags.tag_id
+                 WHERE sample_tags.sample_id = samples.id
+             )),
+             (SELECT group_concat(value, ' ') FROM sample_metadata
+              WHERE sample_id = samples.id AND key IN ('comment', 'description'))
+         FROM samples WHERE id = ?1",
+        params![sample_id],
+    )?;
+    Ok(())
+}
+
+/// Loads the samples still present on disk whose analysis is missing or was
+/// done by an older analysis version.
+pub fn load_unanalyzed_samples(conn: &Connection, version: u32) -> rusqlite::Result<Vec<Sample>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM samples WHERE missing = 0 AND analysis_version < ?1",
+        SAMPLE_COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![version], sample_from_row)?;
+
+    let mut samples = Vec::new();
+    for row in rows {
+        samples.push(row?);
+    }
+    Ok(samples)
+}
+
+/// Stores the results of analyzing a sample's audio. Its category replaces
+/// the sample's previous auto-tag, unless the user already tagged it so, and
+/// its feature vector and fingerprint replace the previous ones.
+pub fn store_analysis(
+    conn: &Connection,
+    sample_id: isize,
+    analysis: &Analysis,
+    version: u32,
+) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    let updated = tx.execute(
+        "UPDATE samples SET bpm = ?1, bpm_confidence = ?2, estimated_key = ?3,
+            key_confidence = ?4, true_peak = ?5, rms = ?6, lufs = ?7, short_term_lufs = ?8,
+            pitch = ?9, pitch_confidence = ?10, audio_hash = ?11, analysis_version = ?12
+         WHERE id = ?13",
+        params![
+            analysis.bpm,
+            analysis.bpm_confidence,
+            analysis.key,
+            analysis.key_confidence,
+            analysis.loudness.map(|l| l.true_peak),
+            analysis.loudness.map(|l| l.rms),
+            analysis.loudness.map(|l| l.integrated),
+            analysis.loudness.map(|l| l.short_term),
+            analysis.pitch,
+            analysis.pitch_confidence,
+            analysis.audio_hash,
+            version,
+            sample_id
+        ],
+    )?;
+    // Deleted while it was being analyzed
+    if updated == 0 {
+        return Ok(());
+    }
+
+    tx.execute(
+        "DELETE FROM sample_tags WHERE sample_id = ?1 AND auto = 1",
+        params![sample_id],
+    )?;
+    if let Some((category, confidence)) = analysis.category {
+        tx.execute(
+            "INSERT OR IGNORE INTO tags (name) VALUES (?1)",
+            params![category.name()],
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO sample_tags (sample_id, tag_id, auto, confidence)
+             SELECT ?1, id, 1, ?2 FROM tags WHERE name = ?3",
+            params![sample_id, confidence, category.name()],
+        )?;
+    }
+
+    tx.execute(
+        "DELETE FROM sample_features WHERE sample_id = ?1",
+        params![sample_id],
+    )?;
+    if let Some(vector) = &analysis.feature_vector {
+        let bytes: Vec<u8> = vector
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        tx.execute(
+            "INSERT INTO sample_features (sample_id, vector) VALUES (?1, ?2)",
+            params![sample_id, bytes],
+        )?;
+    }
+
+    tx.execute(
+        "DELETE FROM sample_fingerprints WHERE sample_id = ?1",
+        params![sample_id],
+    )?;
+    if let Some(fingerprint) = &analysis.fingerprint {
+        let bytes: Vec<u8> = fingerprint
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        tx.execute(
+            "INSERT INTO sample_fingerprints (sample_id, fingerprint) VALUES (?1, ?2)",
+            params![sample_id, bytes],
+        )?;
+    }
+    update_search_index(&tx, sample_id)?;
+    tx.commit()
+}
+
+/// Loads the similarity feature vector of every sample still present on disk.
+pub fn load_feature_vectors(conn: &Connection) -> rusqlite::Result<Vec<(isize, Vec<f32>)>> {
+    let mut stmt = conn.prepare(
+        "SELECT sample_id, vector FROM sample_features
+         JOIN samples ON samples.id = sample_features.sample_id
+         WHERE samples.missing = 0",
+    )?;
     let rows = stmt.query_map([], |row| {
-        Ok(Sample {
-            id: row.get(0)?,
-            path: row.get(1)?,
-            name: row.get(2)?,
-            format: row.get(3)?,
-            sample_rate: row.get(4)?,
-            size: row.get(5)?,
+        let bytes: Vec<u8> = row.get(1)?;
+        let vector = bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        Ok((row.get(0)?, vector))
+    })?;
+
+    let mut vectors = Vec::new();
+    for row in rows {
+        vectors.push(row?);
+    }
+    Ok(vectors)
+}
+
+/// Loads the audio hash and fingerprint of every sample still present on
+/// disk that has either, hidden ones included.
+pub fn load_duplicate_candidates(conn: &Connection) -> rusqlite::Result<Vec<DuplicateCandidate>> {
+    let mut stmt = conn.prepare(
+        "SELECT samples.id, samples.audio_hash, sample_fingerprints.fingerprint, samples.pitch
+         FROM samples
+         LEFT JOIN sample_fingerprints ON sample_fingerprints.sample_id = samples.id
+         WHERE samples.missing = 0
+         AND (samples.audio_hash IS NOT NULL OR sample_fingerprints.fingerprint IS NOT NULL)",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        let bytes: Option<Vec<u8>> = row.get(2)?;
+        let fingerprint = bytes
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        Ok(DuplicateCandidate {
+            sample_id: row.get(0)?,
+            audio_hash: row.get(1)?,
+            fingerprint,
+            pitch: row.get(3)?,
         })
     })?;
 
+    let mut candidates = Vec::new();
+    for row in rows {
+        candidates.push(row?);
+    }
+    Ok(candidates)
+}
+
+/// Loads the given samples, hidden ones included, in no particular order.
+pub fn load_samples_by_ids(
+    conn: &Connection,
+    sample_ids: &[isize],
+) -> rusqlite::Result<Vec<Sample>> {
+    if sample_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let placeholders = vec!["?"; sample_ids.len()].join(", ");
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM samples WHERE id IN ({})",
+        SAMPLE_COLUMNS, placeholders
+    ))?;
+    let rows = stmt.query_map(params_from_iter(sample_ids), sample_from_row)?;
+
+    let mut samples = Vec::new();
+    for row in rows {
+        samples.push(row?);
+    }
+    Ok(samples)
+}
+
+/// Removes samples from the library along with everything stored about
+/// them. The files themselves are left alone.
+pub fn delete_samples(conn: &Connection, sample_ids: &[isize]) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for &sample_id in sample_ids {
+        // Everything referencing the sample goes with it, except the
+        // full-text index
+        tx.execute(
+            "DELETE FROM samples_fts WHERE rowid = ?1",
+            params![sample_id],
+        )?;
+        tx.execute("DELETE FROM samples WHERE id = ?1", params![sample_id])?;
+    }
+    tx.commit()
+}
+
+/// Queues a sample for analysis again, e.g. after its file changed.
+pub fn reset_analysis(conn: &Connection, sample_id: isize) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE samples SET analysis_version = 0 WHERE id = ?1",
+        params![sample_id],
+    )?;
+    Ok(())
+}
+
+/// Loads the sample stored at `path` or anywhere below it if `path` is a folder.
+pub fn load_samples_under(conn: &Connection, path: &str) -> rusqlite::Result<Vec<Sample>> {
+    let prefix = format!("{}/", path.trim_end_matches('/'));
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM samples WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+        SAMPLE_COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![path, prefix], sample_from_row)?;
+
     let mut samples = Vec::new();
     for row in rows {
         samples.push(row?);
     }
     Ok(samples)
 }
+
+/// Replaces all embedded metadata stored for a sample.
+pub fn replace_sample_metadata(
+    conn: &Connection,
+    sample_id: isize,
+    metadata: &[MetadataTag],
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "DELETE FROM sample_metadata WHERE sample_id = ?1",
+        params![sample_id],
+    )?;
+    let mut stmt = conn.prepare(
+        "INSERT INTO sample_metadata (sample_id, key, value, source) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for tag in metadata {
+        stmt.execute(params![sample_id, tag.key, tag.value, tag.source])?;
+    }
+    Ok(())
+}
+
+pub fn load_sample_metadata(
+    conn: &Connection,
+    sample_id: isize,
+) -> rusqlite::Result<Vec<MetadataTag>> {
+    let mut stmt = conn.prepare(
+        "SELECT key, value, source FROM sample_metadata WHERE sample_id = ?1 ORDER BY id",
+    )?;
+    let rows = stmt.query_map(params![sample_id], |row| {
+        Ok(MetadataTag {
+            key: row.get(0)?,
+            value: row.get(1)?,
+            source: row.get(2)?,
+        })
+    })?;
+
+    let mut metadata = Vec::new();
+    for row in rows {
+        metadata.push(row?);
+    }
+    Ok(metadata)
+}
+
+/// Replaces all cue markers stored for a sample.
+pub fn replace_sample_cues(
+    conn: &Connection,
+    sample_id: isize,
+    cues: &[CueMarker],
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "DELETE FROM sample_cues WHERE sample_id = ?1",
+        params![sample_id],
+    )?;
+    let mut stmt =
+        conn.prepare("INSERT INTO sample_cues (sample_id, position, label) VALUES (?1, ?2, ?3)")?;
+    for cue in cues {
+        stmt.execute(params![sample_id, cue.frame as i64, cue.label])?;
+    }
+    Ok(())
+}
+
+pub fn load_sample_cues(conn: &Connection, sample_id: isize) -> rusqlite::Result<Vec<CueMarker>> {
+    let mut stmt = conn.prepare(
+        "SELECT position, label FROM sample_cues WHERE sample_id = ?1 ORDER BY position",
+    )?;
+    let rows = stmt.query_map(params![sample_id], |row| {
+        Ok(CueMarker {
+            frame: row.get(0)?,
+            label: row.get(1)?,
+        })
+    })?;
+
+    let mut cues = Vec::new();
+    for row in rows {
+        cues.push(row?);
+    }
+    Ok(cues)
+}
+
+/// Loads every tag with the number of samples it is assigned to.
+pub fn load_tags(conn: &Connection) -> rusqlite::Result<Vec<(Tag, usize)>> {
+    let mut stmt = conn.prepare(
+        "SELECT tags.id, tags.name, count(sample_tags.sample_id) FROM tags
+         LEFT JOIN sample_tags ON sample_tags.tag_id = tags.id
+         GROUP BY tags.id ORDER BY tags.name",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        let tag = Tag {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            auto_confidence: None,
+        };
+        Ok((tag, row.get::<_, i64>(2)? as usize))
+    })?;
+
+    let mut tags = Vec::new();
+    for row in rows {
+        tags.push(row?);
+    }
+    Ok(tags)
+}
+
+pub fn load_sample_tags(conn: &Connection, sample_id: isize) -> rusqlite::Result<Vec<Tag>> {
+    let mut stmt = conn.prepare(
+        "SELECT tags.id, tags.name, CASE WHEN sample_tags.auto THEN sample_tags.confidence END
+         FROM sample_tags JOIN tags ON tags.id = sample_tags.tag_id
+         WHERE sample_tags.sample_id = ?1 ORDER BY tags.name",
+    )?;
+    let rows = stmt.query_map(params![sample_id], |row| {
+        Ok(Tag {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            auto_confidence: row.get(2)?,
+        })
+    })?;
+
+    let mut tags = Vec::new();
+    for row in rows {
+        tags.push(row?);
+    }
+    Ok(tags)
+}
+
+/// Assigns the tag called `name` to every sample in `sample_ids`, creating
+/// the tag if needed. Tag names are case-insensitive. Tagging a sample with
+/// its auto-tag by hand keeps the tag when the sample is classified again.
+pub fn add_tag_to_samples(
+    conn: &Connection,
+    name: &str,
+    sample_ids: &[isize],
+) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT OR IGNORE INTO tags (name) VALUES (?1)",
+        params![name],
+    )?;
+    let tag_id: isize = tx.query_row(
+        "SELECT id FROM tags WHERE name = ?1",
+        params![name],
+        |row| row.get(0),
+    )?;
+    for &sample_id in sample_ids {
+        tx.execute(
+            "INSERT INTO sample_tags (sample_id, tag_id) VALUES (?1, ?2)
+             ON CONFLICT DO UPDATE SET auto = 0, confidence = NULL",
+            params![sample_id, tag_id],
+        )?;
+        update_search_index(&tx, sample_id)?;
+    }
+    tx.commit()
+}
+
+pub fn remove_tag_from_samples(
+    conn: &Connection,
+    tag_id: isize,
+    sample_ids: &[isize],
+) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for &sample_id in sample_ids {
+        tx.execute(
+            "DELETE FROM sample_tags WHERE sample_id = ?1 AND tag_id = ?2",
+            params![sample_id, tag_id],
+        )?;
+        update_search_index(&tx, sample_id)?;
+    }
+    tx.commit()
+}
+
+/// Renames a tag. Renaming to the name of another tag merges the two.
+pub fn rename_tag(conn: &Connection, tag_id: isize, new_name: &str) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    let existing: Option<isize> = tx
+        .query_row(
+            "SELECT id FROM tags WHERE name = ?1 AND id != ?2",
+            params![new_name, tag_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    let sample_ids = tagged_sample_ids(&tx, tag_id)?;
+    match existing {
+        Some(target_id) => {
+            // A manual assignment of either tag wins over an auto-tag
+            tx.execute(
+                "INSERT INTO sample_tags (sample_id, tag_id, auto, confidence)
+                 SELECT sample_id, ?2, auto, confidence FROM sample_tags WHERE tag_id = ?1
+                 ON CONFLICT (sample_id, tag_id) DO UPDATE SET
+                     auto = min(auto, excluded.auto),
+                     confidence = CASE WHEN min(auto, excluded.auto) = 0 THEN NULL
+                         ELSE confidence END",
+                params![tag_id, target_id],
+            )?;
+            tx.execute("DELETE FROM sample_tags WHERE tag_id = ?1", params![tag_id])?;
+            tx.execute("DELETE FROM tags WHERE id = ?1", params![tag_id])?;
+        }
+        None => {
+            tx.execute(
+                "UPDATE tags SET name = ?1 WHERE id = ?2",
+                params![new_name, tag_id],
+            )?;
+        }
+    }
+    for sample_id in sample_ids {
+        update_search_index(&tx, sample_id)?;
+    }
+    tx.commit()
+}
+
+pub fn delete_tag(conn: &Connection, tag_id: isize) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    let sample_ids = tagged_sample_ids(&tx, tag_id)?;
+    tx.execute("DELETE FROM sample_tags WHERE tag_id = ?1", params![tag_id])?;
+    tx.execute("DELETE FROM tags WHERE id = ?1", params![tag_id])?;
+    for sample_id in sample_ids {
+        update_search_index(&tx, sample_id)?;
+    }
+    tx.commit()
+}
+
+fn tagged_sample_ids(conn: &Connection, tag_id: isize) -> rusqlite::Result<Vec<isize>> {
+    let mut stmt = conn.prepare("SELECT sample_id FROM sample_tags WHERE tag_id = ?1")?;
+    let rows = stmt.query_map(params![tag_id], |row| row.get(0))?;
+
+    let mut ids = Vec::new();
+    for row in rows {
+        ids.push(row?);
+    }
+    Ok(ids)
+}
+
+/// Loads every collection and folder with the number of samples it shows
+/// when browsed, which leaves out hidden ones.
+pub fn load_collections(conn: &Connection) -> rusqlite::Result<Vec<(Collection, usize)>> {
+    let mut stmt = conn.prepare(
+        "SELECT collections.id, collections.name, collections.parent_id, collections.is_folder,
+             collections.query, count(samples.id)
+         FROM collections
+         LEFT JOIN collection_samples ON collection_samples.collection_id = collections.id
+         LEFT JOIN samples ON samples.id = collection_samples.sample_id AND samples.hidden = 0
+         GROUP BY collections.id ORDER BY collections.is_folder DESC, collections.name",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        let collection = Collection {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            parent_id: row.get(2)?,
+            is_folder: row.get(3)?,
+            query: row.get(4)?,
+        };
+        Ok((collection, row.get::<_, i64>(5)? as usize))
+    })?;
+
+    let mut collections = Vec::new();
+    for row in rows {
+        collections.push(row?);
+    }
+    Ok(collections)
+}
+
+/// Creates a collection, or a folder of collections, and returns its id.
+pub fn create_collection(
+    conn: &Connection,
+    name: &str,
+    parent_id: Option<isize>,
+    is_folder: bool,
+) -> rusqlite::Result<isize> {
+    conn.execute(
+        "INSERT INTO collections (name, parent_id, is_folder) VALUES (?1, ?2, ?3)",
+        params![name, parent_id, is_folder],
+    )?;
+    Ok(conn.last_insert_rowid() as isize)
+}
+
+pub fn rename_collection(
+    conn: &Connection,
+    collection_id: isize,
+    name: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE collections SET name = ?1 WHERE id = ?2",
+        params![name, collection_id],
+    )?;
+    Ok(())
+}
+
+/// Turns a collection into a smart collection with the given search query,
+/// and fills it with the samples currently matching.
+pub fn set_collection_query(
+    conn: &Connection,
+    collection_id: isize,
+    query: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE collections SET query = ?1 WHERE id = ?2",
+        params![query, collection_id],
+    )?;
+    refresh_smart_collections(conn)
+}
+
+/// Re-evaluates every smart collection's query against the library. Their
+/// matches are kept in `collection_samples` like regular collections, so they
+/// browse and count the same way. Hidden samples and files gone from disk
+/// don't match.
+pub fn refresh_smart_collections(conn: &Connection) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    let smart: Vec<(isize, String)> = {
+        let mut stmt = tx.prepare("SELECT id, query FROM collections WHERE query IS NOT NULL")?;
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?
+    };
+
+    for (collection_id, query) in smart {
+        tx.execute(
+            "DELETE FROM collection_samples WHERE collection_id = ?1",
+            params![collection_id],
+        )?;
+        let (clause, mut values) = SearchQuery::parse(&query).to_sql();
+        values.insert(0, Value::Integer(collection_id as i64));
+        tx.execute(
+            &format!(
+                "INSERT INTO collection_samples (collection_id, sample_id, position)
+                 SELECT ?, id, id FROM samples WHERE hidden = 0 AND missing = 0 AND {}",
+                clause
+            ),
+            params_from_iter(values),
+        )?;
+    }
+    tx.commit()
+}
+
+/// Moves a collection or folder into another folder, or to the top level if
+/// `parent_id` is `None`. Moving a folder into itself or its own subfolders
+/// is ignored.
+pub fn move_collection(
+    conn: &Connection,
+    collection_id: isize,
+    parent_id: Option<isize>,
+) -> rusqlite::Result<()> {
+    if let Some(parent_id) = parent_id
+        && collection_subtree(conn, collection_id)?.contains(&parent_id)
+    {
+        return Ok(());
+    }
+    conn.execute(
+        "UPDATE collections SET parent_id = ?1 WHERE id = ?2",
+        params![parent_id, collection_id],
+    )?;
+    Ok(())
+}
+
+/// Deletes a collection, or a folder with everything in it. The samples
+/// themselves stay in the library.
+pub fn delete_collection(conn: &Connection, collection_id: isize) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for id in collection_subtree(&tx, collection_id)? {
+        tx.execute(
+            "DELETE FROM collection_samples WHERE collection_id = ?1",
+            params![id],
+        )?;
+        tx.execute("DELETE FROM collections WHERE id = ?1", params![id])?;
+    }
+    tx.commit()
+}
+
+/// Returns the collection and all collections nested below it.
+fn collection_subtree(conn: &Connection, collection_id: isize) -> rusqlite::Result<Vec<isize>> {
+    let mut stmt = conn.prepare(
+        "WITH RECURSIVE subtree(id) AS (
+             SELECT ?1
+             UNION SELECT collections.id FROM collections
+             JOIN subtree ON collections.parent_id = subtree.id
+         )
+         SELECT id FROM subtree",
+    )?;
+    let rows = stmt.query_map(params![collection_id], |row| row.get(0))?;
+
+    let mut ids = Vec::new();
+    for row in rows {
+        ids.push(row?);
+    }
+    Ok(ids)
+}
+
+/// Appends samples to the end of a collection, skipping ones already in it.
+pub fn add_samples_to_collection(
+    conn: &Connection,
+    collection_id: isize,
+    sample_ids: &[isize],
+) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for &sample_id in sample_ids {
+        tx.execute(
+            "INSERT OR IGNORE INTO collection_samples (collection_id, sample_id, position)
+             SELECT ?1, ?2, coalesce(max(position) + 1, 0) FROM collection_samples
+             WHERE collection_id = ?1",
+            params![collection_id, sample_id],
+        )?;
+    }
+    tx.commit()
+}
+
+pub fn remove_samples_from_collection(
+    conn: &Connection,
+    collection_id: isize,
+    sample_ids: &[isize],
+) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    for &sample_id in sample_ids {
+        tx.execute(
+            "DELETE FROM collection_samples WHERE collection_id = ?1 AND sample_id = ?2",
+            params![collection_id, sample_id],
+        )?;
+    }
+    tx.commit()
+}
+
+/// Swaps the positions of two samples within a collection.
+pub fn swap_collection_positions(
+    conn: &Connection,
+    collection_id: isize,
+    first_id: isize,
+    second_id: isize,
+) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    let position = |sample_id: isize| -> rusqlite::Result<i64> {
+        tx.query_row(
+            "SELECT position FROM collection_samples WHERE collection_id = ?1 AND sample_id = ?2",
+            params![collection_id, sample_id],
+            |row| row.get(0),
+        )
+    };
+    let (first_position, second_position) = (position(first_id)?, position(second_id)?);
+    for (sample_id, new_position) in [(first_id, second_position), (second_id, first_position)] {
+        tx.execute(
+            "UPDATE collection_samples SET position = ?1 WHERE collection_id = ?2 AND sample_id = ?3",
+            params![new_position, collection_id, sample_id],
+        )?;
+    }
+    tx.commit()
+}
+
+pub fn insert_library_root(
+    conn: &Connection,
+    path: &str,
+    follow_symlinks: bool,
+    ignore_patterns: &[String],
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO library_roots (path, follow_symlinks, ignore_patterns)
+         VALUES (?1, ?2, ?3)",
+        params![path, follow_symlinks, ignore_patterns.join("\n")],
+    )?;
+    Ok(())
+}
+
+pub fn update_library_root(conn: &Connection, root: &LibraryRoot) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE library_roots SET follow_symlinks = ?1, ignore_patterns = ?2 WHERE id = ?3",
+        params![
+            root.follow_symlinks,
+            root.ignore_patterns.join("\n"),
+            root.id
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn delete_library_root(conn: &Connection, root_id: isize) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    // Everything referencing the samples goes with them, except the
+    // full-text index
+    tx.execute(
+        "DELETE FROM samples_fts WHERE rowid IN (SELECT id FROM samples WHERE root_id = ?1)",
+        params![root_id],
+    )?;
+    tx.execute("DELETE FROM samples WHERE root_id = ?1", params![root_id])?;
+    tx.execute("DELETE FROM library_roots WHERE id = ?1", params![root_id])?;
+    tx.commit()
+}
+
+pub fn load_library_roots(conn: &Connection) -> rusqlite::Result<Vec<LibraryRoot>> {
+    let mut stmt =
+        conn.prepare("SELECT id, path, follow_symlinks, ignore_patterns FROM library_roots")?;
+    let rows = stmt.query_map([], |row| {
+        let ignore_patterns: String = row.get(3)?;
+        Ok(LibraryRoot {
+            id: row.get(0)?,
+            path: row.get(1)?,
+            follow_symlinks: row.get(2)?,
+            ignore_patterns: ignore_patterns
+                .lines()
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(String::from)
+                .collect(),
+        })
+    })?;
+
+    let mut roots = Vec::new();
+    for row in rows {
+        roots.push(row?);
+    }
+    Ok(roots)
+}
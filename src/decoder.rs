@@ -0,0 +1,676 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rtrb::Producer;
+use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Channels, Signal};
+use symphonia::core::codecs::{CODEC_TYPE_NULL, Decoder, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::sample::Sample;
+use symphonia::default::{get_codecs, get_probe};
+
+use crate::audio_player::AudioPlayerError;
+use crate::resample::{ResampleQuality, Resampler};
+
+/// How long the decoder thread sleeps while the buffer is full or while it
+/// waits for the audio callback.
+const WAIT: Duration = Duration::from_millis(2);
+/// Points in the waveform overview.
+const OVERVIEW_POINTS: usize = 2000;
+/// Frames per overview point while a file's length is unknown; they're
+/// merged down to `OVERVIEW_POINTS` once it's known.
+const OVERVIEW_BLOCK: u64 = 1024;
+/// How often the overview is published while it's being built, in packets.
+const OVERVIEW_PUBLISH_PACKETS: usize = 64;
+/// The overview pass decodes this much of a file at full speed. Past it, it
+/// throttles itself so long files don't compete with playback, which fills
+/// in the overview for what it plays in the meantime.
+const OVERVIEW_HEAD_SECONDS: u64 = 60;
+
+/// A file opened for decoding its first audio track.
+pub struct OpenTrack {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    pub sample_rate: Option<u32>,
+    /// Length in frames, if the container says
+    pub frames: Option<u64>,
+    pub channels: Option<Channels>,
+}
+
+pub fn open_track(path: &Path) -> Result<OpenTrack, AudioPlayerError> {
+    let file = File::open(path)?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    // Probe the file format
+    let probed = get_probe()
+        .format(
+            &Default::default(),
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|e| AudioPlayerError::SymphoniaError(Box::new(e)))?;
+    let format = probed.format;
+
+    // Find the first audio track
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| {
+            AudioPlayerError::UnsupportedFormat("No supported audio tracks found".to_string())
+        })?;
+
+    let decoder = get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| AudioPlayerError::SymphoniaError(Box::new(e)))?;
+
+    Ok(OpenTrack {
+        track_id: track.id,
+        sample_rate: track.codec_params.sample_rate,
+        frames: track.codec_params.n_frames,
+        channels: track.codec_params.channels,
+        format,
+        decoder,
+    })
+}
+
+/// Where a run of contiguous audio starts in the buffer: from device frame
+/// `output_frame` of the stream on, playback is at `source_frame` of the file.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    output_frame: u64,
+    source_frame: u64,
+}
+
+/// What the player, the decoder thread and the audio callback share about
+/// the file being played.
+#[derive(Default)]
+pub struct StreamControl {
+    pub loop_enabled: AtomicBool,
+    /// Loop start and end in frames of the file; an end of 0 loops the whole
+    /// file
+    pub loop_start: AtomicU64,
+    pub loop_end: AtomicU64,
+    /// Length of the file in frames, 0 until known
+    pub total_frames: AtomicU64,
+    /// Device frames the audio callback has taken from the buffer, including
+    /// those it threw away when flushing
+    pub frames_read: AtomicU64,
+    /// The decoder bumps `flush_requested` to have the callback empty the
+    /// buffer, and the callback copies it to `flushed` once it has
+    pub flush_requested: AtomicU64,
+    pub flushed: AtomicU64,
+    /// Set once the last frame of the file is in the buffer
+    pub finished: AtomicBool,
+    /// Set by the callback once it has played the last frame
+    pub ended: AtomicBool,
+    segments: Mutex<Vec<Segment>>,
+}
+
+impl StreamControl {
+    /// The frame of the file being heard, given the file's and the device's
+    /// sample rates.
+    pub fn position(&self, source_rate: u32, device_rate: u32) -> u64 {
+        let read = self.frames_read.load(Ordering::Relaxed);
+        let mut segments = self.segments.lock().unwrap();
+        let Some(index) = segments.iter().rposition(|s| s.output_frame <= read) else {
+            return segments.first().map_or(0, |s| s.source_frame);
+        };
+        // Earlier runs have been played out
+        segments.drain(..index);
+        let segment = segments[0];
+        let elapsed =
+            (read - segment.output_frame) as f64 * source_rate as f64 / device_rate as f64;
+        let frame = segment.source_frame + elapsed as u64;
+        match self.total_frames.load(Ordering::Relaxed) {
+            0 => frame,
+            total => frame.min(total),
+        }
+    }
+
+    fn loop_end(&self) -> u64 {
+        match self.loop_end.load(Ordering::Relaxed) {
+            0 => u64::MAX,
+            end => end,
+        }
+    }
+}
+
+/// Decodes the loaded file on a background thread into the buffer the audio
+/// callback plays from, and builds its waveform overview on another.
+pub struct StreamDecoder {
+    seeks: Sender<u64>,
+    control: Arc<StreamControl>,
+    cancel: Arc<AtomicBool>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl StreamDecoder {
+    /// Starts decoding `track`, which must know its channels, at the start
+    /// of the file.
+    pub fn spawn(
+        path: &Path,
+        track: OpenTrack,
+        device_rate: u32,
+        quality: ResampleQuality,
+        producer: Producer<f32>,
+        control: Arc<StreamControl>,
+        peaks: Arc<Mutex<Vec<(f32, f32)>>>,
+    ) -> Self {
+        let (seeks, seek_receiver) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let source_rate = track.sample_rate.unwrap_or(device_rate);
+        let channels = track.channels.map_or(1, |channels| channels.count());
+        let (track_frames, track_sample_rate) = (track.frames, track.sample_rate);
+        // Both threads widen the same points, so they're laid out up front
+        if let Some(frames) = track_frames {
+            let count = frames.div_ceil(overview_block(frames)) as usize;
+            *peaks.lock().unwrap() = vec![(0.0, 0.0); count];
+        }
+
+        let decode = DecodeLoop {
+            track,
+            channels,
+            resampler: Resampler::new(channels, source_rate, device_rate, quality),
+            source_rate,
+            device_rate,
+            quality,
+            producer,
+            control: Arc::clone(&control),
+            seeks: seek_receiver,
+            pending_seek: None,
+            cancel: Arc::clone(&cancel),
+            pushed: 0,
+            resampler_start: 0,
+            skip_to: 0,
+            position: 0,
+            decoded: Vec::new(),
+            resampled: Vec::new(),
+            peaks: Arc::clone(&peaks),
+            overview_block: track_frames.map(overview_block),
+            overview_from: overview_head(track_sample_rate),
+        };
+        let decode_control = Arc::clone(&control);
+        let decode_handle = thread::spawn(move || decode.run());
+
+        let path = path.to_path_buf();
+        let cancel_overview = Arc::clone(&cancel);
+        let overview_handle =
+            thread::spawn(move || build_overview(&path, &cancel_overview, &peaks, &control));
+
+        Self {
+            seeks,
+            control: decode_control,
+            cancel,
+            handles: vec![decode_handle, overview_handle],
+        }
+    }
+
+    /// Moves playback to `frame` of the file, dropping what's buffered.
+    pub fn seek(&self, frame: u64) {
+        // Playback isn't over while the decoder catches up with the seek
+        self.control.finished.store(false, Ordering::Relaxed);
+        self.control.ended.store(false, Ordering::Relaxed);
+        let _ = self.seeks.send(frame);
+    }
+}
+
+impl Drop for StreamDecoder {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+struct DecodeLoop {
+    track: OpenTrack,
+    channels: usize,
+    resampler: Resampler,
+    source_rate: u32,
+    device_rate: u32,
+    quality: ResampleQuality,
+    producer: Producer<f32>,
+    control: Arc<StreamControl>,
+    seeks: Receiver<u64>,
+    pending_seek: Option<u64>,
+    cancel: Arc<AtomicBool>,
+    /// Device frames pushed into the buffer so far
+    pushed: u64,
+    /// `pushed` when the resampler was last reset
+    resampler_start: u64,
+    /// Frames before this one are decoded but dropped, after a seek
+    skip_to: u64,
+    /// The file frame after the last one decoded
+    position: u64,
+    decoded: Vec<f32>,
+    resampled: Vec<f32>,
+    /// Waveform overview and frames per point, fed from playback past the
+    /// frames the overview pass decodes at full speed; `None` if the length
+    /// is unknown
+    peaks: Arc<Mutex<Vec<(f32, f32)>>>,
+    overview_block: Option<u64>,
+    overview_from: u64,
+}
+
+impl DecodeLoop {
+    fn run(mut self) {
+        self.control.segments.lock().unwrap().push(Segment {
+            output_frame: 0,
+            source_frame: 0,
+        });
+
+        while !self.cancel.load(Ordering::Relaxed) {
+            if let Some(frame) = self.take_seek() {
+                self.seek(frame);
+                continue;
+            }
+            if self.control.finished.load(Ordering::Relaxed) {
+                // Nothing left to decode until playback is moved
+                thread::sleep(WAIT);
+                continue;
+            }
+
+            let packet = match self.track.format.next_packet() {
+                Ok(packet) => packet,
+                // End of stream
+                Err(SymphoniaError::IoError(_)) => {
+                    self.end_of_file();
+                    continue;
+                }
+                Err(error) => {
+                    println!("Error: {}", error);
+                    self.finish();
+                    continue;
+                }
+            };
+            if packet.track_id() != self.track.track_id {
+                continue;
+            }
+
+            let decoded = match self.track.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A corrupt packet only costs us that packet
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(error) => {
+                    println!("Error: {}", error);
+                    self.finish();
+                    continue;
+                }
+            };
+            if decoded.spec().channels.count() != self.channels {
+                let error = AudioPlayerError::DecodingError(
+                    "Channel count changes during the file".to_string(),
+                );
+                println!("Error: {}", error);
+                self.finish();
+                continue;
+            }
+
+            self.decoded.clear();
+            if let Err(error) = interleave(decoded, &mut self.decoded) {
+                println!("Error: {}", error);
+                self.finish();
+                continue;
+            }
+            self.play_packet(packet.ts());
+        }
+    }
+
+    /// Queues the decoded packet starting at file frame `start`, cut to the
+    /// loop and to where a seek wanted playback to begin.
+    fn play_packet(&mut self, start: u64) {
+        let frames = (self.decoded.len() / self.channels) as u64;
+        let end = start + frames;
+        self.position = end;
+
+        if let Some(block) = self.overview_block
+            && end > self.overview_from
+        {
+            let mut peaks = self.peaks.lock().unwrap();
+            add_to_overview(&mut peaks, block, self.channels, start, &self.decoded);
+        }
+
+        let loop_end = self.control.loop_end();
+        let wraps = self.control.loop_enabled.load(Ordering::Relaxed)
+            && start < loop_end
+            && end >= loop_end;
+        let from = self.skip_to.clamp(start, end);
+        let to = if wraps { loop_end } else { end };
+        if from < to {
+            let range =
+                (from - start) as usize * self.channels..(to - start) as usize * self.channels;
+            self.resampled.clear();
+            let decoded = std::mem::take(&mut self.decoded);
+            self.resampler.process(&decoded[range], &mut self.resampled);
+            self.decoded = decoded;
+            self.push_resampled();
+        }
+
+        if wraps {
+            self.wrap();
+        }
+    }
+
+    fn end_of_file(&mut self) {
+        let _ = self.control.total_frames.compare_exchange(
+            0,
+            self.position,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+        if self.control.loop_enabled.load(Ordering::Relaxed) && self.position > 0 {
+            self.wrap();
+        } else {
+            self.finish();
+        }
+    }
+
+    /// Flushes the resampler and marks the file as fully buffered, unless a
+    /// seek is about to move playback elsewhere anyway.
+    fn finish(&mut self) {
+        if self.has_pending_seek() {
+            return;
+        }
+        self.resampled.clear();
+        self.resampler.finish(&mut self.resampled);
+        self.push_resampled();
+        self.control.finished.store(true, Ordering::Relaxed);
+    }
+
+    /// Jumps back to the loop start without a gap: the buffer is kept and the
+    /// resampler carries on across the splice.
+    fn wrap(&mut self) {
+        let start = self.control.loop_start.load(Ordering::Relaxed);
+        let output_frame = self.resampler_start + self.resampler.next_output_frame();
+        if self.seek_track(start) {
+            self.control.segments.lock().unwrap().push(Segment {
+                output_frame,
+                source_frame: start,
+            });
+        } else {
+            self.finish();
+        }
+    }
+
+    /// Moves playback to `frame`: has the audio callback drop what's
+    /// buffered, then starts decoding there.
+    fn seek(&mut self, frame: u64) {
+        let request = self.control.flush_requested.fetch_add(1, Ordering::Relaxed) + 1;
+        while self.control.flushed.load(Ordering::Acquire) < request {
+            if self.cancel.load(Ordering::Relaxed) {
+                return;
+            }
+            thread::sleep(WAIT);
+        }
+
+        // The buffer is empty, and everything pushed has been counted as read
+        self.resampler = Resampler::new(
+            self.channels,
+            self.source_rate,
+            self.device_rate,
+            self.quality,
+        );
+        self.resampler_start = self.pushed;
+        self.control.ended.store(false, Ordering::Relaxed);
+        self.control.finished.store(false, Ordering::Relaxed);
+        *self.control.segments.lock().unwrap() = vec![Segment {
+            output_frame: self.pushed,
+            source_frame: frame,
+        }];
+        if !self.seek_track(frame) {
+            self.finish();
+        }
+    }
+
+    fn seek_track(&mut self, frame: u64) -> bool {
+        let seek_to = SeekTo::TimeStamp {
+            ts: frame,
+            track_id: self.track.track_id,
+        };
+        match self.track.format.seek(SeekMode::Accurate, seek_to) {
+            Ok(seeked) => {
+                self.track.decoder.reset();
+                self.skip_to = seeked.required_ts;
+                self.position = seeked.actual_ts;
+                true
+            }
+            Err(error) => {
+                println!("Error: {}", error);
+                false
+            }
+        }
+    }
+
+    /// Pushes the resampled frames into the buffer as room frees up. Gives up
+    /// on them if a seek comes in meanwhile, as they'd be dropped anyway.
+    fn push_resampled(&mut self) {
+        let mut written = 0;
+        while written < self.resampled.len() {
+            if self.cancel.load(Ordering::Relaxed) || self.has_pending_seek() {
+                return;
+            }
+            let room = self.producer.slots() / self.channels * self.channels;
+            if room == 0 {
+                thread::sleep(WAIT);
+                continue;
+            }
+            let remaining = &self.resampled[written..];
+            let count = room.min(remaining.len());
+            if let Ok(mut chunk) = self.producer.write_chunk(count) {
+                let (first, second) = chunk.as_mut_slices();
+                first.copy_from_slice(&remaining[..first.len()]);
+                second.copy_from_slice(&remaining[first.len()..count]);
+                chunk.commit_all();
+            }
+            self.pushed += (count / self.channels) as u64;
+            written += count;
+        }
+    }
+
+    fn has_pending_seek(&mut self) -> bool {
+        if let Some(frame) = self.seeks.try_iter().last() {
+            self.pending_seek = Some(frame);
+        }
+        self.pending_seek.is_some()
+    }
+
+    /// The latest seek asked for, skipping any it overtook.
+    fn take_seek(&mut self) -> Option<u64> {
+        self.has_pending_seek();
+        self.pending_seek.take()
+    }
+}
+
+/// Decodes the file once more into a coarse min/max overview for the
+/// waveform, publishing it as it goes when the file's length is known and
+/// once it's done otherwise. Past the first `OVERVIEW_HEAD_SECONDS` it spends
+/// at most half its time decoding.
+fn build_overview(
+    path: &Path,
+    cancel: &AtomicBool,
+    peaks: &Mutex<Vec<(f32, f32)>>,
+    control: &StreamControl,
+) {
+    let mut track = match open_track(path) {
+        Ok(track) => track,
+        Err(error) => {
+            println!("Error: {}", error);
+            return;
+        }
+    };
+
+    let block = match track.frames {
+        Some(frames) => overview_block(frames),
+        None => OVERVIEW_BLOCK,
+    };
+    let mut points: Vec<(f32, f32)> = match track.frames {
+        Some(frames) => vec![(0.0, 0.0); frames.div_ceil(block) as usize],
+        None => Vec::new(),
+    };
+    let head_frames = overview_head(track.sample_rate);
+    let mut decoded = Vec::new();
+    let mut frame = 0u64;
+    let mut packets = 0;
+    let mut batch_started = Instant::now();
+
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return;
+        }
+        let packet = match track.format.next_packet() {
+            Ok(packet) => packet,
+            Err(_) => break,
+        };
+        if packet.track_id() != track.track_id {
+            continue;
+        }
+        let buffer = match track.decoder.decode(&packet) {
+            Ok(buffer) => buffer,
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(_) => break,
+        };
+        let channels = buffer.spec().channels.count().max(1);
+        decoded.clear();
+        if interleave(buffer, &mut decoded).is_err() {
+            break;
+        }
+
+        if track.frames.is_none() {
+            let end = frame + (decoded.len() / channels) as u64;
+            points.resize(end.div_ceil(block) as usize, (0.0, 0.0));
+        }
+        add_to_overview(&mut points, block, channels, frame, &decoded);
+        frame += (decoded.len() / channels) as u64;
+
+        packets += 1;
+        if packets % OVERVIEW_PUBLISH_PACKETS == 0 {
+            if track.frames.is_some() {
+                merge_overview(&mut peaks.lock().unwrap(), &points);
+            }
+            if frame > head_frames {
+                thread::sleep(batch_started.elapsed());
+            }
+            batch_started = Instant::now();
+        }
+    }
+
+    if track.frames.is_some() {
+        merge_overview(&mut peaks.lock().unwrap(), &points);
+        return;
+    }
+
+    let _ = control
+        .total_frames
+        .compare_exchange(0, frame, Ordering::Relaxed, Ordering::Relaxed);
+    if points.len() > OVERVIEW_POINTS {
+        let group = points.len().div_ceil(OVERVIEW_POINTS);
+        points = points
+            .chunks(group)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .fold((0.0f32, 0.0f32), |(min, max), &(lo, hi)| {
+                        (min.min(lo), max.max(hi))
+                    })
+            })
+            .collect();
+    }
+    *peaks.lock().unwrap() = points;
+}
+
+/// Frames the overview pass decodes at full speed.
+fn overview_head(sample_rate: Option<u32>) -> u64 {
+    sample_rate.unwrap_or(48_000) as u64 * OVERVIEW_HEAD_SECONDS
+}
+
+/// Frames per overview point for a file `frames` long.
+fn overview_block(frames: u64) -> u64 {
+    frames.div_ceil(OVERVIEW_POINTS as u64).max(1)
+}
+
+/// Widens the overview `points`, one per `block` frames, to take in the
+/// interleaved `samples` starting at frame `start`.
+fn add_to_overview(
+    points: &mut [(f32, f32)],
+    block: u64,
+    channels: usize,
+    start: u64,
+    samples: &[f32],
+) {
+    for (frame, samples) in (start..).zip(samples.chunks_exact(channels)) {
+        let Some(point) = points.get_mut((frame / block) as usize) else {
+            break;
+        };
+        for &sample in samples {
+            point.0 = point.0.min(sample);
+            point.1 = point.1.max(sample);
+        }
+    }
+}
+
+/// Widens the overview `into` to take in `points` covering the same frames.
+fn merge_overview(into: &mut Vec<(f32, f32)>, points: &[(f32, f32)]) {
+    into.resize(points.len().max(into.len()), (0.0, 0.0));
+    for (into, point) in into.iter_mut().zip(points) {
+        into.0 = into.0.min(point.0);
+        into.1 = into.1.max(point.1);
+    }
+}
+
+/// Appends every channel of a decoded buffer to `output`, interleaved.
+pub fn interleave(decoded: AudioBufferRef, output: &mut Vec<f32>) -> Result<(), AudioPlayerError> {
+    match decoded {
+        AudioBufferRef::F32(buf) => interleave_with(&buf, |s| s, output),
+        AudioBufferRef::F64(buf) => interleave_with(&buf, |s| s as f32, output),
+        AudioBufferRef::S16(buf) => interleave_with(&buf, |s| s as f32 / i16::MAX as f32, output),
+        AudioBufferRef::S32(buf) => interleave_with(&buf, |s| s as f32 / i32::MAX as f32, output),
+        AudioBufferRef::S24(buf) => {
+            // Fixed S24 normalization
+            interleave_with(
+                &buf,
+                |s| {
+                    let i32_val = s.inner();
+                    // Proper S24 normalization: signed 24-bit has range [-2^23, 2^23-1]
+                    if i32_val >= 0 {
+                        i32_val as f32 / 8_388_607.0 // 2^23 - 1
+                    } else {
+                        i32_val as f32 / 8_388_608.0 // 2^23
+                    }
+                },
+                output,
+            )
+        }
+        AudioBufferRef::U8(buf) => interleave_with(&buf, |s| (s as f32 - 128.0) / 128.0, output),
+        _ => {
+            return Err(AudioPlayerError::UnsupportedFormat(
+                "Unsupported audio buffer format".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn interleave_with<S: Sample>(
+    buf: &AudioBuffer<S>,
+    convert: impl Fn(S) -> f32,
+    output: &mut Vec<f32>,
+) {
+    let channels = buf.spec().channels.count();
+    output.reserve(buf.frames() * channels);
+    for i in 0..buf.frames() {
+        for channel in 0..channels {
+            output.push(convert(buf.chan(channel)[i]));
+        }
+    }
+}
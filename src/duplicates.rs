@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+
+use rustfft::num_complex::Complex;
+
+use crate::analysis::DecodedAudio;
+use crate::spectrum::{Stft, frame_starts};
+
+/// Fingerprint frame length and hop, in seconds, so files at different
+/// sample rates line up.
+const FRAME_SECONDS: f32 = 0.05;
+const HOP_SECONDS: f32 = 0.025;
+/// Only the start of long files is fingerprinted.
+const MAX_SECONDS: f32 = 30.0;
+/// Frequency bands compared to each other, spaced evenly on a log scale
+/// between these frequencies in Hz.
+const BANDS: usize = 16;
+const MIN_FREQUENCY: f32 = 50.0;
+const MAX_FREQUENCY: f32 = 8000.0;
+/// Leading and trailing frames this much quieter than the loudest (-40 dB)
+/// are trimmed, so padding with silence doesn't hide a duplicate. Band
+/// energies are floored at the same level, so dither and encoder noise in
+/// quiet passages don't flip bits.
+const SILENCE: f32 = 1e-4;
+/// How far fingerprints are shifted against each other to line them up, in
+/// frames.
+const MAX_OFFSET: isize = 4;
+/// Fingerprints whose lengths differ by more than this share can't match.
+const MAX_LENGTH_DIFFERENCE: f32 = 0.1;
+/// Share of fingerprint bits two near-duplicates have in common at least.
+/// Unrelated audio agrees on about half.
+const MIN_SIMILARITY: f32 = 0.9;
+/// Coarse bands can't tell a kick from the same kick tuned a little higher,
+/// so pitched samples must also be this close in detected pitch, in
+/// semitones.
+const MAX_PITCH_DIFFERENCE: f64 = 0.35;
+
+/// Hashes decoded audio with 64-bit FNV-1a, so the same recording matches
+/// whatever container, encoding or tags it is stored with. `frames` is the
+/// file's full length, which covers any audio past what was decoded.
+pub fn audio_hash(audio: &DecodedAudio, frames: Option<u64>) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut feed = |bytes: &[u8]| {
+        for &byte in bytes {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    };
+
+    feed(&audio.sample_rate.to_le_bytes());
+    feed(&(audio.channels.len() as u32).to_le_bytes());
+    feed(&frames.unwrap_or(0).to_le_bytes());
+    let length = audio.channels.iter().map(Vec::len).min().unwrap_or(0);
+    for i in 0..length {
+        for channel in &audio.channels {
+            feed(&channel[i].to_bits().to_le_bytes());
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+/// Computes an audio fingerprint of mono audio: one word per frame, whose
+/// bits tell which bands got louder since the previous frame and which bands
+/// are louder than the next one up. Both survive gain changes and lossy
+/// encoding. `None` for silence.
+pub fn fingerprint(samples: &[f32], sample_rate: u32) -> Option<Vec<u32>> {
+    if sample_rate == 0 {
+        return None;
+    }
+    let rate = sample_rate as f32;
+    let frame_size = (rate * FRAME_SECONDS) as usize;
+    let hop = ((rate * HOP_SECONDS) as usize).max(1);
+    let samples = &samples[..samples.len().min((rate * MAX_SECONDS) as usize)];
+    if frame_size == 0 || samples.is_empty() {
+        return None;
+    }
+
+    let mut stft = Stft::new(frame_size);
+    let edges: Vec<usize> = (0..=BANDS)
+        .map(|i| {
+            let frequency =
+                MIN_FREQUENCY * (MAX_FREQUENCY / MIN_FREQUENCY).powf(i as f32 / BANDS as f32);
+            ((frequency * frame_size as f32 / rate) as usize).min(frame_size / 2)
+        })
+        .collect();
+
+    let mut frames: Vec<[f32; BANDS]> = Vec::new();
+    for start in frame_starts(samples, hop) {
+        let spectrum = stft.spectrum(samples, start);
+        let mut bands = [0.0f32; BANDS];
+        for (band, energy) in bands.iter_mut().enumerate() {
+            *energy = spectrum[edges[band]..edges[band + 1].max(edges[band] + 1)]
+                .iter()
+                .map(Complex::norm_sqr)
+                .sum();
+        }
+        frames.push(bands);
+    }
+
+    let energy = |bands: &[f32; BANDS]| bands.iter().sum::<f32>();
+    let loudest = frames.iter().map(energy).fold(0.0, f32::max);
+    if loudest <= 1e-9 {
+        return None;
+    }
+    let first = frames.iter().position(|f| energy(f) >= loudest * SILENCE)?;
+    let last = frames
+        .iter()
+        .rposition(|f| energy(f) >= loudest * SILENCE)?;
+
+    let floor = loudest * SILENCE / BANDS as f32;
+    let mut previous = [floor; BANDS];
+    let mut words = Vec::with_capacity(last - first + 1);
+    for bands in &frames[first..=last] {
+        let bands = bands.map(|e| e.max(floor));
+        let mut word = 0u32;
+        for band in 0..BANDS {
+            if bands[band] > previous[band] {
+                word |= 1 << band;
+            }
+            if band + 1 < BANDS && bands[band] > bands[band + 1] {
+                word |= 1 << (BANDS + band);
+            }
+        }
+        words.push(word);
+        previous = bands;
+    }
+    Some(words)
+}
+
+/// Share of bits two fingerprints have in common where they line up best,
+/// from 0 to 1. Fingerprints of clearly different lengths score 0.
+pub fn fingerprint_similarity(a: &[u32], b: &[u32]) -> f32 {
+    let longest = a.len().max(b.len());
+    if longest == 0 || lengths_differ(a.len(), b.len()) {
+        return 0.0;
+    }
+
+    let bits = (2 * BANDS - 1) as f32;
+    let mut best = 0.0f32;
+    for offset in -MAX_OFFSET..=MAX_OFFSET {
+        let (a, b) = if offset >= 0 {
+            (a, b.get(offset as usize..).unwrap_or_default())
+        } else {
+            (a.get(offset.unsigned_abs()..).unwrap_or_default(), b)
+        };
+        let overlap = a.len().min(b.len());
+        if overlap == 0 {
+            continue;
+        }
+        let differing: u32 = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum();
+        // Frames left over at either end count as mismatches
+        let similarity = 1.0 - differing as f32 / (overlap as f32 * bits);
+        best = best.max(similarity * overlap as f32 / longest as f32);
+    }
+    best
+}
+
+fn lengths_differ(a: usize, b: usize) -> bool {
+    let (short, long) = (a.min(b), a.max(b));
+    long - short > 2 && (long - short) as f32 > long as f32 * MAX_LENGTH_DIFFERENCE
+}
+
+/// What duplicate detection knows about one sample.
+pub struct DuplicateCandidate {
+    pub sample_id: isize,
+    pub audio_hash: Option<String>,
+    pub fingerprint: Vec<u32>,
+    /// Detected pitch in Hz
+    pub pitch: Option<f64>,
+}
+
+/// Samples that are copies of one another.
+#[derive(Debug, Clone)]
+pub struct DuplicateSet {
+    pub sample_ids: Vec<isize>,
+    /// Whether every sample decodes to exactly the same audio
+    pub exact: bool,
+    /// Fingerprint similarity of the least alike pair that joined the set,
+    /// 1 for exact duplicates
+    pub similarity: f32,
+}
+
+/// Groups samples with identical audio or near-identical fingerprints, most
+/// alike sets first. Samples are only compared to samples of about the same
+/// length, which keeps this quick on large libraries.
+pub fn group_duplicates(candidates: &[DuplicateCandidate]) -> Vec<DuplicateSet> {
+    let mut links: Vec<(usize, usize, f32)> = Vec::new();
+
+    let mut by_hash: HashMap<&str, usize> = HashMap::new();
+    for (i, candidate) in candidates.iter().enumerate() {
+        if let Some(hash) = &candidate.audio_hash {
+            match by_hash.get(hash.as_str()) {
+                Some(&first) => links.push((first, i, 1.0)),
+                None => {
+                    by_hash.insert(hash, i);
+                }
+            }
+        }
+    }
+
+    let mut by_length: Vec<usize> = (0..candidates.len())
+        .filter(|&i| !candidates[i].fingerprint.is_empty())
+        .collect();
+    by_length.sort_by_key(|&i| candidates[i].fingerprint.len());
+    for (position, &i) in by_length.iter().enumerate() {
+        let a = &candidates[i];
+        for &j in &by_length[position + 1..] {
+            let b = &candidates[j];
+            if lengths_differ(a.fingerprint.len(), b.fingerprint.len()) {
+                break;
+            }
+            if a.audio_hash.is_some() && a.audio_hash == b.audio_hash {
+                continue;
+            }
+            if let (Some(x), Some(y)) = (a.pitch, b.pitch)
+                && (12.0 * (x / y).log2()).abs() > MAX_PITCH_DIFFERENCE
+            {
+                continue;
+            }
+            let similarity = fingerprint_similarity(&a.fingerprint, &b.fingerprint);
+            if similarity >= MIN_SIMILARITY {
+                links.push((i, j, similarity));
+            }
+        }
+    }
+
+    // Linked samples end up in one set, even if not every pair matched
+    let mut parent: Vec<usize> = (0..candidates.len()).collect();
+    for &(a, b, _) in &links {
+        let (a, b) = (find_root(&mut parent, a), find_root(&mut parent, b));
+        parent[b] = a;
+    }
+    let mut sets: HashMap<usize, (Vec<usize>, f32)> = HashMap::new();
+    for &(a, _, similarity) in &links {
+        let set = sets
+            .entry(find_root(&mut parent, a))
+            .or_insert((Vec::new(), 1.0));
+        set.1 = set.1.min(similarity);
+    }
+    for i in 0..candidates.len() {
+        if let Some(set) = sets.get_mut(&find_root(&mut parent, i)) {
+            set.0.push(i);
+        }
+    }
+
+    let mut sets: Vec<DuplicateSet> = sets
+        .into_values()
+        .map(|(members, similarity)| {
+            let hash = &candidates[members[0]].audio_hash;
+            DuplicateSet {
+                exact: hash.is_some() && members.iter().all(|&i| candidates[i].audio_hash == *hash),
+                sample_ids: members.iter().map(|&i| candidates[i].sample_id).collect(),
+                similarity,
+            }
+        })
+        .collect();
+    sets.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+    sets
+}
+
+fn find_root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+
+    /// Deterministic pseudo-random fingerprint words.
+    fn words(seed: u32, count: usize) -> Vec<u32> {
+        let mut state = seed;
+        (0..count)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                state >> 1
+            })
+            .collect()
+    }
+
+    fn candidate(
+        sample_id: isize,
+        hash: Option<&str>,
+        fingerprint: Vec<u32>,
+    ) -> DuplicateCandidate {
+        DuplicateCandidate {
+            sample_id,
+            audio_hash: hash.map(str::to_string),
+            fingerprint,
+            pitch: None,
+        }
+    }
+
+    fn sorted_sets(sets: &[DuplicateSet]) -> Vec<(Vec<isize>, bool)> {
+        let mut sets: Vec<_> = sets
+            .iter()
+            .map(|set| {
+                let mut ids = set.sample_ids.clone();
+                ids.sort();
+                (ids, set.exact)
+            })
+            .collect();
+        sets.sort();
+        sets
+    }
+
+    #[test]
+    fn scores_fingerprint_similarity() {
+        let a = words(1, 200);
+        assert_eq!(fingerprint_similarity(&a, &a), 1.0);
+        assert_eq!(fingerprint_similarity(&[], &[]), 0.0);
+
+        // Lined up again when one starts a couple of frames later
+        let shifted = fingerprint_similarity(&a, &a[2..]);
+        assert!(shifted > 0.98, "{}", shifted);
+
+        let unrelated = fingerprint_similarity(&a, &words(2, 200));
+        assert!((0.4..0.6).contains(&unrelated), "{}", unrelated);
+
+        assert_eq!(fingerprint_similarity(&a, &a[..150]), 0.0);
+    }
+
+    #[test]
+    fn fingerprint_ignores_gain_and_padding() {
+        let rate = 22050;
+        let audio: Vec<f32> = (0..rate)
+            .map(|i| {
+                let t = i as f32 / rate as f32;
+                let envelope = (1.0 - t).max(0.0);
+                envelope * ((2.0 * PI * 220.0 * t).sin() + 0.5 * (2.0 * PI * 1760.0 * t * t).sin())
+            })
+            .collect();
+        let original = fingerprint(&audio, rate as u32).unwrap();
+
+        let mut padded = vec![0.0; rate / 4];
+        padded.extend(audio.iter().map(|s| s * 0.5));
+        let quieter = fingerprint(&padded, rate as u32).unwrap();
+        let similarity = fingerprint_similarity(&original, &quieter);
+        assert!(similarity >= MIN_SIMILARITY, "{}", similarity);
+
+        assert_eq!(fingerprint(&vec![0.0; rate], rate as u32), None);
+        assert_eq!(fingerprint(&audio, 0), None);
+    }
+
+    #[test]
+    fn groups_exact_and_near_duplicates() {
+        let base = words(3, 100);
+        let mut near = base.clone();
+        near[10] ^= 0xff;
+        let other = words(4, 100);
+
+        let candidates = [
+            candidate(1, Some("aa"), base.clone()),
+            candidate(2, Some("aa"), Vec::new()),
+            candidate(3, Some("bb"), near),
+            candidate(4, Some("cc"), other.clone()),
+            candidate(5, None, other),
+            candidate(6, None, words(5, 100)),
+        ];
+        let sets = group_duplicates(&candidates);
+        assert_eq!(
+            sorted_sets(&sets),
+            vec![(vec![1, 2, 3], false), (vec![4, 5], false)]
+        );
+        assert!(sets.windows(2).all(|w| w[0].similarity >= w[1].similarity));
+    }
+
+    #[test]
+    fn exact_sets_need_matching_hashes() {
+        let candidates = [
+            candidate(1, Some("aa"), Vec::new()),
+            candidate(2, Some("aa"), Vec::new()),
+            candidate(3, Some("aa"), Vec::new()),
+        ];
+        let sets = group_duplicates(&candidates);
+        assert_eq!(sorted_sets(&sets), vec![(vec![1, 2, 3], true)]);
+        assert_eq!(sets[0].similarity, 1.0);
+    }
+
+    #[test]
+    fn keeps_retuned_samples_apart() {
+        let fingerprint = words(6, 100);
+        let mut candidates = [
+            candidate(1, None, fingerprint.clone()),
+            candidate(2, None, fingerprint),
+        ];
+        candidates[0].pitch = Some(110.0);
+        candidates[1].pitch = Some(110.0 * 2f64.powf(1.0 / 12.0));
+        assert!(group_duplicates(&candidates).is_empty());
+
+        candidates[1].pitch = Some(111.0);
+        assert_eq!(group_duplicates(&candidates).len(), 1);
+    }
+}
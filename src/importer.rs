@@ -0,0 +1,112 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::db::open_db;
+use crate::sample::LibraryRoot;
+use crate::scanner::{ImportEvent, ScanOptions, import_library};
+
+/// Progress of the running (or last) import, as shown in the UI.
+#[derive(Debug, Clone, Default)]
+pub struct ImportProgress {
+    pub total: Option<usize>,
+    pub found: usize,
+    pub done: usize,
+    pub current_path: Option<PathBuf>,
+    pub errors: Vec<(PathBuf, String)>,
+    pub cancelled: bool,
+    /// Why the import stopped, if it failed
+    pub failed: Option<String>,
+}
+
+/// Runs `import_library` on a background thread with its own database
+/// connection and streams the resulting events back to the UI thread.
+pub struct ImportWorker {
+    receiver: Receiver<ImportEvent>,
+    cancel: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ImportWorker {
+    pub fn spawn(db_path: &str, roots: Vec<LibraryRoot>, options: ScanOptions) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let db_path = db_path.to_string();
+        let cancel_worker = Arc::clone(&cancel);
+        let handle = thread::spawn(move || {
+            let result = open_db(&db_path)
+                .and_then(|conn| {
+                    // The filesystem watcher may be writing at the same time
+                    conn.busy_timeout(Duration::from_secs(5))?;
+                    Ok(conn)
+                })
+                .map_err(|e| e.into())
+                .and_then(|conn| {
+                    import_library(&conn, &roots, options, &cancel_worker, &mut |event| {
+                        let _ = sender.send(event);
+                    })
+                });
+
+            if let Err(error) = result {
+                println!("Error: import failed: {}", error);
+                let _ = sender.send(ImportEvent::Finished {
+                    summary: Default::default(),
+                    cancelled: false,
+                    error: Some(error.to_string()),
+                });
+            }
+        });
+
+        Self {
+            receiver,
+            cancel,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns every event that arrived since the last call without blocking.
+    pub fn poll_events(&self) -> Vec<ImportEvent> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl Drop for ImportWorker {
+    fn drop(&mut self) {
+        self.cancel();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl ImportProgress {
+    pub fn apply(&mut self, event: &ImportEvent) {
+        match event {
+            ImportEvent::Scanning { found } => self.found = *found,
+            ImportEvent::Started { total } => self.total = Some(*total),
+            ImportEvent::Progress { done, current } => {
+                self.done = *done;
+                self.current_path = Some(current.clone());
+            }
+            ImportEvent::Error { path, message } => {
+                self.errors.push((path.clone(), message.clone()));
+            }
+            ImportEvent::Finished {
+                cancelled, error, ..
+            } => {
+                self.cancelled = *cancelled;
+                self.failed = error.clone();
+                self.current_path = None;
+            }
+            ImportEvent::Indexed(_) | ImportEvent::Missing(_) => {}
+        }
+    }
+}
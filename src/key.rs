@@ -0,0 +1,104 @@
+use crate::music::key_name;
+use crate::spectrum::{Stft, frame_starts};
+
+/// Frequency range folded into the chroma, in Hz. Below it the FFT can't
+/// tell semitones apart; above it harmonics blur the picture.
+const MIN_FREQUENCY: f32 = 55.0;
+const MAX_FREQUENCY: f32 = 4000.0;
+/// Analysis window length, in seconds.
+const WINDOW_SECONDS: f32 = 0.2;
+/// Drums and noise still correlate a little with some key; below this they
+/// are treated as having none.
+const MIN_CORRELATION: f32 = 0.3;
+
+/// Krumhansl-Kessler key profiles, starting from the tonic.
+const MAJOR_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+/// Estimates the key of mono audio by correlating its chroma with the major
+/// and minor key profiles in all twelve transpositions. Returns the key in
+/// `music::normalize_key` form and the correlation of the best match, from 0
+/// to 1, or `None` for silence and audio with no clear key.
+pub fn detect_key(samples: &[f32], sample_rate: u32) -> Option<(String, f32)> {
+    let chroma = chroma(samples, sample_rate)?;
+
+    let mut best: Option<(u8, bool, f32)> = None;
+    for tonic in 0..12u8 {
+        for (minor, profile) in [(false, &MAJOR_PROFILE), (true, &MINOR_PROFILE)] {
+            let rotated: Vec<f32> = (0..12)
+                .map(|pitch_class| profile[(pitch_class + 12 - tonic as usize) % 12])
+                .collect();
+            let correlation = pearson(&chroma, &rotated);
+            if best.is_none_or(|(_, _, best_correlation)| correlation > best_correlation) {
+                best = Some((tonic, minor, correlation));
+            }
+        }
+    }
+
+    let (tonic, minor, correlation) = best.filter(|&(.., c)| c >= MIN_CORRELATION)?;
+    Some((key_name(tonic, minor), correlation.min(1.0)))
+}
+
+/// Sums the spectrum of every window into twelve pitch classes, C first.
+fn chroma(samples: &[f32], sample_rate: u32) -> Option<[f32; 12]> {
+    if sample_rate == 0 {
+        return None;
+    }
+
+    let window_size = ((sample_rate as f32 * WINDOW_SECONDS) as usize).next_power_of_two();
+    let hop = window_size / 2;
+    let mut stft = Stft::new(window_size);
+
+    // Pitch class of every bin in range, worked out once
+    let bin_width = sample_rate as f32 / window_size as f32;
+    let bins: Vec<(usize, usize)> = (1..window_size / 2)
+        .filter_map(|bin| {
+            let frequency = bin as f32 * bin_width;
+            if !(MIN_FREQUENCY..=MAX_FREQUENCY).contains(&frequency) {
+                return None;
+            }
+            let midi_note = 69.0 + 12.0 * (frequency / 440.0).log2();
+            Some((bin, (midi_note.round() as i32).rem_euclid(12) as usize))
+        })
+        .collect();
+
+    let mut chroma = [0.0f32; 12];
+    for start in frame_starts(samples, hop) {
+        let spectrum = stft.spectrum(samples, start);
+        // Normalize each window so loud passages don't drown out the rest
+        let mut frame = [0.0f32; 12];
+        for &(bin, pitch_class) in &bins {
+            frame[pitch_class] += spectrum[bin].norm();
+        }
+        let total: f32 = frame.iter().sum();
+        if total > f32::EPSILON {
+            for (sum, value) in chroma.iter_mut().zip(frame) {
+                *sum += value / total;
+            }
+        }
+    }
+
+    chroma.iter().any(|&value| value > 0.0).then_some(chroma)
+}
+
+fn pearson(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len() as f32;
+    let mean_a = a.iter().sum::<f32>() / n;
+    let mean_b = b.iter().sum::<f32>() / n;
+    let mut covariance = 0.0;
+    let mut variance_a = 0.0;
+    let mut variance_b = 0.0;
+    for (x, y) in a.iter().zip(b) {
+        covariance += (x - mean_a) * (y - mean_b);
+        variance_a += (x - mean_a) * (x - mean_a);
+        variance_b += (y - mean_b) * (y - mean_b);
+    }
+    if variance_a <= f32::EPSILON || variance_b <= f32::EPSILON {
+        return 0.0;
+    }
+    covariance / (variance_a * variance_b).sqrt()
+}
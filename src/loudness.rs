@@ -0,0 +1,331 @@
+use std::f64::consts::PI;
+
+/// Loudness measurements of a sample. Levels are relative to digital full
+/// scale: dBTP for the true peak, dBFS for RMS and LUFS for loudness.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    /// Highest level between samples, estimated by oversampling
+    pub true_peak: f32,
+    /// Average level over all channels, unweighted
+    pub rms: f32,
+    /// EBU R128 integrated loudness
+    pub integrated: f32,
+    /// Loudest EBU R128 short-term (3 second) window
+    pub short_term: f32,
+}
+
+/// EBU R128 gating block length and step, in seconds.
+const BLOCK_SECONDS: f64 = 0.4;
+const STEP_SECONDS: f64 = 0.1;
+/// Short-term loudness window, in seconds.
+const SHORT_TERM_SECONDS: f64 = 3.0;
+/// Blocks quieter than this never count towards the integrated loudness.
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks more than this many LU below the ungated average are dropped too.
+const RELATIVE_GATE: f64 = -10.0;
+/// Reported instead of minus infinity for levels that are practically silent.
+const FLOOR_DB: f32 = -120.0;
+
+/// True peaks are estimated at this multiple of the sample rate.
+const OVERSAMPLING: usize = 4;
+/// Input samples on either side of an interpolated point.
+const INTERPOLATION_TAPS: usize = 8;
+
+/// Measures planar audio, one `Vec` per channel. Returns `None` for digital
+/// silence.
+///
+/// Samples shorter than the gating block or short-term window are measured
+/// as one block or window, so one-shots still get a loudness.
+pub fn measure_loudness(channels: &[Vec<f32>], sample_rate: u32) -> Option<Loudness> {
+    let frames = channels.iter().map(Vec::len).min()?;
+    if frames == 0 || sample_rate == 0 {
+        return None;
+    }
+
+    let true_peak = channels
+        .iter()
+        .map(|channel| true_peak(&channel[..frames]))
+        .fold(0.0f32, f32::max);
+    if true_peak <= 0.0 {
+        return None;
+    }
+
+    let square_sum: f64 = channels
+        .iter()
+        .flat_map(|channel| &channel[..frames])
+        .map(|&s| s as f64 * s as f64)
+        .sum();
+    let rms = 10.0 * (square_sum / (frames * channels.len()) as f64).log10();
+
+    // Running sums of the K-weighted power of every channel, weighted by
+    // position, so any window's mean power is one subtraction away
+    let weights = channel_weights(channels.len());
+    let mut power = vec![0.0f64; frames + 1];
+    for (channel, weight) in channels.iter().zip(weights) {
+        if weight == 0.0 {
+            continue;
+        }
+        let weighted = k_weighted(&channel[..frames], sample_rate);
+        let mut sum = 0.0;
+        for (i, value) in weighted.iter().enumerate() {
+            sum += weight * value * value;
+            power[i + 1] += sum;
+        }
+    }
+    let mean_power = |start: usize, end: usize| (power[end] - power[start]) / (end - start) as f64;
+
+    let rate = sample_rate as f64;
+    let step = ((rate * STEP_SECONDS) as usize).max(1);
+    let blocks = window_powers(frames, (rate * BLOCK_SECONDS) as usize, step, &mean_power);
+    let windows = window_powers(
+        frames,
+        (rate * SHORT_TERM_SECONDS) as usize,
+        step,
+        &mean_power,
+    );
+
+    let integrated = gated_loudness(&blocks).unwrap_or_else(|| loudness(mean_power(0, frames)));
+    let short_term = windows
+        .into_iter()
+        .map(loudness)
+        .fold(f64::NEG_INFINITY, f64::max);
+
+    let level = |db: f64| (db as f32).max(FLOOR_DB);
+    Some(Loudness {
+        true_peak: level(20.0 * (true_peak as f64).log10()),
+        rms: level(rms),
+        integrated: level(integrated),
+        short_term: level(short_term),
+    })
+}
+
+/// Mean powers of windows of `length` frames, `step` frames apart. Audio
+/// shorter than one window is a single window.
+fn window_powers(
+    frames: usize,
+    length: usize,
+    step: usize,
+    mean_power: &dyn Fn(usize, usize) -> f64,
+) -> Vec<f64> {
+    if frames <= length {
+        return vec![mean_power(0, frames)];
+    }
+    (0..=frames - length)
+        .step_by(step)
+        .map(|start| mean_power(start, start + length))
+        .collect()
+}
+
+/// Integrated loudness of gating blocks by the two gates of EBU R128, or
+/// `None` if every block is below the absolute gate.
+fn gated_loudness(blocks: &[f64]) -> Option<f64> {
+    let mean = |powers: &mut dyn Iterator<Item = f64>| {
+        let (sum, count) = powers.fold((0.0, 0), |(sum, count), p| (sum + p, count + 1));
+        (count > 0).then(|| sum / count as f64)
+    };
+
+    let audible = || {
+        blocks
+            .iter()
+            .copied()
+            .filter(|&p| loudness(p) > ABSOLUTE_GATE)
+    };
+    let relative_gate = loudness(mean(&mut audible())?) + RELATIVE_GATE;
+    let gated = mean(&mut audible().filter(|&p| loudness(p) > relative_gate))?;
+    Some(loudness(gated))
+}
+
+fn loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// BS.1770 channel weights. Only 5.1 has an LFE channel to leave out and
+/// surround channels to weight up; other layouts count every channel once.
+fn channel_weights(channels: usize) -> Vec<f64> {
+    if channels == 6 {
+        vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41]
+    } else {
+        vec![1.0; channels]
+    }
+}
+
+/// Applies the BS.1770 K-weighting: a high shelf modelling the head,
+/// followed by a high-pass filter. The coefficients are derived for the
+/// sample rate rather than tabulated for 48 kHz.
+fn k_weighted(samples: &[f32], sample_rate: u32) -> Vec<f64> {
+    let rate = sample_rate as f64;
+
+    let k = (PI * 1681.974450955533 / rate).tan();
+    let q = 0.7071752369554196;
+    let vh = 10f64.powf(3.999843853973347 / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    let k = (PI * 38.13547087602444 / rate).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    };
+
+    high_pass.process(shelf.process(samples.iter().map(|&s| s as f64)))
+}
+
+struct Biquad {
+    b: [f64; 3],
+    /// `a1` and `a2`, with `a0` normalized to 1
+    a: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&self, input: impl IntoIterator<Item = f64>) -> Vec<f64> {
+        let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+        input
+            .into_iter()
+            .map(|x| {
+                let y = self.b[0] * x + self.b[1] * x1 + self.b[2] * x2
+                    - self.a[0] * y1
+                    - self.a[1] * y2;
+                (x2, x1, y2, y1) = (x1, x, y1, y);
+                y
+            })
+            .collect()
+    }
+}
+
+/// Highest absolute value of the signal oversampled with a windowed-sinc
+/// interpolator, which catches peaks that fall between samples.
+fn true_peak(samples: &[f32]) -> f32 {
+    let sample_peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+
+    // One filter per fractional position between two input samples
+    let phases: Vec<Vec<f32>> = (1..OVERSAMPLING)
+        .map(|phase| {
+            let offset = phase as f64 / OVERSAMPLING as f64;
+            (0..2 * INTERPOLATION_TAPS)
+                .map(|tap| {
+                    let x = tap as f64 - (INTERPOLATION_TAPS - 1) as f64 - offset;
+                    let sinc = if x == 0.0 {
+                        1.0
+                    } else {
+                        (PI * x).sin() / (PI * x)
+                    };
+                    let window = 0.5 + 0.5 * (PI * x / INTERPOLATION_TAPS as f64).cos();
+                    (sinc * window) as f32
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut peak = sample_peak;
+    for start in 0..samples.len().saturating_sub(1) {
+        for taps in &phases {
+            let mut value = 0.0f32;
+            for (tap, coefficient) in taps.iter().enumerate() {
+                let index = (start + tap).checked_sub(INTERPOLATION_TAPS - 1);
+                if let Some(&sample) = index.and_then(|i| samples.get(i)) {
+                    value += sample * coefficient;
+                }
+            }
+            peak = peak.max(value.abs());
+        }
+    }
+    peak
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, amplitude: f64, phase: f64, seconds: f64, rate: u32) -> Vec<f32> {
+        (0..(seconds * rate as f64) as usize)
+            .map(|i| {
+                let t = i as f64 / rate as f64;
+                (amplitude * (2.0 * PI * frequency * t + phase).sin()) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn measures_a_full_scale_sine() {
+        for rate in [44100, 48000, 96000] {
+            let loudness = measure_loudness(&[sine(1000.0, 1.0, 0.0, 3.0, rate)], rate).unwrap();
+            assert!((loudness.integrated + 3.01).abs() < 0.05, "{:?}", loudness);
+            assert!((loudness.short_term + 3.01).abs() < 0.05, "{:?}", loudness);
+            assert!((loudness.rms + 3.01).abs() < 0.01, "{:?}", loudness);
+            assert!(loudness.true_peak.abs() < 0.05, "{:?}", loudness);
+        }
+    }
+
+    #[test]
+    fn finds_peaks_between_samples() {
+        // A quarter of the sample rate, sampled at ±0.707 but peaking at 1
+        let samples = sine(12000.0, 1.0, PI / 4.0, 1.0, 48000);
+        let sample_peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!((sample_peak - 0.707).abs() < 0.01);
+        let loudness = measure_loudness(&[samples], 48000).unwrap();
+        assert!(loudness.true_peak > -0.5, "{:?}", loudness);
+    }
+
+    #[test]
+    fn weights_low_frequencies_down() {
+        let low = measure_loudness(&[sine(30.0, 1.0, 0.0, 5.0, 48000)], 48000).unwrap();
+        assert!(low.integrated < -5.0, "{:?}", low);
+        assert!((low.rms + 3.01).abs() < 0.05, "{:?}", low);
+    }
+
+    #[test]
+    fn ignores_silence() {
+        assert_eq!(measure_loudness(&[vec![0.0; 48000]], 48000), None);
+        assert_eq!(
+            measure_loudness(&[vec![0.0; 48000], vec![0.0; 48000]], 48000),
+            None
+        );
+        assert_eq!(measure_loudness(&[Vec::new()], 48000), None);
+        assert_eq!(measure_loudness(&[], 48000), None);
+    }
+
+    #[test]
+    fn gates_quiet_passages() {
+        let rate = 48000;
+        let loud = sine(1000.0, 0.1, 0.0, 5.0, rate);
+        let alone = measure_loudness(std::slice::from_ref(&loud), rate)
+            .unwrap()
+            .integrated;
+        assert!((alone + 23.01).abs() < 0.05, "{}", alone);
+
+        // Below the absolute gate, which leaves only the blocks straddling the
+        // change; averaged in, the tail would take off about 5 LU
+        let mut with_tail = loud.clone();
+        with_tail.extend(sine(1000.0, 1e-4, 0.0, 10.0, rate));
+        let tailed = measure_loudness(&[with_tail], rate).unwrap().integrated;
+        assert!((tailed - alone).abs() < 0.25, "{} vs {}", tailed, alone);
+
+        // Above the absolute gate but over 10 LU below the rest; only the
+        // blocks straddling the change get through, where half the power
+        // would be without the gate
+        let mut with_quiet = loud.clone();
+        with_quiet.extend(sine(1000.0, 0.005, 0.0, 5.0, rate));
+        let quieter = measure_loudness(&[with_quiet], rate).unwrap().integrated;
+        assert!((quieter - alone).abs() < 0.25, "{} vs {}", quieter, alone);
+    }
+
+    #[test]
+    fn sums_channels() {
+        let rate = 48000;
+        let channel = sine(1000.0, 0.5, 0.0, 5.0, rate);
+        let mono = measure_loudness(std::slice::from_ref(&channel), rate).unwrap();
+        let stereo = measure_loudness(&[channel.clone(), channel], rate).unwrap();
+        assert!((stereo.integrated - mono.integrated - 3.01).abs() < 0.05);
+        assert!((stereo.rms - mono.rms).abs() < 0.01);
+    }
+}
@@ -0,0 +1,167 @@
+use symphonia::core::meta::{MetadataRevision, StandardTagKey};
+
+use crate::riff::WavChunks;
+
+/// One embedded metadata field, normalized to a common key where we know the
+/// meaning (`artist`, `genre`, `bpm`, `key`, ...) and otherwise stored under
+/// the lowercased key the file used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataTag {
+    pub key: String,
+    pub value: String,
+    /// Where the tag came from: `id3`, `vorbis`, `riff_info`, `bext`, `smpl`, `acid` or `tag`
+    pub source: String,
+}
+
+impl MetadataTag {
+    fn new(key: &str, value: &str, source: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            value: value.trim().to_string(),
+            source: source.to_string(),
+        }
+    }
+}
+
+/// Converts the tags of a symphonia metadata revision (ID3v1/v2, Vorbis
+/// comments, APE...). Binary values are skipped.
+pub fn from_symphonia(revision: &MetadataRevision, source: &str) -> Vec<MetadataTag> {
+    revision
+        .tags()
+        .iter()
+        .filter_map(|tag| {
+            let value = match &tag.value {
+                symphonia::core::meta::Value::Binary(_) => return None,
+                value => value.to_string(),
+            };
+            let key = tag
+                .std_key
+                .and_then(standard_key_name)
+                .map(String::from)
+                .unwrap_or_else(|| raw_key_name(&tag.key));
+            Some(MetadataTag::new(&key, &value, source))
+        })
+        .filter(|tag| !tag.value.is_empty())
+        .collect()
+}
+
+pub fn from_wav_chunks(chunks: &WavChunks) -> Vec<MetadataTag> {
+    let mut tags: Vec<MetadataTag> = chunks
+        .info
+        .iter()
+        .map(|(id, text)| MetadataTag::new(&info_key_name(id), text, "riff_info"))
+        .collect();
+
+    if let Some(bext) = &chunks.bext {
+        let fields = [
+            ("description", bext.description.clone()),
+            ("originator", bext.originator.clone()),
+            ("originator_reference", bext.originator_reference.clone()),
+            ("origination_date", bext.origination_date.clone()),
+            ("origination_time", bext.origination_time.clone()),
+            ("time_reference", bext.time_reference.to_string()),
+            ("coding_history", bext.coding_history.clone()),
+        ];
+        for (key, value) in fields {
+            if !value.is_empty() && value != "0" {
+                tags.push(MetadataTag::new(key, &value, "bext"));
+            }
+        }
+    }
+
+    if let Some(smpl) = &chunks.smpl {
+        if smpl.midi_pitch_fraction != 0 {
+            // The fraction is in units of 1/2^32 semitone
+            let cents = smpl.midi_pitch_fraction as f64 / 4_294_967_296.0 * 100.0;
+            tags.push(MetadataTag::new(
+                "fine_tune",
+                &format!("{:.1} cents", cents),
+                "smpl",
+            ));
+        }
+        if let Some(sample_loop) = smpl.loops.first() {
+            let mode = match sample_loop.loop_type {
+                0 => "forward",
+                1 => "ping-pong",
+                2 => "backward",
+                _ => "custom",
+            };
+            tags.push(MetadataTag::new("loop_mode", mode, "smpl"));
+            if sample_loop.play_count > 0 {
+                let count = sample_loop.play_count.to_string();
+                tags.push(MetadataTag::new("loop_count", &count, "smpl"));
+            }
+        }
+    }
+
+    if let Some(acid) = &chunks.acid {
+        if acid.is_one_shot() {
+            tags.push(MetadataTag::new("acid_type", "one-shot", "acid"));
+        } else {
+            tags.push(MetadataTag::new("acid_type", "loop", "acid"));
+            if acid.beats > 0 {
+                tags.push(MetadataTag::new("beats", &acid.beats.to_string(), "acid"));
+            }
+            if acid.meter_numerator > 0 && acid.meter_denominator > 0 {
+                let meter = format!("{}/{}", acid.meter_numerator, acid.meter_denominator);
+                tags.push(MetadataTag::new("meter", &meter, "acid"));
+            }
+        }
+    }
+
+    tags.retain(|tag| !tag.value.is_empty());
+    tags
+}
+
+fn standard_key_name(key: StandardTagKey) -> Option<&'static str> {
+    let name = match key {
+        StandardTagKey::Album => "album",
+        StandardTagKey::AlbumArtist => "album_artist",
+        StandardTagKey::Artist => "artist",
+        StandardTagKey::Bpm => "bpm",
+        StandardTagKey::Comment => "comment",
+        StandardTagKey::Composer => "composer",
+        StandardTagKey::Copyright => "copyright",
+        StandardTagKey::Date => "date",
+        StandardTagKey::Description => "description",
+        StandardTagKey::Encoder => "encoder",
+        StandardTagKey::Genre => "genre",
+        StandardTagKey::Label => "label",
+        StandardTagKey::Mood => "mood",
+        StandardTagKey::TrackTitle => "title",
+        StandardTagKey::TrackNumber => "track",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn raw_key_name(key: &str) -> String {
+    match key.to_ascii_uppercase().as_str() {
+        // ID3 TKEY, Vorbis INITIALKEY / KEY
+        "TKEY" | "INITIALKEY" | "KEY" => "key".to_string(),
+        "TBPM" | "BPM" | "TEMPO" => "bpm".to_string(),
+        _ => key.to_lowercase(),
+    }
+}
+
+fn info_key_name(id: &str) -> String {
+    let name = match id {
+        "IART" => "artist",
+        "INAM" => "title",
+        "IPRD" => "album",
+        "IGNR" => "genre",
+        "ICMT" => "comment",
+        "ICOP" => "copyright",
+        "ICRD" => "date",
+        "ISFT" => "software",
+        "IENG" => "engineer",
+        "IKEY" => "keywords",
+        "ISBJ" => "subject",
+        "ISRC" => "source",
+        "ITCH" => "technician",
+        "ITRK" | "IPRT" => "track",
+        "IBPM" => "bpm",
+        _ => return id.to_lowercase(),
+    };
+    name.to_string()
+}
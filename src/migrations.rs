@@ -0,0 +1,361 @@
+use std::error::Error;
+use std::fmt;
+
+use rusqlite::{Connection, Transaction, params};
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Sqlite(rusqlite::Error),
+    /// The database was written by a newer build that knows schema changes we don't
+    NewerSchema {
+        found: u32,
+        supported: u32,
+    },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Sqlite(err) => write!(f, "SQLite error: {}", err),
+            MigrationError::NewerSchema { found, supported } => write!(
+                f,
+                "Database schema version {} is newer than the supported version {}",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl Error for MigrationError {}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(err: rusqlite::Error) -> Self {
+        MigrationError::Sqlite(err)
+    }
+}
+
+type Migration = fn(&Transaction) -> rusqlite::Result<()>;
+
+/// Schema migrations in order. Migration `i` upgrades a database from
+/// `user_version` `i` to `i + 1`; append new steps, never edit shipped ones.
+const MIGRATIONS: &[Migration] = &[
+    initial_schema,
+    library_roots,
+    incremental_rescans,
+    technical_metadata,
+    embedded_metadata,
+    sampler_chunks,
+    search_index,
+    user_tags,
+    user_marks,
+    collections,
+    smart_collections,
+    tempo_analysis,
+    key_analysis,
+    loudness_analysis,
+    pitch_analysis,
+    auto_tags,
+    similarity_features,
+    duplicates,
+    search_artists,
+];
+
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Upgrades the database to `SCHEMA_VERSION`, one transaction per step.
+pub fn migrate(conn: &Connection) -> Result<(), MigrationError> {
+    let mut version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version > SCHEMA_VERSION {
+        return Err(MigrationError::NewerSchema {
+            found: version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+
+    while version < SCHEMA_VERSION {
+        let tx = conn.unchecked_transaction()?;
+        MIGRATIONS[version as usize](&tx)?;
+        version += 1;
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+        println!("Migrated database to schema version {}", version);
+    }
+
+    Ok(())
+}
+
+/// Adds a column unless it is already there. Databases created before
+/// migrations existed may already have some of the columns later steps add.
+fn add_column_if_missing(
+    tx: &Transaction,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> rusqlite::Result<()> {
+    let exists = tx
+        .prepare("SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2")?
+        .exists(params![table, column])?;
+    if !exists {
+        tx.execute_batch(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {};",
+            table, column, definition
+        ))?;
+    }
+    Ok(())
+}
+
+fn initial_schema(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS samples (
+            id INTEGER PRIMARY KEY,
+            path TEXT UNIQUE NOT NULL,
+            name TEXT NOT NULL,
+            format TEXT,
+            sample_rate INTEGER,
+            size INTEGER
+        );
+        ",
+    )
+}
+
+fn library_roots(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS library_roots (
+            id INTEGER PRIMARY KEY,
+            path TEXT UNIQUE NOT NULL,
+            follow_symlinks INTEGER NOT NULL DEFAULT 1,
+            ignore_patterns TEXT NOT NULL DEFAULT ''
+        );
+        ",
+    )?;
+    add_column_if_missing(
+        tx,
+        "samples",
+        "root_id",
+        "INTEGER REFERENCES library_roots(id) ON DELETE SET NULL",
+    )
+}
+
+fn incremental_rescans(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+        ",
+    )?;
+    add_column_if_missing(tx, "samples", "modified", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(tx, "samples", "content_hash", "TEXT")?;
+    add_column_if_missing(tx, "samples", "missing", "INTEGER NOT NULL DEFAULT 0")
+}
+
+fn technical_metadata(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "samples", "duration", "REAL")?;
+    add_column_if_missing(tx, "samples", "channels", "INTEGER")?;
+    add_column_if_missing(tx, "samples", "channel_layout", "TEXT")?;
+    add_column_if_missing(tx, "samples", "bit_depth", "INTEGER")?;
+    add_column_if_missing(tx, "samples", "frames", "INTEGER")?;
+    add_column_if_missing(tx, "samples", "bitrate", "INTEGER")?;
+    add_column_if_missing(tx, "samples", "container", "TEXT")?;
+    // Force the next rescan to re-probe every file so the new columns get filled
+    tx.execute_batch("UPDATE samples SET modified = 0;")
+}
+
+fn embedded_metadata(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS sample_metadata (
+            id INTEGER PRIMARY KEY,
+            sample_id INTEGER NOT NULL REFERENCES samples(id) ON DELETE CASCADE,
+            key TEXT NOT NULL,
+            value TEXT NOT NULL,
+            source TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS sample_metadata_sample ON sample_metadata(sample_id);
+        CREATE INDEX IF NOT EXISTS sample_metadata_key ON sample_metadata(key, value);
+
+        UPDATE samples SET modified = 0;
+        ",
+    )
+}
+
+fn sampler_chunks(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "samples", "loop_start", "INTEGER")?;
+    add_column_if_missing(tx, "samples", "loop_end", "INTEGER")?;
+    add_column_if_missing(tx, "samples", "root_note", "INTEGER")?;
+    add_column_if_missing(tx, "samples", "tempo", "REAL")?;
+    tx.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS sample_cues (
+            id INTEGER PRIMARY KEY,
+            sample_id INTEGER NOT NULL REFERENCES samples(id) ON DELETE CASCADE,
+            position INTEGER NOT NULL,
+            label TEXT
+        );
+        CREATE INDEX IF NOT EXISTS sample_cues_sample ON sample_cues(sample_id);
+
+        UPDATE samples SET modified = 0;
+        ",
+    )
+}
+
+fn search_index(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "samples", "musical_key", "TEXT")?;
+    tx.execute_batch(
+        "
+        CREATE INDEX IF NOT EXISTS samples_musical_key ON samples(musical_key);
+        CREATE INDEX IF NOT EXISTS samples_tempo ON samples(tempo);
+
+        CREATE VIRTUAL TABLE IF NOT EXISTS samples_fts USING fts5(name, path, tags, comments);
+        INSERT INTO samples_fts (rowid, name, path, tags, comments)
+        SELECT id, name, path,
+            (SELECT group_concat(value, ' ') FROM sample_metadata
+             WHERE sample_id = samples.id
+             AND key IN ('genre', 'keywords', 'mood', 'subject', 'style', 'grouping')),
+            (SELECT group_concat(value, ' ') FROM sample_metadata
+             WHERE sample_id = samples.id AND key IN ('comment', 'description'))
+        FROM samples;
+
+        UPDATE samples SET modified = 0;
+        ",
+    )
+}
+
+fn user_tags(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS tags (
+            id INTEGER PRIMARY KEY,
+            name TEXT UNIQUE NOT NULL COLLATE NOCASE
+        );
+        CREATE TABLE IF NOT EXISTS sample_tags (
+            sample_id INTEGER NOT NULL REFERENCES samples(id) ON DELETE CASCADE,
+            tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+            PRIMARY KEY (sample_id, tag_id)
+        );
+        CREATE INDEX IF NOT EXISTS sample_tags_tag ON sample_tags(tag_id);
+        ",
+    )
+}
+
+fn user_marks(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "samples", "favorite", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(tx, "samples", "rating", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(tx, "samples", "color_label", "TEXT")
+}
+
+fn collections(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS collections (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            parent_id INTEGER REFERENCES collections(id) ON DELETE CASCADE,
+            is_folder INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE TABLE IF NOT EXISTS collection_samples (
+            collection_id INTEGER NOT NULL REFERENCES collections(id) ON DELETE CASCADE,
+            sample_id INTEGER NOT NULL REFERENCES samples(id) ON DELETE CASCADE,
+            position INTEGER NOT NULL,
+            PRIMARY KEY (collection_id, sample_id)
+        );
+        CREATE INDEX IF NOT EXISTS collection_samples_sample ON collection_samples(sample_id);
+        ",
+    )
+}
+
+fn smart_collections(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "collections", "query", "TEXT")
+}
+
+fn tempo_analysis(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "samples", "bpm", "REAL")?;
+    add_column_if_missing(tx, "samples", "bpm_confidence", "REAL")?;
+    add_column_if_missing(
+        tx,
+        "samples",
+        "analysis_version",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+    tx.execute_batch("CREATE INDEX IF NOT EXISTS samples_bpm ON samples(bpm);")
+}
+
+fn key_analysis(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "samples", "estimated_key", "TEXT")?;
+    add_column_if_missing(tx, "samples", "key_confidence", "REAL")?;
+    tx.execute_batch("CREATE INDEX IF NOT EXISTS samples_estimated_key ON samples(estimated_key);")
+}
+
+fn loudness_analysis(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "samples", "true_peak", "REAL")?;
+    add_column_if_missing(tx, "samples", "rms", "REAL")?;
+    add_column_if_missing(tx, "samples", "lufs", "REAL")?;
+    add_column_if_missing(tx, "samples", "short_term_lufs", "REAL")?;
+    tx.execute_batch("CREATE INDEX IF NOT EXISTS samples_lufs ON samples(lufs);")
+}
+
+fn pitch_analysis(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "samples", "pitch", "REAL")?;
+    add_column_if_missing(tx, "samples", "pitch_confidence", "REAL")?;
+    tx.execute_batch("CREATE INDEX IF NOT EXISTS samples_pitch ON samples(pitch);")
+}
+
+fn auto_tags(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "sample_tags", "auto", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(tx, "sample_tags", "confidence", "REAL")
+}
+
+fn similarity_features(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS sample_features (
+            sample_id INTEGER PRIMARY KEY REFERENCES samples(id) ON DELETE CASCADE,
+            vector BLOB NOT NULL
+        );
+        ",
+    )
+}
+
+fn duplicates(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "samples", "audio_hash", "TEXT")?;
+    add_column_if_missing(tx, "samples", "hidden", "INTEGER NOT NULL DEFAULT 0")?;
+    tx.execute_batch(
+        "
+        CREATE INDEX IF NOT EXISTS samples_audio_hash ON samples(audio_hash);
+        CREATE TABLE IF NOT EXISTS sample_fingerprints (
+            sample_id INTEGER PRIMARY KEY REFERENCES samples(id) ON DELETE CASCADE,
+            fingerprint BLOB NOT NULL
+        );
+        ",
+    )
+}
+
+fn search_artists(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "
+        DROP TABLE IF EXISTS samples_fts;
+        CREATE VIRTUAL TABLE samples_fts USING fts5(name, title, artist, path, tags, comments);
+        INSERT INTO samples_fts (rowid, name, title, artist, path, tags, comments)
+        SELECT id, name,
+            (SELECT group_concat(value, ' ') FROM sample_metadata
+             WHERE sample_id = samples.id AND key = 'title'),
+            (SELECT group_concat(value, ' ') FROM sample_metadata
+             WHERE sample_id = samples.id AND key IN ('artist', 'album_artist')),
+            path,
+            (SELECT group_concat(value, ' ') FROM (
+                SELECT value FROM sample_metadata
+                WHERE sample_id = samples.id
+                AND key IN ('genre', 'keywords', 'mood', 'subject', 'style', 'grouping')
+                UNION ALL
+                SELECT tags.name FROM sample_tags JOIN tags ON tags.id = sample_tags.tag_id
+                WHERE sample_tags.sample_id = samples.id
+            )),
+            (SELECT group_concat(value, ' ') FROM sample_metadata
+             WHERE sample_id = samples.id AND key IN ('comment', 'description'))
+        FROM samples;
+        ",
+    )
+}
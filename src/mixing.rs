@@ -0,0 +1,269 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::path::Path;
+
+use symphonia::core::audio::Channels;
+
+use crate::riff::read_wav_chunks;
+
+/// Where a channel is meant to be heard. Height and wide channels are folded
+/// into the nearest of these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speaker {
+    FrontLeft,
+    FrontRight,
+    Center,
+    Lfe,
+    SideLeft,
+    SideRight,
+    RearLeft,
+    RearRight,
+    RearCenter,
+}
+
+impl Speaker {
+    fn from_channel(channel: Channels) -> Speaker {
+        match channel {
+            Channels::FRONT_RIGHT
+            | Channels::FRONT_RIGHT_CENTRE
+            | Channels::FRONT_RIGHT_WIDE
+            | Channels::FRONT_RIGHT_HIGH
+            | Channels::TOP_FRONT_RIGHT => Speaker::FrontRight,
+            Channels::FRONT_CENTRE
+            | Channels::TOP_CENTRE
+            | Channels::TOP_FRONT_CENTRE
+            | Channels::FRONT_CENTRE_HIGH => Speaker::Center,
+            Channels::LFE1 | Channels::LFE2 => Speaker::Lfe,
+            Channels::SIDE_LEFT => Speaker::SideLeft,
+            Channels::SIDE_RIGHT => Speaker::SideRight,
+            Channels::REAR_LEFT | Channels::REAR_LEFT_CENTRE | Channels::TOP_REAR_LEFT => {
+                Speaker::RearLeft
+            }
+            Channels::REAR_RIGHT | Channels::REAR_RIGHT_CENTRE | Channels::TOP_REAR_RIGHT => {
+                Speaker::RearRight
+            }
+            Channels::REAR_CENTRE | Channels::TOP_REAR_CENTRE => Speaker::RearCenter,
+            _ => Speaker::FrontLeft,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Speaker::FrontLeft => "L",
+            Speaker::FrontRight => "R",
+            Speaker::Center => "C",
+            Speaker::Lfe => "LFE",
+            Speaker::SideLeft => "Ls",
+            Speaker::SideRight => "Rs",
+            Speaker::RearLeft => "Lb",
+            Speaker::RearRight => "Rb",
+            Speaker::RearCenter => "Cb",
+        }
+    }
+
+    /// Direction in degrees, counterclockwise from straight ahead, for
+    /// decoding ambisonics.
+    fn azimuth(self) -> Option<f32> {
+        match self {
+            Speaker::FrontLeft => Some(45.0),
+            Speaker::FrontRight => Some(-45.0),
+            Speaker::Center => Some(0.0),
+            Speaker::Lfe => None,
+            Speaker::SideLeft => Some(90.0),
+            Speaker::SideRight => Some(-90.0),
+            Speaker::RearLeft => Some(135.0),
+            Speaker::RearRight => Some(-135.0),
+            Speaker::RearCenter => Some(180.0),
+        }
+    }
+}
+
+/// What the channels of a source file carry.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceLayout {
+    /// One channel per speaker, in file order
+    Speakers(Vec<Speaker>),
+    /// First-order ambisonics in AmbiX channel order and normalization:
+    /// W, Y, Z, X
+    BFormat,
+}
+
+impl SourceLayout {
+    /// Works out the layout of a file with symphonia's channel positions.
+    /// Four-channel WAV files that don't say which speakers they're for are
+    /// taken to be B-format, as AmbiX recorders write them that way.
+    pub fn detect(path: &Path, channels: Channels) -> SourceLayout {
+        if channels.count() == 4
+            && let Ok(Some(chunks)) = read_wav_chunks(path)
+            && let Some(format) = chunks.format
+            && format.channels == 4
+            && !format.has_speaker_positions()
+        {
+            return SourceLayout::BFormat;
+        }
+        SourceLayout::Speakers(channels.iter().map(Speaker::from_channel).collect())
+    }
+
+    pub fn channel_count(&self) -> usize {
+        match self {
+            SourceLayout::Speakers(speakers) => speakers.len(),
+            SourceLayout::BFormat => 4,
+        }
+    }
+
+    /// Short names of the channels, for solo and mute buttons.
+    pub fn channel_labels(&self) -> Vec<&'static str> {
+        match self {
+            SourceLayout::Speakers(speakers) if speakers.len() == 1 => vec!["Mono"],
+            SourceLayout::Speakers(speakers) => speakers.iter().map(|s| s.label()).collect(),
+            SourceLayout::BFormat => vec!["W", "Y", "Z", "X"],
+        }
+    }
+}
+
+/// How a channel is auditioned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChannelState {
+    #[default]
+    Normal,
+    Muted,
+    Soloed,
+}
+
+/// Speakers the output device's channels are taken to feed. Devices don't
+/// say, so this follows the WAVE order for common channel counts; channels
+/// past a 7.1 layout stay silent.
+fn output_speakers(count: usize) -> Vec<Option<Speaker>> {
+    use Speaker::*;
+    let layout: &[Speaker] = match count {
+        1 => &[Center],
+        2 => &[FrontLeft, FrontRight],
+        3 => &[FrontLeft, FrontRight, Center],
+        4 => &[FrontLeft, FrontRight, RearLeft, RearRight],
+        5 => &[FrontLeft, FrontRight, Center, RearLeft, RearRight],
+        6 => &[FrontLeft, FrontRight, Center, Lfe, RearLeft, RearRight],
+        7 => &[
+            FrontLeft, FrontRight, Center, Lfe, RearCenter, SideLeft, SideRight,
+        ],
+        _ => &[
+            FrontLeft, FrontRight, Center, Lfe, RearLeft, RearRight, SideLeft, SideRight,
+        ],
+    };
+    (0..count).map(|i| layout.get(i).copied()).collect()
+}
+
+/// Where a source speaker goes when the output lacks it: the first choice
+/// whose speakers the output all has, with their gains. Surround channels
+/// fold into the fronts at -3 dB as in ITU-R BS.775; LFE is dropped.
+fn routing_choices(speaker: Speaker) -> &'static [&'static [(Speaker, f32)]] {
+    use Speaker::*;
+    const HALF: f32 = 0.5;
+    match speaker {
+        FrontLeft => &[&[(FrontLeft, 1.0)]],
+        FrontRight => &[&[(FrontRight, 1.0)]],
+        Center => &[
+            &[(Center, 1.0)],
+            &[(FrontLeft, FRAC_1_SQRT_2), (FrontRight, FRAC_1_SQRT_2)],
+        ],
+        Lfe => &[&[(Lfe, 1.0)]],
+        SideLeft => &[
+            &[(SideLeft, 1.0)],
+            &[(RearLeft, 1.0)],
+            &[(FrontLeft, FRAC_1_SQRT_2)],
+        ],
+        SideRight => &[
+            &[(SideRight, 1.0)],
+            &[(RearRight, 1.0)],
+            &[(FrontRight, FRAC_1_SQRT_2)],
+        ],
+        RearLeft => &[
+            &[(RearLeft, 1.0)],
+            &[(SideLeft, 1.0)],
+            &[(FrontLeft, FRAC_1_SQRT_2)],
+        ],
+        RearRight => &[
+            &[(RearRight, 1.0)],
+            &[(SideRight, 1.0)],
+            &[(FrontRight, FRAC_1_SQRT_2)],
+        ],
+        RearCenter => &[
+            &[(RearCenter, 1.0)],
+            &[(RearLeft, FRAC_1_SQRT_2), (RearRight, FRAC_1_SQRT_2)],
+            &[(SideLeft, FRAC_1_SQRT_2), (SideRight, FRAC_1_SQRT_2)],
+            &[(FrontLeft, HALF), (FrontRight, HALF)],
+        ],
+    }
+}
+
+/// A lone channel is mono and goes to both fronts; soloed channels the mix
+/// would drop, such as LFE, go there too so they can still be heard.
+const FRONTS: &[&[(Speaker, f32)]] = &[
+    &[(Speaker::FrontLeft, 1.0), (Speaker::FrontRight, 1.0)],
+    &[(Speaker::Center, 1.0)],
+];
+
+/// Builds the matrix that mixes frames of `layout` down or up to
+/// `out_channels` device channels, row-major with one row per output
+/// channel. Muted channels are left out, and so is every channel that isn't
+/// soloed while any is.
+pub fn mix_matrix(layout: &SourceLayout, out_channels: usize, states: &[ChannelState]) -> Vec<f32> {
+    let in_channels = layout.channel_count();
+    // Mono output is the average of a stereo mix, as before
+    let outputs = output_speakers(out_channels.max(2));
+    let mut matrix = vec![0.0f32; outputs.len() * in_channels];
+
+    let route = |matrix: &mut [f32], input: usize, choices: &[&[(Speaker, f32)]]| {
+        let choice = choices.iter().find(|choice| {
+            choice
+                .iter()
+                .all(|(speaker, _)| outputs.contains(&Some(*speaker)))
+        });
+        for &(speaker, gain) in choice.copied().unwrap_or_default() {
+            let output = outputs.iter().position(|&s| s == Some(speaker)).unwrap();
+            matrix[output * in_channels + input] += gain;
+        }
+    };
+
+    match layout {
+        SourceLayout::Speakers(speakers) if speakers.len() == 1 => route(&mut matrix, 0, FRONTS),
+        SourceLayout::Speakers(speakers) => {
+            for (input, &speaker) in speakers.iter().enumerate() {
+                route(&mut matrix, input, routing_choices(speaker));
+            }
+        }
+        SourceLayout::BFormat => {
+            // A virtual cardioid microphone pointed at each speaker
+            for (output, speaker) in outputs.iter().enumerate() {
+                let Some(azimuth) = speaker.and_then(Speaker::azimuth) else {
+                    continue;
+                };
+                let (sin, cos) = azimuth.to_radians().sin_cos();
+                let row = &mut matrix[output * in_channels..(output + 1) * in_channels];
+                row.copy_from_slice(&[0.5, 0.5 * sin, 0.0, 0.5 * cos]);
+            }
+        }
+    }
+
+    let soloing = states.contains(&ChannelState::Soloed);
+    for input in 0..in_channels {
+        let state = states.get(input).copied().unwrap_or_default();
+        let audible = match state {
+            ChannelState::Normal => !soloing,
+            ChannelState::Muted => false,
+            ChannelState::Soloed => true,
+        };
+        let silent_in_mix = (0..outputs.len()).all(|o| matrix[o * in_channels + input] == 0.0);
+        if !audible {
+            for output in 0..outputs.len() {
+                matrix[output * in_channels + input] = 0.0;
+            }
+        } else if state == ChannelState::Soloed && silent_in_mix {
+            route(&mut matrix, input, FRONTS);
+        }
+    }
+
+    if out_channels == 1 {
+        let (left, right) = matrix.split_at(in_channels);
+        return left.iter().zip(right).map(|(l, r)| 0.5 * (l + r)).collect();
+    }
+    matrix
+}
@@ -0,0 +1,231 @@
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Formats a MIDI note number as a note name, with middle C (60) as `C4`.
+pub fn note_name(note: u8) -> String {
+    format!("{}{}", NOTE_NAMES[note as usize % 12], note as i32 / 12 - 1)
+}
+
+/// Normalizes the many ways files spell a musical key (`A minor`, `Amin`,
+/// `a-moll`, `Bbm`, `B♭ maj`, or Camelot `8A`) to a short form with sharps:
+/// `Am`, `A#`.
+pub fn normalize_key(text: &str) -> Option<String> {
+    let text = text.trim();
+    if let Some((tonic, minor)) = parse_camelot(text) {
+        return Some(key_name(tonic, minor));
+    }
+
+    let (pitch_class, rest) = parse_pitch_class(text)?;
+    let mode = rest
+        .trim_start_matches([' ', '-', '_'])
+        .trim()
+        .to_lowercase();
+    // "m" alone is minor, but "M" alone is major
+    let minor = match mode.as_str() {
+        "" | "maj" | "major" | "dur" => false,
+        "min" | "minor" | "moll" => true,
+        "m" => !rest.trim().starts_with('M'),
+        _ => return None,
+    };
+
+    Some(key_name(pitch_class.rem_euclid(12) as u8, minor))
+}
+
+/// Reads a note letter and optional accidental off the start of `text`.
+/// Returns the pitch class, which is -1 for `Cb` and 12 for `B#`, and the
+/// rest of the text.
+fn parse_pitch_class(text: &str) -> Option<(i32, &str)> {
+    let mut chars = text.chars();
+    let letter = chars.next()?.to_ascii_uppercase();
+    let pitch_class: i32 = match letter {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+
+    let rest = chars.as_str();
+    if let Some(stripped) = rest.strip_prefix(['#', '♯']) {
+        Some((pitch_class + 1, stripped))
+    } else if let Some(stripped) = rest.strip_prefix(['b', '♭']) {
+        Some((pitch_class - 1, stripped))
+    } else {
+        Some((pitch_class, rest))
+    }
+}
+
+/// Parses a note name such as `C#1`, `Db2` or `a-1` into a MIDI note number,
+/// the inverse of `note_name`.
+pub fn parse_note(text: &str) -> Option<u8> {
+    let (pitch_class, octave) = parse_pitch_class(text.trim())?;
+    let octave: i32 = octave.parse().ok()?;
+    u8::try_from((octave + 1) * 12 + pitch_class)
+        .ok()
+        .filter(|&note| note < 128)
+}
+
+/// Frequency in Hz of a (possibly fractional) MIDI note, with A4 at 440 Hz.
+pub fn note_frequency(note: f64) -> f64 {
+    440.0 * 2f64.powf((note - 69.0) / 12.0)
+}
+
+/// The MIDI note nearest to `frequency` and how far off it is, in cents.
+pub fn frequency_to_note(frequency: f64) -> Option<(u8, f32)> {
+    let note = 69.0 + 12.0 * (frequency / 440.0).log2();
+    let nearest = note.round();
+    if !(0.0..128.0).contains(&nearest) {
+        return None;
+    }
+    Some((nearest as u8, ((note - nearest) * 100.0) as f32))
+}
+
+/// Formats a frequency as the nearest note and its offset, e.g. `C#1 +12¢`.
+pub fn pitch_name(frequency: f64) -> Option<String> {
+    let (note, cents) = frequency_to_note(frequency)?;
+    Some(format!("{} {:+}¢", note_name(note), cents.round() as i32))
+}
+
+/// Formats a key from its tonic pitch class (C = 0) in `normalize_key` form.
+pub fn key_name(tonic: u8, minor: bool) -> String {
+    let name = NOTE_NAMES[tonic as usize % 12];
+    if minor {
+        format!("{}m", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Splits a key in `normalize_key` form into its tonic pitch class and
+/// whether it is minor.
+fn parse_key(key: &str) -> Option<(u8, bool)> {
+    let (name, minor) = match key.strip_suffix('m') {
+        Some(name) => (name, true),
+        None => (key, false),
+    };
+    let tonic = NOTE_NAMES.iter().position(|&note| note == name)?;
+    Some((tonic as u8, minor))
+}
+
+/// The key on the Camelot wheel, e.g. `8A` for A minor and `8B` for C
+/// major. Neighbouring numbers are a fifth apart; A is minor, B major.
+pub fn camelot(key: &str) -> Option<String> {
+    let (number, letter) = camelot_position(key)?;
+    Some(format!("{}{}", number, letter))
+}
+
+/// The number and letter of `camelot`, for sorting around the wheel.
+pub fn camelot_position(key: &str) -> Option<(u8, char)> {
+    let (tonic, minor) = parse_key(key)?;
+    Some(wheel_position(tonic, minor))
+}
+
+fn wheel_position(tonic: u8, minor: bool) -> (u8, char) {
+    // Minor keys share the number of their relative major, a minor third up
+    let major_tonic = if minor { (tonic + 3) % 12 } else { tonic };
+    // C major is 8B, and each step clockwise is a fifth (7 semitones) up
+    let number = (major_tonic as u32 * 7 + 7) % 12 + 1;
+    (number as u8, if minor { 'A' } else { 'B' })
+}
+
+fn parse_camelot(text: &str) -> Option<(u8, bool)> {
+    let letter = text.chars().last()?.to_ascii_uppercase();
+    if !matches!(letter, 'A' | 'B') {
+        return None;
+    }
+    let number: u8 = text[..text.len() - 1].parse().ok()?;
+    if !(1..=12).contains(&number) {
+        return None;
+    }
+    let minor = letter == 'A';
+    (0..12u8)
+        .find(|&tonic| wheel_position(tonic, minor) == (number, letter))
+        .map(|tonic| (tonic, minor))
+}
+
+/// Keys that mix harmonically with `key` by the Camelot rules: the key
+/// itself, its relative major or minor, and its neighbours a fifth up and
+/// down. All in `normalize_key` form.
+pub fn compatible_keys(key: &str) -> Vec<String> {
+    let Some((tonic, minor)) = parse_key(key) else {
+        return Vec::new();
+    };
+    let relative = if minor {
+        key_name((tonic + 3) % 12, false)
+    } else {
+        key_name((tonic + 9) % 12, true)
+    };
+    vec![
+        key_name(tonic, minor),
+        relative,
+        key_name((tonic + 7) % 12, minor),
+        key_name((tonic + 5) % 12, minor),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_key_spellings() {
+        let cases = [
+            ("A minor", "Am"),
+            ("Amin", "Am"),
+            ("a-moll", "Am"),
+            ("Bbm", "A#m"),
+            ("B♭ maj", "A#"),
+            ("F# Major", "F#"),
+            ("CM", "C"),
+            ("Cm", "Cm"),
+            ("Cb", "B"),
+            ("B#m", "Cm"),
+            ("8A", "Am"),
+            ("8b", "C"),
+            ("12B", "E"),
+        ];
+        for (text, key) in cases {
+            assert_eq!(normalize_key(text).as_deref(), Some(key), "{}", text);
+        }
+
+        for text in ["", "H", "A dorian", "13A", "0B", "8C"] {
+            assert_eq!(normalize_key(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn maps_keys_to_camelot() {
+        assert_eq!(camelot("Am").as_deref(), Some("8A"));
+        assert_eq!(camelot("C").as_deref(), Some("8B"));
+        assert_eq!(camelot("G").as_deref(), Some("9B"));
+        assert_eq!(camelot("F").as_deref(), Some("7B"));
+        assert_eq!(camelot("A").as_deref(), Some("11B"));
+        assert_eq!(camelot("G#m").as_deref(), Some("1A"));
+        assert_eq!(camelot("Bb"), None);
+    }
+
+    #[test]
+    fn camelot_round_trips() {
+        for number in 1..=12 {
+            for letter in ['A', 'B'] {
+                let code = format!("{}{}", number, letter);
+                let (tonic, minor) = parse_camelot(&code).unwrap();
+                assert_eq!(minor, letter == 'A');
+                assert_eq!(camelot(&key_name(tonic, minor)), Some(code));
+            }
+        }
+        assert_eq!(parse_camelot("8"), None);
+        assert_eq!(parse_camelot("xA"), None);
+    }
+
+    #[test]
+    fn lists_compatible_keys() {
+        assert_eq!(compatible_keys("Am"), vec!["Am", "C", "Em", "Dm"]);
+        assert_eq!(compatible_keys("C"), vec!["C", "Am", "G", "F"]);
+        assert!(compatible_keys("Hm").is_empty());
+    }
+}
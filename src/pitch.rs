@@ -0,0 +1,261 @@
+use std::sync::Arc;
+
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+
+/// Fundamental frequencies considered, in Hz: from below the lowest 808s up
+/// to the top of most leads.
+const MIN_FREQUENCY: f64 = 25.0;
+const MAX_FREQUENCY: f64 = 2000.0;
+/// Only the start of the sample is searched; one-shots ring out long before.
+const MAX_SECONDS: f64 = 4.0;
+/// The attack is mostly noise and pitch sweep, so frames this soon after the
+/// loudest point are skipped.
+const ATTACK_SECONDS: f64 = 0.03;
+/// Frames quieter than the loudest one by this factor in RMS (-30 dB) are tail.
+const MIN_RELATIVE_LEVEL: f32 = 0.03;
+/// YIN's absolute threshold: the first dip in the normalized difference
+/// below this is taken as the period.
+const YIN_THRESHOLD: f32 = 0.15;
+/// Frames whose best dip is above this are unpitched.
+const MAX_APERIODICITY: f32 = 0.5;
+/// Results less confident than this are treated as having no pitch.
+const MIN_CONFIDENCE: f32 = 0.3;
+
+/// Estimates the fundamental frequency of mono audio with the YIN algorithm.
+/// Returns the frequency in Hz and a confidence from 0 to 1: how periodic the
+/// pitched frames are, scaled by how much of the sample is pitched. `None`
+/// for audio with no clear pitch, such as noise, drum loops and chords.
+pub fn detect_pitch(samples: &[f32], sample_rate: u32) -> Option<(f64, f32)> {
+    if sample_rate == 0 {
+        return None;
+    }
+    let rate = sample_rate as f64;
+    let max_lag = (rate / MIN_FREQUENCY).ceil() as usize;
+    let min_lag = ((rate / MAX_FREQUENCY).floor() as usize).max(2);
+    let window = max_lag;
+    let hop = window / 2;
+
+    let samples = &samples[..samples.len().min((rate * MAX_SECONDS) as usize)];
+    if samples.len() < window + max_lag {
+        return None;
+    }
+
+    // Frame levels decide where the pitched body of the sample is
+    let frame_starts: Vec<usize> = (0..=samples.len() - window - max_lag)
+        .step_by(hop)
+        .collect();
+    let levels: Vec<f32> = frame_starts
+        .iter()
+        .map(|&start| {
+            let frame = &samples[start..start + window];
+            (frame.iter().map(|s| s * s).sum::<f32>() / window as f32).sqrt()
+        })
+        .collect();
+    let (loudest, &peak_level) = levels
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))?;
+    if peak_level <= f32::EPSILON {
+        return None;
+    }
+
+    let body_start = frame_starts[loudest] + (rate * ATTACK_SECONDS) as usize;
+    let body: Vec<usize> = frame_starts
+        .iter()
+        .zip(&levels)
+        .filter(|&(&start, &level)| {
+            start >= body_start.min(frame_starts[frame_starts.len() - 1])
+                && level >= peak_level * MIN_RELATIVE_LEVEL
+        })
+        .map(|(&start, _)| start)
+        .collect();
+    if body.is_empty() {
+        return None;
+    }
+
+    let yin = Yin::new(window, max_lag);
+    let mut pitched: Vec<(f64, f32)> = body
+        .iter()
+        .filter_map(|&start| {
+            let (lag, aperiodicity) =
+                yin.period(&samples[start..start + window + max_lag], min_lag)?;
+            (aperiodicity <= MAX_APERIODICITY).then(|| (rate / lag, 1.0 - aperiodicity))
+        })
+        .collect();
+    if pitched.is_empty() {
+        return None;
+    }
+
+    // The median frame ignores the odd octave slip
+    pitched.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let (frequency, _) = pitched[pitched.len() / 2];
+    let periodicity = pitched.iter().map(|&(_, c)| c).sum::<f32>() / pitched.len() as f32;
+    let confidence = periodicity * pitched.len() as f32 / body.len() as f32;
+
+    (confidence >= MIN_CONFIDENCE).then_some((frequency, confidence))
+}
+
+/// The YIN difference function for frames of a fixed size, computed through
+/// the FFT.
+struct Yin {
+    window: usize,
+    max_lag: usize,
+    fft_size: usize,
+    forward: Arc<dyn Fft<f32>>,
+    inverse: Arc<dyn Fft<f32>>,
+}
+
+impl Yin {
+    fn new(window: usize, max_lag: usize) -> Self {
+        let fft_size = (2 * window + max_lag).next_power_of_two();
+        let mut planner = FftPlanner::new();
+        Self {
+            window,
+            max_lag,
+            fft_size,
+            forward: planner.plan_fft_forward(fft_size),
+            inverse: planner.plan_fft_inverse(fft_size),
+        }
+    }
+
+    /// Finds the period of `segment`, which is `window + max_lag` samples
+    /// long, in fractional samples, with the normalized difference at that
+    /// lag (0 for perfectly periodic audio).
+    fn period(&self, segment: &[f32], min_lag: usize) -> Option<(f64, f32)> {
+        let (window, max_lag) = (self.window, self.max_lag);
+
+        // Autocorrelation of the first window against every lag at once
+        let mut frame = vec![Complex::new(0.0, 0.0); self.fft_size];
+        let mut whole = vec![Complex::new(0.0, 0.0); self.fft_size];
+        for (i, &sample) in segment.iter().enumerate() {
+            whole[i].re = sample;
+            if i < window {
+                frame[i].re = sample;
+            }
+        }
+        self.forward.process(&mut frame);
+        self.forward.process(&mut whole);
+        for (f, w) in frame.iter_mut().zip(&whole) {
+            *f = f.conj() * w;
+        }
+        self.inverse.process(&mut frame);
+        let scale = 1.0 / self.fft_size as f32;
+
+        // Energy of every window-sized stretch, from running sums of squares
+        let mut energy = vec![0.0f32; segment.len() + 1];
+        for (i, &sample) in segment.iter().enumerate() {
+            energy[i + 1] = energy[i] + sample * sample;
+        }
+        let stretch_energy = |lag: usize| energy[lag + window] - energy[lag];
+
+        // Cumulative mean normalized difference
+        let mut normalized = vec![1.0f32; max_lag + 1];
+        let mut running = 0.0f32;
+        for lag in 1..=max_lag {
+            let difference =
+                (stretch_energy(0) + stretch_energy(lag) - 2.0 * frame[lag].re * scale).max(0.0);
+            running += difference;
+            if running > f32::EPSILON {
+                normalized[lag] = difference * lag as f32 / running;
+            }
+        }
+
+        // The first dip under the threshold, followed down to its bottom,
+        // else the deepest dip overall
+        let lag = match (min_lag..max_lag).find(|&lag| normalized[lag] < YIN_THRESHOLD) {
+            Some(mut lag) => {
+                while lag + 1 < max_lag && normalized[lag + 1] < normalized[lag] {
+                    lag += 1;
+                }
+                lag
+            }
+            None => (min_lag..max_lag).min_by(|&a, &b| normalized[a].total_cmp(&normalized[b]))?,
+        };
+
+        // Parabolic interpolation for a fractional period
+        let (before, at, after) = (normalized[lag - 1], normalized[lag], normalized[lag + 1]);
+        let curvature = before - 2.0 * at + after;
+        let offset = if curvature > 0.0 {
+            (0.5 * (before - after) / curvature).clamp(-0.5, 0.5)
+        } else {
+            0.0
+        };
+        Some((lag as f64 + offset as f64, at))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+    use crate::music::frequency_to_note;
+
+    const RATE: u32 = 44100;
+
+    /// A decaying tone with a few harmonics, like a plucked or synth one-shot.
+    fn tone(frequency: f64, harmonics: usize) -> Vec<f32> {
+        (0..RATE as usize)
+            .map(|i| {
+                let t = i as f64 / RATE as f64;
+                let partials: f64 = (1..=harmonics)
+                    .map(|h| (2.0 * PI * frequency * h as f64 * t).sin() / h as f64)
+                    .sum();
+                (0.5 * (-3.0 * t).exp() * partials) as f32
+            })
+            .collect()
+    }
+
+    fn cents(detected: f64, expected: f64) -> f64 {
+        1200.0 * (detected / expected).log2()
+    }
+
+    #[test]
+    fn detects_sine_pitch() {
+        for frequency in [55.0, 440.0, 1000.0] {
+            let (detected, confidence) = detect_pitch(&tone(frequency, 1), RATE).unwrap();
+            assert!(cents(detected, frequency).abs() < 5.0, "{} Hz", detected);
+            assert!(confidence > 0.8, "{}", confidence);
+        }
+    }
+
+    #[test]
+    fn detects_the_fundamental_under_harmonics() {
+        for frequency in [41.2, 110.0, 261.63] {
+            let (detected, _) = detect_pitch(&tone(frequency, 6), RATE).unwrap();
+            assert!(cents(detected, frequency).abs() < 5.0, "{} Hz", detected);
+        }
+    }
+
+    #[test]
+    fn finds_no_pitch_in_noise() {
+        let mut state = 1u32;
+        let noise: Vec<f32> = (0..RATE)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 8) as f32 / (1 << 23) as f32 - 1.0
+            })
+            .collect();
+        if let Some((frequency, confidence)) = detect_pitch(&noise, RATE) {
+            assert!(confidence < 0.5, "{} Hz at {}", frequency, confidence);
+        }
+
+        assert_eq!(detect_pitch(&vec![0.0; RATE as usize], RATE), None);
+        assert_eq!(detect_pitch(&tone(440.0, 1)[..100], RATE), None);
+        assert_eq!(detect_pitch(&tone(440.0, 1), 0), None);
+    }
+
+    #[test]
+    fn reports_detuning_in_cents() {
+        let frequency = 440.0 * 2f64.powf(30.0 / 1200.0);
+        let (detected, _) = detect_pitch(&tone(frequency, 1), RATE).unwrap();
+        let (note, cents) = frequency_to_note(detected).unwrap();
+        assert_eq!(note, 69);
+        assert!((cents - 30.0).abs() < 5.0, "{}", cents);
+
+        let (note, cents) = frequency_to_note(55.0 * 2f64.powf(-20.0 / 1200.0)).unwrap();
+        assert_eq!(note, 33);
+        assert!((cents + 20.0).abs() < 0.01, "{}", cents);
+    }
+}
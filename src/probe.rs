@@ -0,0 +1,294 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use symphonia::core::audio::{Channels, Layout};
+use symphonia::core::codecs::{
+    CODEC_TYPE_AAC, CODEC_TYPE_MP1, CODEC_TYPE_MP2, CODEC_TYPE_MP3, CODEC_TYPE_NULL,
+    CODEC_TYPE_OPUS, CODEC_TYPE_VORBIS, CodecParameters, CodecType,
+};
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::default::{get_codecs, get_probe};
+
+use crate::metadata::{MetadataTag, from_symphonia, from_wav_chunks};
+use crate::music::normalize_key;
+use crate::riff::{WavChunks, read_wav_chunks};
+use crate::sample::{CueMarker, Sample};
+use crate::scanner::file_stamp;
+
+const LOSSY_CODECS: [CodecType; 6] = [
+    CODEC_TYPE_MP1,
+    CODEC_TYPE_MP2,
+    CODEC_TYPE_MP3,
+    CODEC_TYPE_AAC,
+    CODEC_TYPE_VORBIS,
+    CODEC_TYPE_OPUS,
+];
+
+/// Result of probing a file: the sample row plus the embedded data that is
+/// stored in tables of its own.
+#[derive(Debug, Clone)]
+pub struct ProbedFile {
+    pub sample: Sample,
+    pub embedded: EmbeddedData,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EmbeddedData {
+    pub metadata: Vec<MetadataTag>,
+    pub cues: Vec<CueMarker>,
+}
+
+/// Probes an audio file and collects everything we index about it without
+/// decoding any audio.
+pub fn process_file(path: &Path) -> Result<ProbedFile, Box<dyn std::error::Error>> {
+    let name = path.file_name().unwrap().to_string_lossy().to_string();
+    let (size, modified) = file_stamp(path)?;
+    let container = sniff_container(path)?;
+
+    // Open file
+    let file = File::open(path)?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    // Probe format
+    let mut probed = get_probe().format(
+        &Default::default(),
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    let mut format_reader = probed.format;
+
+    // WAV metadata lives in chunks symphonia skips or only partially reads, so
+    // parse those ourselves; everything else comes from symphonia's readers
+    let wav_chunks = if container.as_deref() == Some("wav") {
+        read_wav_chunks(path)?
+    } else {
+        None
+    };
+    let metadata = if let Some(chunks) = &wav_chunks {
+        from_wav_chunks(chunks)
+    } else {
+        let source = match container.as_deref() {
+            Some("mp3") => "id3",
+            Some("flac") | Some("ogg") => "vorbis",
+            _ => "tag",
+        };
+        let mut tags = Vec::new();
+        if let Some(revision) = probed.metadata.get().as_ref().and_then(|m| m.current()) {
+            tags.extend(from_symphonia(revision, source));
+        }
+        if let Some(revision) = format_reader.metadata().current() {
+            tags.extend(from_symphonia(revision, source));
+        }
+        tags.dedup();
+        tags
+    };
+
+    // Take the first audio track
+    let track = format_reader
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("no supported audio tracks found")?;
+    let codec_params = &track.codec_params;
+
+    let format_name = get_codecs()
+        .get_codec(codec_params.codec)
+        .map(|descriptor| descriptor.short_name.to_string())
+        .unwrap_or_else(|| codec_params.codec.to_string());
+
+    let sample_rate = codec_params.sample_rate;
+    let frames = codec_params.n_frames;
+    let duration = match (frames, sample_rate) {
+        (Some(frames), Some(rate)) if rate > 0 => Some(frames as f64 / rate as f64),
+        _ => None,
+    };
+
+    // Lossless bitrates are just rate * depth * channels, so only lossy ones are worth storing
+    let bitrate = match duration {
+        Some(duration) if duration > 0.0 && LOSSY_CODECS.contains(&codec_params.codec) => {
+            Some((size as f64 * 8.0 / duration) as u32)
+        }
+        _ => None,
+    };
+
+    let mut sample = Sample {
+        id: 0,
+        root_id: None,
+        path: path.to_string_lossy().to_string(),
+        name,
+        format: format_name,
+        sample_rate,
+        size,
+        modified,
+        content_hash: None,
+        missing: false,
+        duration,
+        channels: channel_count(codec_params),
+        channel_layout: channel_layout_name(codec_params),
+        bit_depth: codec_params.bits_per_sample,
+        frames,
+        bitrate,
+        container,
+        loop_start: None,
+        loop_end: None,
+        root_note: None,
+        tempo: None,
+        musical_key: None,
+        favorite: false,
+        rating: 0,
+        color_label: None,
+        hidden: false,
+        bpm: None,
+        bpm_confidence: None,
+        estimated_key: None,
+        key_confidence: None,
+        loudness: None,
+        pitch: None,
+        pitch_confidence: None,
+    };
+
+    let cues = match &wav_chunks {
+        Some(chunks) => apply_sampler_chunks(&mut sample, chunks),
+        None => Vec::new(),
+    };
+
+    // Fall back to BPM and key tags for files without sampler chunks
+    let tag_value = |key: &str| {
+        metadata
+            .iter()
+            .find(|tag| tag.key == key)
+            .map(|tag| tag.value.as_str())
+    };
+    if sample.tempo.is_none() {
+        sample.tempo = tag_value("bpm")
+            .and_then(|bpm| bpm.parse::<f64>().ok())
+            .filter(|bpm| bpm.is_finite() && *bpm > 0.0);
+    }
+    sample.musical_key = tag_value("key").and_then(normalize_key);
+
+    Ok(ProbedFile {
+        sample,
+        embedded: EmbeddedData { metadata, cues },
+    })
+}
+
+/// Fills in the loop region, root note and tempo from `smpl` and `acid`
+/// chunks and returns the file's cue points.
+fn apply_sampler_chunks(sample: &mut Sample, chunks: &WavChunks) -> Vec<CueMarker> {
+    if let Some(sample_loop) = chunks.smpl.as_ref().and_then(|smpl| smpl.loops.first())
+        && sample_loop.end >= sample_loop.start
+    {
+        sample.loop_start = Some(sample_loop.start as u64);
+        sample.loop_end = Some(sample_loop.end as u64 + 1);
+    }
+
+    let smpl_note = chunks
+        .smpl
+        .as_ref()
+        .map(|smpl| smpl.midi_unity_note)
+        // Many tools write a zeroed smpl chunk just to carry loops
+        .filter(|&note| note > 0 && note < 128);
+    let acid_note = chunks
+        .acid
+        .filter(|acid| acid.has_root_note() && acid.root_note < 128)
+        .map(|acid| acid.root_note as u32);
+    sample.root_note = smpl_note.or(acid_note).map(|note| note as u8);
+
+    sample.tempo = chunks
+        .acid
+        .filter(|acid| !acid.is_one_shot() && acid.tempo.is_finite() && acid.tempo > 0.0)
+        .map(|acid| acid.tempo as f64);
+
+    chunks
+        .cues
+        .iter()
+        .map(|cue| CueMarker {
+            frame: cue.position as u64,
+            label: cue.label.clone(),
+        })
+        .collect()
+}
+
+fn channel_count(codec_params: &CodecParameters) -> Option<u32> {
+    codec_params
+        .channels
+        .or_else(|| codec_params.channel_layout.map(Layout::into_channels))
+        .map(|channels| channels.count() as u32)
+}
+
+fn channel_layout_name(codec_params: &CodecParameters) -> Option<String> {
+    if let Some(layout) = codec_params.channel_layout {
+        let name = match layout {
+            Layout::Mono => "mono",
+            Layout::Stereo => "stereo",
+            Layout::TwoPointOne => "2.1",
+            Layout::FivePointOne => "5.1",
+        };
+        return Some(name.to_string());
+    }
+
+    let channels = codec_params.channels?;
+    let front = Channels::FRONT_LEFT | Channels::FRONT_RIGHT;
+    let quad = front | Channels::REAR_LEFT | Channels::REAR_RIGHT;
+    let five_one = quad | Channels::FRONT_CENTRE | Channels::LFE1;
+    let five_one_side = front
+        | Channels::FRONT_CENTRE
+        | Channels::LFE1
+        | Channels::SIDE_LEFT
+        | Channels::SIDE_RIGHT;
+    let seven_one = five_one | Channels::SIDE_LEFT | Channels::SIDE_RIGHT;
+
+    let name = if channels == Channels::FRONT_LEFT || channels == Channels::FRONT_CENTRE {
+        "mono".to_string()
+    } else if channels == front {
+        "stereo".to_string()
+    } else if channels == quad {
+        "quad".to_string()
+    } else if channels == five_one || channels == five_one_side {
+        "5.1".to_string()
+    } else if channels == seven_one {
+        "7.1".to_string()
+    } else {
+        format!("{} ch", channels.count())
+    };
+    Some(name)
+}
+
+/// Identifies the container from the file's magic bytes, since symphonia's
+/// format readers don't expose their name.
+fn sniff_container(path: &Path) -> std::io::Result<Option<String>> {
+    let mut header = [0u8; 12];
+    let mut file = File::open(path)?;
+    let read = file.read(&mut header)?;
+    let header = &header[..read];
+
+    let name = if header.len() >= 12
+        && matches!(&header[0..4], b"RIFF" | b"RIFX" | b"RF64")
+        && &header[8..12] == b"WAVE"
+    {
+        Some("wav")
+    } else if header.starts_with(b"fLaC") {
+        Some("flac")
+    } else if header.starts_with(b"OggS") {
+        Some("ogg")
+    } else if header.len() >= 12 && &header[0..4] == b"FORM" && &header[8..11] == b"AIF" {
+        Some("aiff")
+    } else if header.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+        Some("mkv")
+    } else if header.len() >= 8 && &header[4..8] == b"ftyp" {
+        Some("mp4")
+    } else if header.starts_with(b"ID3")
+        || (header.len() >= 2 && header[0] == 0xff && header[1] & 0xe0 == 0xe0)
+    {
+        Some("mp3")
+    } else {
+        None
+    };
+
+    Ok(name.map(String::from))
+}
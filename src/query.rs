@@ -0,0 +1,586 @@
+use rusqlite::types::Value;
+
+use crate::classify::Category;
+use crate::music::{compatible_keys, normalize_key, note_frequency, parse_note};
+use crate::sample::ColorLabel;
+
+/// Numeric fields as (query name, `samples` column, tolerance, units). A plain
+/// value like `bpm:120` matches within the tolerance; ranges are inclusive.
+const RANGE_FIELDS: &[(&str, &str, f64, &[Unit])] = &[
+    ("bpm", "bpm", 0.5, BPM_UNITS),
+    ("tempo", "tempo", 0.5, BPM_UNITS),
+    ("rate", "sample_rate", 0.0, RATE_UNITS),
+    ("samplerate", "sample_rate", 0.0, RATE_UNITS),
+    ("bits", "bit_depth", 0.0, &[("bit", 1.0)]),
+    ("depth", "bit_depth", 0.0, &[("bit", 1.0)]),
+    ("channels", "channels", 0.0, &[("ch", 1.0)]),
+    ("duration", "duration", 0.5, DURATION_UNITS),
+    ("length", "duration", 0.5, DURATION_UNITS),
+    ("rating", "rating", 0.0, &[]),
+    ("confidence", "bpm_confidence", 0.0, &[("%", 0.01)]),
+    ("lufs", "lufs", 0.5, LEVEL_UNITS),
+    ("loudness", "lufs", 0.5, LEVEL_UNITS),
+    ("shortterm", "short_term_lufs", 0.5, LEVEL_UNITS),
+    ("peak", "true_peak", 0.5, LEVEL_UNITS),
+    ("rms", "rms", 0.5, LEVEL_UNITS),
+    ("pitch", "pitch", 0.5, RATE_UNITS),
+];
+
+/// A unit suffix and the factor converting it to the column's unit. Longer
+/// suffixes must come before shorter ones they end with.
+type Unit = (&'static str, f64);
+
+const BPM_UNITS: &[Unit] = &[("bpm", 1.0)];
+const RATE_UNITS: &[Unit] = &[("khz", 1000.0), ("hz", 1.0), ("k", 1000.0)];
+const LEVEL_UNITS: &[Unit] = &[
+    ("lufs", 1.0),
+    ("dbtp", 1.0),
+    ("dbfs", 1.0),
+    ("db", 1.0),
+    ("lu", 1.0),
+];
+const DURATION_UNITS: &[Unit] = &[
+    ("ms", 0.001),
+    ("sec", 1.0),
+    ("s", 1.0),
+    ("min", 60.0),
+    ("m", 60.0),
+];
+
+/// Full-text fields as (query name, `samples_fts` column).
+const TEXT_FIELDS: &[(&str, &str)] = &[
+    ("name", "name"),
+    ("title", "title"),
+    ("artist", "artist"),
+    ("path", "path"),
+    ("tag", "tags"),
+    ("comment", "comments"),
+];
+
+/// The part of the library the sidebar restricts the table to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LibraryScope {
+    #[default]
+    All,
+    Favorites,
+    Tag(isize),
+    Collection(isize),
+}
+
+impl LibraryScope {
+    /// Builds a `WHERE` clause over the `samples` table and its parameters.
+    pub fn to_sql(self) -> (String, Vec<Value>) {
+        match self {
+            LibraryScope::All => ("1".to_string(), Vec::new()),
+            LibraryScope::Favorites => ("favorite = 1".to_string(), Vec::new()),
+            LibraryScope::Tag(tag_id) => (
+                "id IN (SELECT sample_id FROM sample_tags WHERE tag_id = ?)".to_string(),
+                vec![Value::Integer(tag_id as i64)],
+            ),
+            LibraryScope::Collection(collection_id) => (
+                "id IN (SELECT sample_id FROM collection_samples WHERE collection_id = ?)"
+                    .to_string(),
+                vec![Value::Integer(collection_id as i64)],
+            ),
+        }
+    }
+
+    /// Builds an `ORDER BY` expression for scopes with an order of their own.
+    pub fn order_sql(self) -> Option<(String, Vec<Value>)> {
+        match self {
+            LibraryScope::Collection(collection_id) => Some((
+                "(SELECT position FROM collection_samples
+                  WHERE collection_id = ? AND sample_id = samples.id)"
+                    .to_string(),
+                vec![Value::Integer(collection_id as i64)],
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// A parsed search box query such as `bpm:120-128 key:Am format:flac tag:kick
+/// -tag:loop rating>=4 is:fav snare`. Keys may be given in Camelot notation
+/// (`key:8A`), `compatible:Am` matches every key that mixes with A minor, and
+/// `note:C#1` matches samples whose detected pitch is nearest to C#1.
+/// `class:kick` only matches auto-tags, while `tag:kick` matches both kinds.
+/// All terms must match; a leading `-` negates a term and words without a
+/// field are matched as prefixes against the full-text index. An uppercase
+/// `AND` between terms is allowed, as in `tag:snare AND duration<1s`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchQuery {
+    terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Term {
+    negated: bool,
+    filter: Filter,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    /// A word or quoted phrase, optionally restricted to one full-text column
+    Text {
+        column: Option<&'static str>,
+        text: String,
+        phrase: bool,
+    },
+    /// Comparisons on a numeric column, all of which must hold
+    Range {
+        column: &'static str,
+        bounds: Vec<(&'static str, f64)>,
+    },
+    /// Musical keys in `normalize_key` form, any of which matches
+    Key(Vec<String>),
+    /// A codec or container name
+    Format(String),
+    Favorite(bool),
+    ColorLabel(ColorLabel),
+    /// A category the classifier auto-tagged the sample with
+    Category(String),
+}
+
+impl SearchQuery {
+    pub fn parse(input: &str) -> Self {
+        let terms = tokenize(input)
+            .into_iter()
+            .filter_map(|(negated, token, quoted)| {
+                parse_term(&token, quoted).map(|filter| Term { negated, filter })
+            })
+            .collect();
+        Self { terms }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Builds a `WHERE` clause over the `samples` table and its parameters.
+    pub fn to_sql(&self) -> (String, Vec<Value>) {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+
+        // Plain words share one full-text lookup; everything else gets its own condition
+        let positive_text: Vec<String> = self
+            .terms
+            .iter()
+            .filter(|term| !term.negated)
+            .filter_map(|term| match &term.filter {
+                Filter::Text {
+                    column,
+                    text,
+                    phrase,
+                } => Some(fts_expression(*column, text, *phrase)),
+                _ => None,
+            })
+            .collect();
+        if !positive_text.is_empty() {
+            conditions.push(
+                "id IN (SELECT rowid FROM samples_fts WHERE samples_fts MATCH ?)".to_string(),
+            );
+            values.push(Value::Text(positive_text.join(" ")));
+        }
+
+        for term in &self.terms {
+            let condition = match &term.filter {
+                Filter::Text { .. } if !term.negated => continue,
+                Filter::Text {
+                    column,
+                    text,
+                    phrase,
+                } => {
+                    values.push(Value::Text(fts_expression(*column, text, *phrase)));
+                    "id IN (SELECT rowid FROM samples_fts WHERE samples_fts MATCH ?)".to_string()
+                }
+                Filter::Range { column, bounds } => {
+                    let parts: Vec<String> = bounds
+                        .iter()
+                        .map(|(op, value)| {
+                            values.push(Value::Real(*value));
+                            format!("{} {} ?", column, op)
+                        })
+                        .collect();
+                    parts.join(" AND ")
+                }
+                Filter::Key(keys) => {
+                    values.extend(keys.iter().cloned().map(Value::Text));
+                    format!("estimated_key IN ({})", vec!["?"; keys.len()].join(", "))
+                }
+                Filter::Format(format) => {
+                    values.push(Value::Text(format.clone()));
+                    values.push(Value::Text(format.clone()));
+                    "(lower(format) = ? OR lower(container) = ?)".to_string()
+                }
+                Filter::Favorite(favorite) => {
+                    values.push(Value::Integer(*favorite as i64));
+                    "favorite = ?".to_string()
+                }
+                Filter::ColorLabel(label) => {
+                    values.push(Value::Text(label.key().to_string()));
+                    "color_label = ?".to_string()
+                }
+                Filter::Category(name) => {
+                    values.push(Value::Text(name.clone()));
+                    "id IN (SELECT sample_id FROM sample_tags JOIN tags ON tags.id = tag_id
+                        WHERE auto = 1 AND tags.name = ?)"
+                        .to_string()
+                }
+            };
+
+            if term.negated {
+                // Samples without a value for the field don't match the term, so keep them
+                conditions.push(format!("NOT coalesce(({}), 0)", condition));
+            } else {
+                conditions.push(condition);
+            }
+        }
+
+        if conditions.is_empty() {
+            return ("1".to_string(), values);
+        }
+        (conditions.join(" AND "), values)
+    }
+}
+
+/// Splits the query on whitespace outside double quotes. Returns each token
+/// with whether it was negated and whether it contained a quoted part.
+fn tokenize(input: &str) -> Vec<(bool, String, bool)> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let negated = chars.next_if_eq(&'-').is_some();
+        let mut token = String::new();
+        let mut quoted = false;
+        let mut in_quotes = false;
+        while let Some(c) = chars.next_if(|&c| in_quotes || !c.is_whitespace()) {
+            if c == '"' {
+                in_quotes = !in_quotes;
+                quoted = true;
+            } else {
+                token.push(c);
+            }
+        }
+
+        // All terms must match anyway, so an explicit AND is just a separator
+        let is_and = !negated && !quoted && token == "AND";
+        if !token.trim().is_empty() && !is_and {
+            tokens.push((negated, token, quoted));
+        }
+    }
+
+    tokens
+}
+
+fn parse_term(token: &str, quoted: bool) -> Option<Filter> {
+    if let Some((field, value)) = split_field(token) {
+        let field = field.to_lowercase();
+        let value = value.trim();
+        if let Some(filter) = parse_field(&field, value, quoted) {
+            return Some(filter);
+        }
+    }
+
+    text_filter(None, token, quoted)
+}
+
+/// Splits `field:value`, and also `field>=value` style comparisons such as
+/// `rating>=4`, where the operator stays part of the value.
+fn split_field(token: &str) -> Option<(&str, &str)> {
+    let operator = token.find(['<', '>']);
+    match (token.find(':'), operator) {
+        (Some(colon), Some(op)) if op < colon => Some((&token[..op], &token[op..])),
+        (Some(colon), _) => Some((&token[..colon], &token[colon + 1..])),
+        (None, Some(op)) if op > 0 => Some((&token[..op], &token[op..])),
+        _ => None,
+    }
+}
+
+/// Returns `None` for unknown fields and values that don't parse, which are
+/// then searched as plain text.
+fn parse_field(field: &str, value: &str, quoted: bool) -> Option<Filter> {
+    if let Some(&(_, column, tolerance, units)) =
+        RANGE_FIELDS.iter().find(|(name, ..)| *name == field)
+    {
+        let bounds = parse_range(value, tolerance, units)?;
+        return Some(Filter::Range { column, bounds });
+    }
+    if let Some(&(_, column)) = TEXT_FIELDS.iter().find(|(name, _)| *name == field) {
+        return text_filter(Some(column), value, quoted);
+    }
+
+    match field {
+        "key" => normalize_key(value).map(|key| Filter::Key(vec![key])),
+        // Anything within half a semitone of the note
+        "note" => parse_note(value).map(|note| Filter::Range {
+            column: "pitch",
+            bounds: vec![
+                (">=", note_frequency(note as f64 - 0.5)),
+                ("<", note_frequency(note as f64 + 0.5)),
+            ],
+        }),
+        "compatible" | "compat" => {
+            normalize_key(value).map(|key| Filter::Key(compatible_keys(&key)))
+        }
+        "format" => (!value.is_empty()).then(|| Filter::Format(value.to_lowercase())),
+        "favorite" | "fav" => match value.to_lowercase().as_str() {
+            "" | "yes" | "true" | "1" => Some(Filter::Favorite(true)),
+            "no" | "false" | "0" => Some(Filter::Favorite(false)),
+            _ => None,
+        },
+        "is" if matches!(value.to_lowercase().as_str(), "fav" | "favorite") => {
+            Some(Filter::Favorite(true))
+        }
+        "color" | "label" => ColorLabel::from_key(&value.to_lowercase()).map(Filter::ColorLabel),
+        "class" | "category" => Category::ALL
+            .into_iter()
+            .find(|category| category.name().eq_ignore_ascii_case(value))
+            .map(|category| Filter::Category(category.name().to_string())),
+        _ => None,
+    }
+}
+
+fn text_filter(column: Option<&'static str>, text: &str, phrase: bool) -> Option<Filter> {
+    // The full-text tokenizer drops punctuation, so a term without letters or
+    // digits can't match anything
+    if !text.chars().any(char::is_alphanumeric) {
+        return None;
+    }
+    Some(Filter::Text {
+        column,
+        text: text.to_string(),
+        phrase,
+    })
+}
+
+/// Parses `120`, `120-128`, `>=120`, `<=128`, `>120` and `<128`. Numbers may
+/// carry one of the field's units, as in `<1s` or `44.1khz`.
+fn parse_range(value: &str, tolerance: f64, units: &[Unit]) -> Option<Vec<(&'static str, f64)>> {
+    for op in [">=", "<=", ">", "<"] {
+        if let Some(number) = value.strip_prefix(op) {
+            return Some(vec![(op, parse_number(number, units)?)]);
+        }
+    }
+
+    // The dash between two numbers, not the sign of the first, as in `-24--12`
+    let dash = value
+        .char_indices()
+        .skip(1)
+        .find(|&(_, c)| c == '-')
+        .map(|(i, _)| i);
+    if let Some(dash) = dash {
+        let min = parse_number(&value[..dash], units)?;
+        let max = parse_number(&value[dash + 1..], units)?;
+        return Some(vec![(">=", min.min(max)), ("<=", min.max(max))]);
+    }
+
+    let number = parse_number(value, units)?;
+    if tolerance > 0.0 {
+        Some(vec![(">=", number - tolerance), ("<", number + tolerance)])
+    } else {
+        Some(vec![("=", number)])
+    }
+}
+
+fn parse_number(text: &str, units: &[Unit]) -> Option<f64> {
+    let text = text.trim().to_lowercase();
+    for (suffix, factor) in units {
+        if let Some(number) = text.strip_suffix(suffix) {
+            return number.trim().parse::<f64>().ok().map(|n| n * factor);
+        }
+    }
+    text.parse().ok()
+}
+
+/// Quotes user text as an FTS5 string so its punctuation isn't parsed as
+/// query syntax. Unquoted words match as prefixes.
+fn fts_expression(column: Option<&str>, text: &str, phrase: bool) -> String {
+    let mut expression = format!("\"{}\"", text.replace('"', "\"\""));
+    if !phrase {
+        expression.push('*');
+    }
+    match column {
+        Some(column) => format!("{} : {}", column, expression),
+        None => expression,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(column: &'static str, bounds: &[(&'static str, f64)]) -> Filter {
+        Filter::Range {
+            column,
+            bounds: bounds.to_vec(),
+        }
+    }
+
+    fn filters(input: &str) -> Vec<(bool, Filter)> {
+        SearchQuery::parse(input)
+            .terms
+            .into_iter()
+            .map(|term| (term.negated, term.filter))
+            .collect()
+    }
+
+    #[test]
+    fn splits_fields_and_comparisons() {
+        assert_eq!(split_field("bpm:120"), Some(("bpm", "120")));
+        assert_eq!(split_field("rating>=4"), Some(("rating", ">=4")));
+        assert_eq!(split_field("duration<1s"), Some(("duration", "<1s")));
+        assert_eq!(split_field("bpm:>120"), Some(("bpm", ">120")));
+        assert_eq!(split_field("a>b:c"), Some(("a", ">b:c")));
+        assert_eq!(split_field(">4"), None);
+        assert_eq!(split_field("snare"), None);
+    }
+
+    #[test]
+    fn parses_ranges_with_units() {
+        assert_eq!(
+            parse_range("120", 0.5, BPM_UNITS),
+            Some(vec![(">=", 119.5), ("<", 120.5)])
+        );
+        assert_eq!(
+            parse_range("128-120", 0.5, BPM_UNITS),
+            Some(vec![(">=", 120.0), ("<=", 128.0)])
+        );
+        assert_eq!(
+            parse_range("-24--12", 0.5, LEVEL_UNITS),
+            Some(vec![(">=", -24.0), ("<=", -12.0)])
+        );
+        assert_eq!(
+            parse_range(">=44.1khz", 0.0, RATE_UNITS),
+            Some(vec![(">=", 44100.0)])
+        );
+        assert_eq!(
+            parse_range("48k", 0.0, RATE_UNITS),
+            Some(vec![("=", 48000.0)])
+        );
+        assert_eq!(
+            parse_range("<500ms", 0.5, DURATION_UNITS),
+            Some(vec![("<", 0.5)])
+        );
+        assert_eq!(parse_range("fast", 0.5, BPM_UNITS), None);
+        assert_eq!(parse_range("120-", 0.5, BPM_UNITS), None);
+    }
+
+    #[test]
+    fn parses_terms() {
+        assert_eq!(
+            filters("bpm:120-128 key:8A format:FLAC -tag:loop rating>=4 is:fav"),
+            vec![
+                (false, range("bpm", &[(">=", 120.0), ("<=", 128.0)])),
+                (false, Filter::Key(vec!["Am".to_string()])),
+                (false, Filter::Format("flac".to_string())),
+                (
+                    true,
+                    Filter::Text {
+                        column: Some("tags"),
+                        text: "loop".to_string(),
+                        phrase: false,
+                    }
+                ),
+                (false, range("rating", &[(">=", 4.0)])),
+                (false, Filter::Favorite(true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn searches_artist_and_title() {
+        assert_eq!(
+            filters("artist:\"Some Band\" title:intro"),
+            vec![
+                (
+                    false,
+                    Filter::Text {
+                        column: Some("artist"),
+                        text: "Some Band".to_string(),
+                        phrase: true,
+                    }
+                ),
+                (
+                    false,
+                    Filter::Text {
+                        column: Some("title"),
+                        text: "intro".to_string(),
+                        phrase: false,
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_text() {
+        // Unknown fields and unparsable values are searched as plain text,
+        // and an uppercase AND only separates terms
+        assert_eq!(
+            filters("foo:bar AND bpm:fast \"snare roll\" ---"),
+            vec![
+                (
+                    false,
+                    Filter::Text {
+                        column: None,
+                        text: "foo:bar".to_string(),
+                        phrase: false,
+                    }
+                ),
+                (
+                    false,
+                    Filter::Text {
+                        column: None,
+                        text: "bpm:fast".to_string(),
+                        phrase: false,
+                    }
+                ),
+                (
+                    false,
+                    Filter::Text {
+                        column: None,
+                        text: "snare roll".to_string(),
+                        phrase: true,
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn builds_sql() {
+        assert_eq!(SearchQuery::parse("  ").to_sql(), ("1".to_string(), vec![]));
+
+        let (sql, values) = SearchQuery::parse("kick tag:\"909\" -loop bpm:120").to_sql();
+        assert_eq!(
+            sql,
+            "id IN (SELECT rowid FROM samples_fts WHERE samples_fts MATCH ?) AND \
+             NOT coalesce((id IN (SELECT rowid FROM samples_fts WHERE samples_fts MATCH ?)), 0) AND \
+             bpm >= ? AND bpm < ?"
+        );
+        assert_eq!(
+            values,
+            vec![
+                Value::Text("\"kick\"* tags : \"909\"".to_string()),
+                Value::Text("\"loop\"*".to_string()),
+                Value::Real(119.5),
+                Value::Real(120.5),
+            ]
+        );
+    }
+
+    #[test]
+    fn quotes_fts_text() {
+        assert_eq!(fts_expression(None, "a\"b", false), "\"a\"\"b\"*");
+        assert_eq!(
+            fts_expression(Some("name"), "hi hat", true),
+            "name : \"hi hat\""
+        );
+    }
+}
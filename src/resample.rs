@@ -0,0 +1,306 @@
+use std::f64::consts::PI;
+
+/// How playback converts a file's sample rate to the output device's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResampleQuality {
+    /// Linear interpolation: cheap, but dulls the highs and lets some
+    /// aliasing through. Good enough for flicking through a library.
+    Fast,
+    /// Kaiser-windowed sinc interpolation.
+    High,
+}
+
+/// Half the length of the sinc kernel, in zero crossings of the sinc.
+const ZERO_CROSSINGS: usize = 16;
+/// Kernel values tabulated per zero crossing; values in between are
+/// interpolated.
+const TABLE_RESOLUTION: usize = 512;
+/// Kaiser window shape: about 80 dB of stopband attenuation.
+const KAISER_BETA: f64 = 8.0;
+/// Cutoff as a share of the lower of the two Nyquist frequencies, leaving the
+/// filter room to roll off before it.
+const PASSBAND: f64 = 0.95;
+
+/// Converts interleaved audio with a fixed number of channels from one sample
+/// rate to another as it streams through, a block at a time. Audio already
+/// at the target rate passes through untouched.
+pub struct Resampler {
+    channels: usize,
+    from: u64,
+    to: u64,
+    /// `None` for linear interpolation
+    kernel: Option<SincKernel>,
+    /// Input frames an output frame looks ahead and back
+    reach: u64,
+    /// Input frames still needed, interleaved, starting at input frame
+    /// `input_start`
+    input: Vec<f32>,
+    input_start: u64,
+    /// Output frames produced so far
+    produced: u64,
+}
+
+impl Resampler {
+    pub fn new(channels: usize, from: u32, to: u32, quality: ResampleQuality) -> Self {
+        let (from, to) = (from.max(1) as u64, to.max(1) as u64);
+        let kernel = (quality == ResampleQuality::High).then(|| SincKernel::new(from, to));
+        let reach = kernel.as_ref().map_or(1, |k| k.half_width.ceil() as u64);
+        Self {
+            channels: channels.max(1),
+            from,
+            to,
+            kernel,
+            reach,
+            input: Vec::new(),
+            input_start: 0,
+            produced: 0,
+        }
+    }
+
+    /// Feeds `input` and appends every output frame it completes to `output`.
+    /// The last few input frames are held back until the frames after them
+    /// arrive.
+    pub fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        if self.from == self.to {
+            output.extend_from_slice(input);
+            self.produced += (input.len() / self.channels) as u64;
+            return;
+        }
+        self.input.extend_from_slice(input);
+        self.produce(output, false);
+    }
+
+    /// Appends the output frames still held back, as if silence followed.
+    pub fn finish(&mut self, output: &mut Vec<f32>) {
+        if self.from != self.to {
+            self.produce(output, true);
+        }
+    }
+
+    /// The output frame the next input frame fed will land on.
+    pub fn next_output_frame(&self) -> u64 {
+        if self.from == self.to {
+            return self.produced;
+        }
+        let fed = self.input_start + (self.input.len() / self.channels) as u64;
+        (fed * self.to).div_ceil(self.from)
+    }
+
+    fn produce(&mut self, output: &mut Vec<f32>, finishing: bool) {
+        let channels = self.channels;
+        let input_end = self.input_start + (self.input.len() / channels) as u64;
+        if input_end == 0 {
+            return;
+        }
+        let total = (input_end * self.to).div_ceil(self.from);
+
+        loop {
+            // Output frame `n` sits at input frame `n * from / to`, kept exact
+            // as a whole frame and a remainder so long files don't drift
+            let scaled = self.produced * self.from;
+            let (whole, fraction) = (scaled / self.to, (scaled % self.to) as f64 / self.to as f64);
+            let ready = if finishing {
+                self.produced < total
+            } else {
+                whole + self.reach < input_end
+            };
+            if !ready {
+                break;
+            }
+
+            let start = output.len();
+            output.resize(start + channels, 0.0);
+            let frame = &mut output[start..];
+            let input_frame = |k: u64| {
+                let offset = (k - self.input_start) as usize * channels;
+                &self.input[offset..offset + channels]
+            };
+            match &self.kernel {
+                None => {
+                    let a = input_frame(whole.min(input_end - 1));
+                    let b = input_frame((whole + 1).min(input_end - 1));
+                    let fraction = fraction as f32;
+                    for ((value, a), b) in frame.iter_mut().zip(a).zip(b) {
+                        *value = a + (b - a) * fraction;
+                    }
+                }
+                Some(kernel) => {
+                    let first = whole.saturating_sub(self.reach - 1);
+                    let last = (whole + self.reach).min(input_end - 1);
+                    for k in first..=last {
+                        let weight = kernel.at(k as f64 - whole as f64 - fraction);
+                        if weight == 0.0 {
+                            continue;
+                        }
+                        for (value, &sample) in frame.iter_mut().zip(input_frame(k)) {
+                            *value += weight * sample;
+                        }
+                    }
+                }
+            }
+            self.produced += 1;
+        }
+
+        // Drop input no output frame to come reaches back to
+        let next_whole = self.produced * self.from / self.to;
+        let keep_from = next_whole.saturating_sub(self.reach).min(input_end);
+        if keep_from > self.input_start {
+            let drop = (keep_from - self.input_start) as usize;
+            self.input.drain(..drop * channels);
+            self.input_start = keep_from;
+        }
+    }
+}
+
+/// A windowed-sinc low-pass filter, tabulated over its right half.
+struct SincKernel {
+    /// How far the kernel reaches on either side, in input frames
+    half_width: f64,
+    table: Vec<f32>,
+}
+
+impl SincKernel {
+    fn new(from: u64, to: u64) -> Self {
+        // Downsampling moves the cutoff below the output's Nyquist frequency
+        // and widens the kernel by as much
+        let cutoff = PASSBAND * (to as f64 / from as f64).min(1.0);
+        let half_width = ZERO_CROSSINGS as f64 / cutoff;
+        let length = ZERO_CROSSINGS * TABLE_RESOLUTION;
+        let window_scale = bessel_i0(KAISER_BETA);
+        let table = (0..=length + 1)
+            .map(|i| {
+                let t = (i as f64 / length as f64).min(1.0);
+                let x = t * half_width * cutoff;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                let window = bessel_i0(KAISER_BETA * (1.0 - t * t).sqrt()) / window_scale;
+                (cutoff * sinc * window) as f32
+            })
+            .collect();
+        Self { half_width, table }
+    }
+
+    /// The kernel at `x` input frames from its center.
+    fn at(&self, x: f64) -> f32 {
+        let position = x.abs() / self.half_width * (self.table.len() - 2) as f64;
+        let index = position as usize;
+        if index + 1 >= self.table.len() {
+            return 0.0;
+        }
+        let fraction = (position - index as f64) as f32;
+        self.table[index] + (self.table[index + 1] - self.table[index]) * fraction
+    }
+}
+
+/// The zeroth-order modified Bessel function of the first kind, from its
+/// power series.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half = x / 2.0;
+    for k in 1..50 {
+        term *= (half / k as f64) * (half / k as f64);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUALITIES: [ResampleQuality; 2] = [ResampleQuality::Fast, ResampleQuality::High];
+
+    /// Resamples `input` fed `block` frames at a time.
+    fn resample(
+        input: &[f32],
+        channels: usize,
+        from: u32,
+        to: u32,
+        quality: ResampleQuality,
+        block: usize,
+    ) -> Vec<f32> {
+        let mut resampler = Resampler::new(channels, from, to, quality);
+        let mut output = Vec::new();
+        for chunk in input.chunks(block * channels) {
+            resampler.process(chunk, &mut output);
+        }
+        resampler.finish(&mut output);
+        output
+    }
+
+    #[test]
+    fn output_length_follows_the_rate_ratio() {
+        for quality in QUALITIES {
+            for (from, to) in [
+                (44100, 48000),
+                (48000, 44100),
+                (96000, 44100),
+                (22050, 48000),
+            ] {
+                for frames in [1, 999, 10_000] {
+                    let input = vec![0.25; frames * 2];
+                    let output = resample(&input, 2, from, to, quality, 256);
+                    let expected = (frames as u64 * to as u64).div_ceil(from as u64) as usize;
+                    assert_eq!(output.len(), expected * 2, "{} -> {}", from, to);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn block_size_does_not_change_the_output() {
+        let input: Vec<f32> = (0..4000).map(|i| (i as f32 * 0.01).sin()).collect();
+        for quality in QUALITIES {
+            let whole = resample(&input, 1, 44100, 48000, quality, input.len());
+            for block in [1, 7, 512] {
+                assert_eq!(resample(&input, 1, 44100, 48000, quality, block), whole);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_dc_level() {
+        for quality in QUALITIES {
+            for (from, to) in [(44100, 48000), (48000, 44100), (96000, 32000)] {
+                let input: Vec<f32> = [0.5, -0.25].repeat(20_000);
+                let output = resample(&input, 2, from, to, quality, 1024);
+                // Away from the edges, where the kernel runs into silence
+                let frames = output.len() / 2;
+                for frame in output.chunks(2).skip(100).take(frames - 200) {
+                    assert!((frame[0] - 0.5).abs() < 1e-3, "{:?}", frame);
+                    assert!((frame[1] + 0.25).abs() < 1e-3, "{:?}", frame);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn passes_matching_rates_through() {
+        let input: Vec<f32> = (0..300).map(|i| i as f32).collect();
+        for quality in QUALITIES {
+            let mut resampler = Resampler::new(3, 48000, 48000, quality);
+            let mut output = Vec::new();
+            resampler.process(&input, &mut output);
+            resampler.finish(&mut output);
+            assert_eq!(output, input);
+            assert_eq!(resampler.next_output_frame(), 100);
+        }
+    }
+
+    #[test]
+    fn predicts_the_next_output_frame() {
+        let mut resampler = Resampler::new(1, 44100, 48000, ResampleQuality::High);
+        let mut output = Vec::new();
+        resampler.process(&vec![0.0; 44100], &mut output);
+        assert_eq!(resampler.next_output_frame(), 48000);
+        resampler.finish(&mut output);
+        assert_eq!(output.len(), 48000);
+    }
+}
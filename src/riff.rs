@@ -0,0 +1,422 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Chunks larger than this are skipped rather than read into memory; none of
+/// the metadata chunks we care about come close.
+const MAX_CHUNK_SIZE: u32 = 1024 * 1024;
+
+/// The parts of a WAV file's chunk list that symphonia doesn't expose.
+#[derive(Debug, Clone, Default)]
+pub struct WavChunks {
+    pub format: Option<WaveFormat>,
+    /// `LIST/INFO` entries as (chunk id, text), e.g. ("IART", "Some Artist")
+    pub info: Vec<(String, String)>,
+    pub bext: Option<BroadcastExtension>,
+    pub smpl: Option<SamplerChunk>,
+    pub acid: Option<AcidChunk>,
+    /// `cue ` points with their `LIST/adtl` labels merged in
+    pub cues: Vec<CuePoint>,
+}
+
+/// The `fmt ` chunk, as far as channel layout goes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WaveFormat {
+    pub channels: u16,
+    /// Speaker positions of `WAVE_FORMAT_EXTENSIBLE` files, 0 if unassigned
+    pub channel_mask: Option<u32>,
+}
+
+impl WaveFormat {
+    const EXTENSIBLE: u16 = 0xfffe;
+
+    /// Whether the file says which speaker each channel is for.
+    pub fn has_speaker_positions(&self) -> bool {
+        self.channel_mask.is_some_and(|mask| mask != 0)
+    }
+}
+
+/// The Broadcast Wave Format `bext` chunk (EBU Tech 3285).
+#[derive(Debug, Clone, Default)]
+pub struct BroadcastExtension {
+    pub description: String,
+    pub originator: String,
+    pub originator_reference: String,
+    pub origination_date: String,
+    pub origination_time: String,
+    pub time_reference: u64,
+    pub coding_history: String,
+}
+
+/// The `smpl` chunk written by samplers and most loop editors.
+#[derive(Debug, Clone, Default)]
+pub struct SamplerChunk {
+    pub midi_unity_note: u32,
+    /// Fine tuning above the unity note, as a fraction of a semitone
+    pub midi_pitch_fraction: u32,
+    pub loops: Vec<SampleLoop>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SampleLoop {
+    pub loop_type: u32,
+    /// First frame of the loop
+    pub start: u32,
+    /// Last frame of the loop, inclusive
+    pub end: u32,
+    pub play_count: u32,
+}
+
+/// The `acid` chunk written by ACID and compatible loop tools.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AcidChunk {
+    pub flags: u32,
+    pub root_note: u16,
+    pub beats: u32,
+    pub meter_numerator: u16,
+    pub meter_denominator: u16,
+    pub tempo: f32,
+}
+
+impl AcidChunk {
+    pub fn is_one_shot(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+
+    pub fn has_root_note(&self) -> bool {
+        self.flags & 0x02 != 0
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CuePoint {
+    pub id: u32,
+    /// Frame offset into the audio data
+    pub position: u32,
+    pub label: Option<String>,
+}
+
+/// Walks the top-level chunks of a RIFF/WAVE file. Returns `None` for
+/// anything that isn't a little-endian RIFF WAVE file.
+pub fn read_wav_chunks(path: &Path) -> io::Result<Option<WavChunks>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut header = [0u8; 12];
+    if reader.read_exact(&mut header).is_err()
+        || &header[0..4] != b"RIFF"
+        || &header[8..12] != b"WAVE"
+    {
+        return Ok(None);
+    }
+
+    let mut chunks = WavChunks::default();
+    let mut labels = Vec::new();
+    loop {
+        let mut chunk_header = [0u8; 8];
+        if reader.read_exact(&mut chunk_header).is_err() {
+            break;
+        }
+        let id: [u8; 4] = chunk_header[0..4].try_into().unwrap();
+        let size = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap());
+        // Chunks are word aligned
+        let padded_size = size as i64 + (size & 1) as i64;
+
+        let wanted = matches!(
+            &id,
+            b"fmt " | b"LIST" | b"bext" | b"smpl" | b"acid" | b"cue "
+        );
+        if !wanted || size > MAX_CHUNK_SIZE {
+            reader.seek(SeekFrom::Current(padded_size))?;
+            continue;
+        }
+
+        let mut data = vec![0u8; size as usize];
+        if reader.read_exact(&mut data).is_err() {
+            // Truncated file; keep whatever we managed to read
+            break;
+        }
+        if size & 1 == 1 {
+            reader.seek(SeekFrom::Current(1))?;
+        }
+
+        match &id {
+            b"fmt " => chunks.format = parse_fmt(&data),
+            b"LIST" => parse_list(&data, &mut chunks, &mut labels),
+            b"bext" => chunks.bext = parse_bext(&data),
+            b"smpl" => chunks.smpl = parse_smpl(&data),
+            b"acid" => chunks.acid = parse_acid(&data),
+            b"cue " => chunks.cues = parse_cue(&data),
+            _ => {}
+        }
+    }
+
+    // Labels can come before or after the cue chunk they refer to
+    for (id, label) in labels {
+        if let Some(cue) = chunks.cues.iter_mut().find(|cue| cue.id == id) {
+            cue.label = Some(label);
+        }
+    }
+
+    Ok(Some(chunks))
+}
+
+fn parse_list(data: &[u8], chunks: &mut WavChunks, labels: &mut Vec<(u32, String)>) {
+    if data.len() < 4 {
+        return;
+    }
+
+    let is_info = &data[0..4] == b"INFO";
+    let is_adtl = &data[0..4] == b"adtl";
+    if !is_info && !is_adtl {
+        return;
+    }
+
+    let mut offset = 4;
+    while offset + 8 <= data.len() {
+        let id = String::from_utf8_lossy(&data[offset..offset + 4]).to_string();
+        let size = read_u32(data, offset + 4) as usize;
+        let start = offset + 8;
+        let end = (start + size).min(data.len());
+
+        if is_info {
+            let text = fixed_string(&data[start..end]);
+            if !text.is_empty() {
+                chunks.info.push((id, text));
+            }
+        } else if id == "labl" && end >= start + 4 {
+            let cue_id = read_u32(data, start);
+            let text = fixed_string(&data[start + 4..end]);
+            if !text.is_empty() {
+                labels.push((cue_id, text));
+            }
+        }
+        offset = start + size + (size & 1);
+    }
+}
+
+fn parse_fmt(data: &[u8]) -> Option<WaveFormat> {
+    if data.len() < 16 {
+        return None;
+    }
+
+    let format_tag = u16::from_le_bytes(data[0..2].try_into().unwrap());
+    let channel_mask =
+        (format_tag == WaveFormat::EXTENSIBLE && data.len() >= 24).then(|| read_u32(data, 20));
+    Some(WaveFormat {
+        channels: u16::from_le_bytes(data[2..4].try_into().unwrap()),
+        channel_mask,
+    })
+}
+
+fn parse_smpl(data: &[u8]) -> Option<SamplerChunk> {
+    if data.len() < 36 {
+        return None;
+    }
+
+    let loop_count = read_u32(data, 28) as usize;
+    let loops = (0..loop_count)
+        .map(|i| 36 + i * 24)
+        .take_while(|&offset| offset + 24 <= data.len())
+        .map(|offset| SampleLoop {
+            loop_type: read_u32(data, offset + 4),
+            start: read_u32(data, offset + 8),
+            end: read_u32(data, offset + 12),
+            play_count: read_u32(data, offset + 20),
+        })
+        .collect();
+
+    Some(SamplerChunk {
+        midi_unity_note: read_u32(data, 12),
+        midi_pitch_fraction: read_u32(data, 16),
+        loops,
+    })
+}
+
+fn parse_acid(data: &[u8]) -> Option<AcidChunk> {
+    if data.len() < 24 {
+        return None;
+    }
+
+    Some(AcidChunk {
+        flags: read_u32(data, 0),
+        root_note: u16::from_le_bytes(data[4..6].try_into().unwrap()),
+        beats: read_u32(data, 12),
+        meter_denominator: u16::from_le_bytes(data[16..18].try_into().unwrap()),
+        meter_numerator: u16::from_le_bytes(data[18..20].try_into().unwrap()),
+        tempo: f32::from_le_bytes(data[20..24].try_into().unwrap()),
+    })
+}
+
+fn parse_cue(data: &[u8]) -> Vec<CuePoint> {
+    if data.len() < 4 {
+        return Vec::new();
+    }
+
+    let count = read_u32(data, 0) as usize;
+    (0..count)
+        .map(|i| 4 + i * 24)
+        .take_while(|&offset| offset + 24 <= data.len())
+        .map(|offset| CuePoint {
+            id: read_u32(data, offset),
+            // The sample offset field; the "position" field is playlist order
+            position: read_u32(data, offset + 20),
+            label: None,
+        })
+        .collect()
+}
+
+fn parse_bext(data: &[u8]) -> Option<BroadcastExtension> {
+    // Description, originator, reference, date, time, time reference, version, UMID,
+    // loudness fields and reserved bytes come before the coding history
+    const CODING_HISTORY_OFFSET: usize = 602;
+    if data.len() < 346 {
+        return None;
+    }
+
+    let time_reference_low = read_u32(data, 338) as u64;
+    let time_reference_high = read_u32(data, 342) as u64;
+
+    Some(BroadcastExtension {
+        description: fixed_string(&data[0..256]),
+        originator: fixed_string(&data[256..288]),
+        originator_reference: fixed_string(&data[288..320]),
+        origination_date: fixed_string(&data[320..330]),
+        origination_time: fixed_string(&data[330..338]),
+        time_reference: time_reference_high << 32 | time_reference_low,
+        coding_history: data
+            .get(CODING_HISTORY_OFFSET..)
+            .map(fixed_string)
+            .unwrap_or_default(),
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Decodes a NUL-padded text field, tolerating non-UTF-8 bytes.
+fn fixed_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_text(data: &mut [u8], offset: usize, text: &str) {
+        data[offset..offset + text.len()].copy_from_slice(text.as_bytes());
+    }
+
+    #[test]
+    fn parses_smpl_loops() {
+        let mut data = vec![0u8; 36 + 2 * 24];
+        put_u32(&mut data, 12, 60);
+        put_u32(&mut data, 16, 0x8000_0000);
+        // One more loop than the chunk holds
+        put_u32(&mut data, 28, 3);
+        for (i, (start, end)) in [(100, 199), (1000, 4999)].into_iter().enumerate() {
+            let offset = 36 + i * 24;
+            put_u32(&mut data, offset, i as u32);
+            put_u32(&mut data, offset + 4, 1);
+            put_u32(&mut data, offset + 8, start);
+            put_u32(&mut data, offset + 12, end);
+            put_u32(&mut data, offset + 16, 7);
+            put_u32(&mut data, offset + 20, i as u32 * 2);
+        }
+
+        let smpl = parse_smpl(&data).unwrap();
+        assert_eq!(smpl.midi_unity_note, 60);
+        assert_eq!(smpl.midi_pitch_fraction, 0x8000_0000);
+        let loops: Vec<_> = smpl
+            .loops
+            .iter()
+            .map(|l| (l.loop_type, l.start, l.end, l.play_count))
+            .collect();
+        assert_eq!(loops, vec![(1, 100, 199, 0), (1, 1000, 4999, 2)]);
+
+        assert!(parse_smpl(&data[..35]).is_none());
+    }
+
+    #[test]
+    fn reads_cue_sample_offsets() {
+        let mut data = vec![0u8; 4 + 2 * 24];
+        put_u32(&mut data, 0, 2);
+        for (i, (id, offset_frames)) in [(1, 4410), (2, 88200)].into_iter().enumerate() {
+            let offset = 4 + i * 24;
+            put_u32(&mut data, offset, id);
+            // Playlist position, which isn't where the cue is
+            put_u32(&mut data, offset + 4, 9);
+            put_text(&mut data, offset + 8, "data");
+            put_u32(&mut data, offset + 20, offset_frames);
+        }
+
+        let cues: Vec<_> = parse_cue(&data)
+            .iter()
+            .map(|cue| (cue.id, cue.position))
+            .collect();
+        assert_eq!(cues, vec![(1, 4410), (2, 88200)]);
+        assert_eq!(parse_cue(&data[..27]).len(), 0);
+    }
+
+    #[test]
+    fn parses_bext_fields() {
+        let mut data = vec![0u8; 602 + 16];
+        put_text(&mut data, 0, "Door slam");
+        put_text(&mut data, 256, "Recorder");
+        put_text(&mut data, 288, "REF123");
+        put_text(&mut data, 320, "2024-05-01");
+        put_text(&mut data, 330, "12:34:56");
+        put_u32(&mut data, 338, 0x2345_6789);
+        put_u32(&mut data, 342, 0x1);
+        put_text(&mut data, 602, "A=PCM,F=48000");
+
+        let bext = parse_bext(&data).unwrap();
+        assert_eq!(bext.description, "Door slam");
+        assert_eq!(bext.originator, "Recorder");
+        assert_eq!(bext.originator_reference, "REF123");
+        assert_eq!(bext.origination_date, "2024-05-01");
+        assert_eq!(bext.origination_time, "12:34:56");
+        assert_eq!(bext.time_reference, 0x1_2345_6789);
+        assert_eq!(bext.coding_history, "A=PCM,F=48000");
+
+        // Version 0 chunks may end before the coding history
+        assert_eq!(parse_bext(&data[..346]).unwrap().coding_history, "");
+        assert!(parse_bext(&data[..345]).is_none());
+    }
+
+    #[test]
+    fn merges_cue_labels() {
+        let mut cue = vec![0u8; 4 + 24];
+        put_u32(&mut cue, 0, 1);
+        put_u32(&mut cue, 4, 7);
+        put_u32(&mut cue, 24, 500);
+
+        // An odd-sized label, padded to a word boundary
+        let mut list = b"adtllabl".to_vec();
+        list.extend(9u32.to_le_bytes());
+        list.extend(7u32.to_le_bytes());
+        list.extend(b"Hit\0\0");
+        list.push(0);
+
+        let mut file = b"RIFF\0\0\0\0WAVE".to_vec();
+        for (id, data) in [(b"LIST", &list), (b"cue ", &cue)] {
+            file.extend(id);
+            file.extend((data.len() as u32).to_le_bytes());
+            file.extend(data);
+        }
+        let path = std::env::temp_dir().join(format!("riff-test-{}.wav", std::process::id()));
+        std::fs::write(&path, &file).unwrap();
+        let chunks = read_wav_chunks(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let cues = chunks.unwrap().unwrap().cues;
+        assert_eq!(cues.len(), 1);
+        assert_eq!(cues[0].position, 500);
+        assert_eq!(cues[0].label.as_deref(), Some("Hit"));
+    }
+}
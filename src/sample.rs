@@ -1,9 +1,367 @@
+use std::cmp::Ordering;
+
+use crate::loudness::Loudness;
+use crate::music::{camelot, camelot_position, note_name, pitch_name};
+
 #[derive(Debug, Clone)]
 pub struct Sample {
     pub id: isize,
+    pub root_id: Option<isize>,
     pub path: String,
     pub name: String,
     pub format: String,
-    pub sample_rate: u32,
+    pub sample_rate: Option<u32>,
     pub size: u64,
+    /// Last modification time in milliseconds since the Unix epoch
+    pub modified: i64,
+    pub content_hash: Option<String>,
+    pub missing: bool,
+    /// Length in seconds
+    pub duration: Option<f64>,
+    pub channels: Option<u32>,
+    pub channel_layout: Option<String>,
+    pub bit_depth: Option<u32>,
+    pub frames: Option<u64>,
+    /// Average bitrate in bits per second, only for lossy codecs
+    pub bitrate: Option<u32>,
+    pub container: Option<String>,
+    /// Embedded loop region in frames, end exclusive
+    pub loop_start: Option<u64>,
+    pub loop_end: Option<u64>,
+    /// MIDI note number the sample was recorded at
+    pub root_note: Option<u8>,
+    /// Tempo in beats per minute as stored in the file
+    pub tempo: Option<f64>,
+    /// Musical key from the file's tags, in `music::normalize_key` form
+    pub musical_key: Option<String>,
+    pub favorite: bool,
+    /// 0 (unrated) to 5 stars
+    pub rating: u8,
+    pub color_label: Option<ColorLabel>,
+    /// Kept out of the browser, e.g. after being picked as a duplicate to hide
+    pub hidden: bool,
+    /// Tempo found by the analysis pass: the file's own tempo, a hint in its
+    /// name, or else detected from the audio
+    pub bpm: Option<f64>,
+    /// 1 for a tempo the file or its name states, otherwise how clearly the
+    /// detected beat stood out, from 0 to 1
+    pub bpm_confidence: Option<f32>,
+    /// Key found by the analysis pass, in `music::normalize_key` form: the
+    /// file's own key, or else estimated from the audio
+    pub estimated_key: Option<String>,
+    /// 1 for a key the file states, otherwise how well the audio matched the
+    /// key's profile, from 0 to 1
+    pub key_confidence: Option<f32>,
+    /// Peak, RMS and LUFS levels measured by the analysis pass
+    pub loudness: Option<Loudness>,
+    /// Fundamental frequency in Hz estimated by the analysis pass
+    pub pitch: Option<f64>,
+    /// How clearly pitched the sample is, from 0 to 1
+    pub pitch_confidence: Option<f32>,
+}
+
+impl Sample {
+    /// Carries over the fields the user sets by hand, which re-probing a
+    /// file must not reset.
+    pub fn keep_user_fields(&mut self, stored: &Sample) {
+        self.favorite = stored.favorite;
+        self.rating = stored.rating;
+        self.color_label = stored.color_label;
+        self.hidden = stored.hidden;
+    }
+
+    /// Carries over the analysis results of a file that moved without
+    /// changing, so it doesn't have to be analyzed again.
+    pub fn keep_analysis(&mut self, stored: &Sample) {
+        self.bpm = stored.bpm;
+        self.bpm_confidence = stored.bpm_confidence;
+        self.estimated_key = stored.estimated_key.clone();
+        self.key_confidence = stored.key_confidence;
+        self.loudness = stored.loudness;
+        self.pitch = stored.pitch;
+        self.pitch_confidence = stored.pitch_confidence;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ColorLabel {
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Blue,
+    Purple,
+    Gray,
+}
+
+impl ColorLabel {
+    pub const ALL: [ColorLabel; 7] = [
+        ColorLabel::Red,
+        ColorLabel::Orange,
+        ColorLabel::Yellow,
+        ColorLabel::Green,
+        ColorLabel::Blue,
+        ColorLabel::Purple,
+        ColorLabel::Gray,
+    ];
+
+    /// Name used in the database and in `color:` queries.
+    pub fn key(self) -> &'static str {
+        match self {
+            ColorLabel::Red => "red",
+            ColorLabel::Orange => "orange",
+            ColorLabel::Yellow => "yellow",
+            ColorLabel::Green => "green",
+            ColorLabel::Blue => "blue",
+            ColorLabel::Purple => "purple",
+            ColorLabel::Gray => "gray",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|label| label.key() == key)
+    }
+}
+
+/// A marker embedded in the file, e.g. a WAV `cue ` point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CueMarker {
+    /// Frame offset into the audio data
+    pub frame: u64,
+    pub label: Option<String>,
+}
+
+/// A user-assigned tag, or one the classifier assigned to a sample.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub id: isize,
+    pub name: String,
+    /// Set on a sample's tag that the classifier assigned rather than the
+    /// user, to its confidence
+    pub auto_confidence: Option<f32>,
+}
+
+/// A named set of samples, or a folder grouping collections and other
+/// folders.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collection {
+    pub id: isize,
+    pub name: String,
+    pub parent_id: Option<isize>,
+    pub is_folder: bool,
+    /// Search query of a smart collection, whose samples are whatever
+    /// currently matches it
+    pub query: Option<String>,
+}
+
+impl Collection {
+    pub fn is_smart(&self) -> bool {
+        self.query.is_some()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LibraryRoot {
+    pub id: isize,
+    pub path: String,
+    pub follow_symlinks: bool,
+    pub ignore_patterns: Vec<String>,
+}
+
+/// Columns of the sample table, in display order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SampleColumn {
+    Name,
+    Path,
+    Format,
+    Container,
+    SampleRate,
+    BitDepth,
+    Channels,
+    Duration,
+    Frames,
+    Bitrate,
+    Size,
+    RootNote,
+    Pitch,
+    Tempo,
+    Bpm,
+    Key,
+    Camelot,
+    Lufs,
+    TruePeak,
+    Rms,
+    Favorite,
+    Rating,
+    ColorLabel,
+}
+
+impl SampleColumn {
+    pub const ALL: [SampleColumn; 23] = [
+        SampleColumn::Favorite,
+        SampleColumn::ColorLabel,
+        SampleColumn::Name,
+        SampleColumn::Rating,
+        SampleColumn::Path,
+        SampleColumn::Format,
+        SampleColumn::Container,
+        SampleColumn::SampleRate,
+        SampleColumn::BitDepth,
+        SampleColumn::Channels,
+        SampleColumn::Duration,
+        SampleColumn::Frames,
+        SampleColumn::Bitrate,
+        SampleColumn::Size,
+        SampleColumn::RootNote,
+        SampleColumn::Pitch,
+        SampleColumn::Tempo,
+        SampleColumn::Bpm,
+        SampleColumn::Key,
+        SampleColumn::Camelot,
+        SampleColumn::Lufs,
+        SampleColumn::TruePeak,
+        SampleColumn::Rms,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            SampleColumn::Name => "Name",
+            SampleColumn::Path => "Path",
+            SampleColumn::Format => "Format",
+            SampleColumn::Container => "Container",
+            SampleColumn::SampleRate => "Sample Rate",
+            SampleColumn::BitDepth => "Bit Depth",
+            SampleColumn::Channels => "Channels",
+            SampleColumn::Duration => "Duration",
+            SampleColumn::Frames => "Frames",
+            SampleColumn::Bitrate => "Bitrate",
+            SampleColumn::Size => "Size",
+            SampleColumn::RootNote => "Root",
+            SampleColumn::Pitch => "Pitch",
+            SampleColumn::Tempo => "Tempo",
+            SampleColumn::Bpm => "BPM",
+            SampleColumn::Key => "Key",
+            SampleColumn::Camelot => "Camelot",
+            SampleColumn::Lufs => "LUFS",
+            SampleColumn::TruePeak => "Peak",
+            SampleColumn::Rms => "RMS",
+            SampleColumn::Favorite => "♥",
+            SampleColumn::Rating => "Rating",
+            SampleColumn::ColorLabel => "Label",
+        }
+    }
+
+    /// Stable identifier used to persist the column in settings.
+    pub fn key(self) -> &'static str {
+        match self {
+            SampleColumn::Name => "name",
+            SampleColumn::Path => "path",
+            SampleColumn::Format => "format",
+            SampleColumn::Container => "container",
+            SampleColumn::SampleRate => "sample_rate",
+            SampleColumn::BitDepth => "bit_depth",
+            SampleColumn::Channels => "channels",
+            SampleColumn::Duration => "duration",
+            SampleColumn::Frames => "frames",
+            SampleColumn::Bitrate => "bitrate",
+            SampleColumn::Size => "size",
+            SampleColumn::RootNote => "root_note",
+            SampleColumn::Pitch => "pitch",
+            SampleColumn::Tempo => "tempo",
+            SampleColumn::Bpm => "bpm",
+            SampleColumn::Key => "key",
+            SampleColumn::Camelot => "camelot",
+            SampleColumn::Lufs => "lufs",
+            SampleColumn::TruePeak => "true_peak",
+            SampleColumn::Rms => "rms",
+            SampleColumn::Favorite => "favorite",
+            SampleColumn::Rating => "rating",
+            SampleColumn::ColorLabel => "color_label",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|column| column.key() == key)
+    }
+
+    pub fn cell_text(self, sample: &Sample) -> String {
+        fn or_blank<T: ToString>(value: Option<T>) -> String {
+            value.map(|v| v.to_string()).unwrap_or_default()
+        }
+
+        match self {
+            SampleColumn::Name => sample.name.clone(),
+            SampleColumn::Path => sample.path.clone(),
+            SampleColumn::Format => sample.format.clone(),
+            SampleColumn::Container => or_blank(sample.container.as_ref()),
+            SampleColumn::SampleRate => or_blank(sample.sample_rate),
+            SampleColumn::BitDepth => or_blank(sample.bit_depth),
+            SampleColumn::Channels => match (&sample.channels, &sample.channel_layout) {
+                (Some(count), Some(layout)) => format!("{} ({})", count, layout),
+                (Some(count), None) => count.to_string(),
+                (None, _) => String::new(),
+            },
+            SampleColumn::Duration => or_blank(sample.duration.map(|d| format!("{:.2}s", d))),
+            SampleColumn::Frames => or_blank(sample.frames),
+            SampleColumn::Bitrate => or_blank(sample.bitrate.map(|b| format!("{} kbps", b / 1000))),
+            SampleColumn::Size => sample.size.to_string(),
+            SampleColumn::RootNote => or_blank(sample.root_note.map(note_name)),
+            SampleColumn::Pitch => or_blank(sample.pitch.and_then(pitch_name)),
+            SampleColumn::Tempo => or_blank(sample.tempo.map(|t| format!("{:.1}", t))),
+            SampleColumn::Bpm => or_blank(sample.bpm.map(|t| format!("{:.1}", t))),
+            SampleColumn::Key => or_blank(sample.estimated_key.as_ref()),
+            SampleColumn::Camelot => or_blank(sample.estimated_key.as_deref().and_then(camelot)),
+            SampleColumn::Lufs => or_blank(sample.loudness.map(|l| format!("{:.1}", l.integrated))),
+            SampleColumn::TruePeak => {
+                or_blank(sample.loudness.map(|l| format!("{:.1} dB", l.true_peak)))
+            }
+            SampleColumn::Rms => or_blank(sample.loudness.map(|l| format!("{:.1} dB", l.rms))),
+            SampleColumn::Favorite => if sample.favorite { "♥" } else { "" }.to_string(),
+            SampleColumn::Rating => "★".repeat(sample.rating as usize),
+            SampleColumn::ColorLabel => or_blank(sample.color_label.map(ColorLabel::key)),
+        }
+    }
+
+    /// Orders two samples by this column. Missing values sort first.
+    pub fn compare(self, a: &Sample, b: &Sample) -> Ordering {
+        match self {
+            SampleColumn::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            SampleColumn::Path => a.path.cmp(&b.path),
+            SampleColumn::Format => a.format.cmp(&b.format),
+            SampleColumn::Container => a.container.cmp(&b.container),
+            SampleColumn::SampleRate => a.sample_rate.cmp(&b.sample_rate),
+            SampleColumn::BitDepth => a.bit_depth.cmp(&b.bit_depth),
+            SampleColumn::Channels => a.channels.cmp(&b.channels),
+            SampleColumn::Duration => a
+                .duration
+                .partial_cmp(&b.duration)
+                .unwrap_or(Ordering::Equal),
+            SampleColumn::Frames => a.frames.cmp(&b.frames),
+            SampleColumn::Bitrate => a.bitrate.cmp(&b.bitrate),
+            SampleColumn::Size => a.size.cmp(&b.size),
+            SampleColumn::RootNote => a.root_note.cmp(&b.root_note),
+            SampleColumn::Pitch => a.pitch.partial_cmp(&b.pitch).unwrap_or(Ordering::Equal),
+            SampleColumn::Tempo => a.tempo.partial_cmp(&b.tempo).unwrap_or(Ordering::Equal),
+            SampleColumn::Bpm => a.bpm.partial_cmp(&b.bpm).unwrap_or(Ordering::Equal),
+            SampleColumn::Key => a.estimated_key.cmp(&b.estimated_key),
+            SampleColumn::Camelot => {
+                let position =
+                    |sample: &Sample| sample.estimated_key.as_deref().and_then(camelot_position);
+                position(a).cmp(&position(b))
+            }
+            SampleColumn::Lufs | SampleColumn::TruePeak | SampleColumn::Rms => {
+                let level = |sample: &Sample| {
+                    sample.loudness.map(|l| match self {
+                        SampleColumn::Lufs => l.integrated,
+                        SampleColumn::TruePeak => l.true_peak,
+                        _ => l.rms,
+                    })
+                };
+                level(a).partial_cmp(&level(b)).unwrap_or(Ordering::Equal)
+            }
+            SampleColumn::Favorite => a.favorite.cmp(&b.favorite),
+            SampleColumn::Rating => a.rating.cmp(&b.rating),
+            SampleColumn::ColorLabel => a.color_label.cmp(&b.color_label),
+        }
+    }
 }
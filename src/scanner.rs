@@ -0,0 +1,606 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::UNIX_EPOCH;
+
+use rusqlite::Connection;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::db::{
+    insert_sample, load_sample_by_path, load_samples, load_samples_under, replace_sample_cues,
+    replace_sample_metadata, reset_analysis, set_sample_missing, update_sample,
+    update_search_index,
+};
+use crate::probe::{EmbeddedData, ProbedFile, process_file};
+use crate::sample::{LibraryRoot, Sample};
+
+pub const AUDIO_EXTENSIONS: [&str; 4] = ["wav", "flac", "mp3", "ogg"];
+
+pub fn is_audio_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Walks a library root recursively and returns every audio file that is not
+/// excluded by one of the root's ignore patterns. Setting `cancel` stops the
+/// walk early with what was found so far.
+pub fn scan_root(root: &LibraryRoot, cancel: &AtomicBool) -> Vec<PathBuf> {
+    scan_dir(root, Path::new(&root.path), cancel)
+}
+
+/// Like `scan_root`, but only walks `dir`, which must lie inside `root`.
+pub fn scan_dir(root: &LibraryRoot, dir: &Path, cancel: &AtomicBool) -> Vec<PathBuf> {
+    let root_path = Path::new(&root.path);
+    let walker = WalkDir::new(dir)
+        .follow_links(root.follow_symlinks)
+        .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || !is_ignored(root, root_path, entry));
+
+    let mut files = Vec::new();
+    for entry in walker {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+        match entry {
+            Ok(entry) => {
+                if entry.file_type().is_file() && is_audio_file(entry.path()) {
+                    files.push(entry.into_path());
+                }
+            }
+            Err(error) => {
+                // Unreadable directories and symlink loops shouldn't abort the whole scan
+                println!(
+                    "Error: skipping entry while scanning {}: {}",
+                    root.path, error
+                );
+            }
+        }
+    }
+
+    files
+}
+
+/// Returns the innermost root containing `path`.
+pub fn root_for_path<'a>(roots: &'a [LibraryRoot], path: &Path) -> Option<&'a LibraryRoot> {
+    roots
+        .iter()
+        .filter(|root| path.starts_with(&root.path))
+        .max_by_key(|root| root.path.len())
+}
+
+/// Checks a walked entry below `root_path` against the root's ignore
+/// patterns.
+pub fn is_ignored(root: &LibraryRoot, root_path: &Path, entry: &DirEntry) -> bool {
+    let name = entry.file_name().to_string_lossy();
+    let relative = relative_path(root_path, entry.path());
+    matches_ignore(root, &name, &relative)
+}
+
+/// Checks `path` and every folder between it and its root against the root's
+/// ignore patterns.
+pub fn is_path_ignored(root: &LibraryRoot, path: &Path) -> bool {
+    let root_path = Path::new(&root.path);
+    let Ok(relative) = path.strip_prefix(root_path) else {
+        return false;
+    };
+
+    let mut prefix = PathBuf::new();
+    relative.components().any(|component| {
+        prefix.push(component);
+        let name = component.as_os_str().to_string_lossy();
+        matches_ignore(root, &name, &relative_path(Path::new(""), &prefix))
+    })
+}
+
+fn relative_path(root_path: &Path, path: &Path) -> String {
+    path.strip_prefix(root_path)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+fn matches_ignore(root: &LibraryRoot, name: &str, relative: &str) -> bool {
+    root.ignore_patterns.iter().any(|pattern| {
+        // Patterns without a slash match the file or folder name anywhere in the tree,
+        // like .gitignore
+        if pattern.contains('/') {
+            glob_match(pattern.trim_start_matches('/'), relative)
+        } else {
+            glob_match(pattern, name)
+        }
+    })
+}
+
+/// Minimal glob matcher supporting `*` (within a path segment), `**` (across
+/// segments) and `?`.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    fn matches(p: &[u8], t: &[u8]) -> bool {
+        match p.first() {
+            None => t.is_empty(),
+            Some(b'*') if p.get(1) == Some(&b'*') => {
+                let rest = p[2..].strip_prefix(b"/").unwrap_or(&p[2..]);
+                (0..=t.len()).any(|i| matches(rest, &t[i..]))
+            }
+            Some(b'*') => {
+                let rest = &p[1..];
+                for i in 0..=t.len() {
+                    if matches(rest, &t[i..]) {
+                        return true;
+                    }
+                    if t.get(i) == Some(&b'/') {
+                        break;
+                    }
+                }
+                false
+            }
+            Some(b'?') => !t.is_empty() && t[0] != b'/' && matches(&p[1..], &t[1..]),
+            Some(&c) => {
+                t.first().is_some_and(|&tc| tc.eq_ignore_ascii_case(&c))
+                    && matches(&p[1..], &t[1..])
+            }
+        }
+    }
+
+    matches(pattern.as_bytes(), text.as_bytes())
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScanOptions {
+    /// Hash file contents so edits that keep size and mtime are caught and
+    /// moved files can be matched reliably
+    pub hash_contents: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScanSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub moved: usize,
+    pub missing: usize,
+    pub unchanged: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum ImportEvent {
+    /// Periodic update while walking the roots, before the total is known
+    Scanning {
+        found: usize,
+    },
+    /// Walking finished; `total` files will be checked
+    Started {
+        total: usize,
+    },
+    Progress {
+        done: usize,
+        current: PathBuf,
+    },
+    /// A sample row was added, changed or moved and should be (re)displayed
+    Indexed(Box<Sample>),
+    Missing(isize),
+    Error {
+        path: PathBuf,
+        message: String,
+    },
+    Finished {
+        summary: ScanSummary,
+        cancelled: bool,
+        /// Why the import stopped, if it failed
+        error: Option<String>,
+    },
+}
+
+enum ProbeTask {
+    Known(isize, PathBuf, Box<Sample>),
+    New(isize, PathBuf),
+}
+
+enum ProbeResult {
+    /// The row needs updating; embedded data is only present if the file was re-probed
+    Changed(Sample, Option<EmbeddedData>),
+    Unchanged,
+    New(ProbedFile),
+}
+
+/// Rescans every root and reconciles the results with the `samples` table,
+/// reporting progress through `report`.
+///
+/// Unchanged files (same size and mtime, and hash when enabled) are skipped,
+/// changed files are re-probed in place, files that disappeared are marked
+/// missing, and new files that match a vanished row are treated as moves so the
+/// existing row (and its id) is reused. Probing runs on a pool of worker
+/// threads while database writes stay on the calling thread. Setting `cancel`
+/// stops the scan early without marking anything missing.
+pub fn import_library(
+    conn: &Connection,
+    roots: &[LibraryRoot],
+    options: ScanOptions,
+    cancel: &AtomicBool,
+    report: &mut dyn FnMut(ImportEvent),
+) -> Result<ScanSummary, Box<dyn std::error::Error>> {
+    let mut summary = ScanSummary::default();
+    let mut known: HashMap<String, Sample> = load_samples(conn)?
+        .into_iter()
+        .map(|sample| (sample.path.clone(), sample))
+        .collect();
+
+    let mut tasks = Vec::new();
+    for root in roots {
+        let files = scan_root(root, cancel);
+        if cancel.load(Ordering::Relaxed) {
+            report(ImportEvent::Finished {
+                summary,
+                cancelled: true,
+                error: None,
+            });
+            return Ok(summary);
+        }
+
+        for path in files {
+            let path_str = path.to_string_lossy().to_string();
+            match known.remove(&path_str) {
+                Some(stored) => tasks.push(ProbeTask::Known(root.id, path, Box::new(stored))),
+                None => tasks.push(ProbeTask::New(root.id, path)),
+            }
+            if tasks.len() % 256 == 0 {
+                report(ImportEvent::Scanning { found: tasks.len() });
+            }
+        }
+    }
+
+    // Whatever is left in `known` was not found on disk; new files may be those
+    // samples under a different path
+    let mut vanished: Vec<Sample> = known.into_values().collect();
+
+    let total = tasks.len();
+    report(ImportEvent::Started { total });
+
+    let next_task = AtomicUsize::new(0);
+    let worker_count = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(total.max(1));
+    let (sender, receiver) = mpsc::channel();
+
+    let result = std::thread::scope(|scope| -> Result<(), Box<dyn std::error::Error>> {
+        for _ in 0..worker_count {
+            let sender = sender.clone();
+            let tasks = &tasks;
+            let next_task = &next_task;
+            scope.spawn(move || {
+                loop {
+                    let idx = next_task.fetch_add(1, Ordering::Relaxed);
+                    if idx >= tasks.len() || cancel.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let result = match &tasks[idx] {
+                        ProbeTask::Known(root_id, path, stored) => {
+                            refresh_sample(path, (**stored).clone(), *root_id, options).map(
+                                |changed| match changed {
+                                    Some((sample, embedded)) => {
+                                        ProbeResult::Changed(sample, embedded)
+                                    }
+                                    None => ProbeResult::Unchanged,
+                                },
+                            )
+                        }
+                        ProbeTask::New(root_id, path) => {
+                            probe_new_file(path, *root_id, options).map(ProbeResult::New)
+                        }
+                    };
+                    let result = result.map_err(|error| error.to_string());
+                    if sender.send((idx, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (done, (idx, result)) in (1..).zip(receiver) {
+            let path = match &tasks[idx] {
+                ProbeTask::Known(_, path, _) | ProbeTask::New(_, path) => path,
+            };
+            report(ImportEvent::Progress {
+                done,
+                current: path.clone(),
+            });
+
+            match result {
+                Ok(ProbeResult::Unchanged) => summary.unchanged += 1,
+                Ok(ProbeResult::Changed(sample, embedded)) => {
+                    store_changed_sample(conn, sample, embedded, &mut summary, report)?;
+                }
+                Ok(ProbeResult::New(file_meta)) => {
+                    store_new_file(conn, file_meta, &mut vanished, &mut summary, report)?;
+                }
+                Err(message) => {
+                    report(ImportEvent::Error {
+                        path: path.clone(),
+                        message,
+                    });
+                }
+            }
+        }
+        Ok(())
+    });
+    result?;
+
+    let cancelled = cancel.load(Ordering::Relaxed);
+    if !cancelled {
+        mark_missing(conn, &vanished, &mut summary, report)?;
+    }
+
+    report(ImportEvent::Finished {
+        summary,
+        cancelled,
+        error: None,
+    });
+    Ok(summary)
+}
+
+/// Reconciles a batch of individually changed paths, as reported by the
+/// filesystem watcher, without walking the whole library.
+///
+/// Paths may be files or folders that were created, modified, removed or moved;
+/// a removal and a creation in the same batch that look like the same file are
+/// treated as a move.
+pub fn reconcile_paths(
+    conn: &Connection,
+    roots: &[LibraryRoot],
+    options: ScanOptions,
+    changed: &[PathBuf],
+    report: &mut dyn FnMut(ImportEvent),
+) -> Result<ScanSummary, Box<dyn std::error::Error>> {
+    let mut summary = ScanSummary::default();
+    let mut present = Vec::new();
+    let mut vanished: Vec<Sample> = Vec::new();
+
+    for path in changed {
+        let Some(root) = root_for_path(roots, path) else {
+            continue;
+        };
+
+        if path.is_dir() {
+            if !is_path_ignored(root, path) {
+                // A partial walk would mark the rest missing, so it always finishes
+                let files = scan_dir(root, path, &AtomicBool::new(false));
+                present.extend(files.into_iter().map(|file| (root.id, file)));
+            }
+        } else if path.is_file() {
+            if is_audio_file(path) && !is_path_ignored(root, path) {
+                present.push((root.id, path.clone()));
+            }
+        } else {
+            // Gone: either a single file or a whole folder of samples
+            for sample in load_samples_under(conn, &path.to_string_lossy())? {
+                if !vanished.iter().any(|old| old.id == sample.id) {
+                    vanished.push(sample);
+                }
+            }
+        }
+    }
+
+    for (root_id, path) in present {
+        let result = match load_sample_by_path(conn, &path.to_string_lossy())? {
+            Some(stored) => {
+                refresh_sample(&path, stored, root_id, options).map(|changed| match changed {
+                    Some((sample, embedded)) => ProbeResult::Changed(sample, embedded),
+                    None => ProbeResult::Unchanged,
+                })
+            }
+            None => probe_new_file(&path, root_id, options).map(ProbeResult::New),
+        };
+
+        match result {
+            Ok(ProbeResult::Unchanged) => summary.unchanged += 1,
+            Ok(ProbeResult::Changed(sample, embedded)) => {
+                store_changed_sample(conn, sample, embedded, &mut summary, report)?;
+            }
+            Ok(ProbeResult::New(file_meta)) => {
+                store_new_file(conn, file_meta, &mut vanished, &mut summary, report)?;
+            }
+            Err(error) => {
+                report(ImportEvent::Error {
+                    path,
+                    message: error.to_string(),
+                });
+            }
+        }
+    }
+
+    mark_missing(conn, &vanished, &mut summary, report)?;
+    Ok(summary)
+}
+
+fn store_changed_sample(
+    conn: &Connection,
+    sample: Sample,
+    embedded: Option<EmbeddedData>,
+    summary: &mut ScanSummary,
+    report: &mut dyn FnMut(ImportEvent),
+) -> rusqlite::Result<()> {
+    update_sample(conn, &sample)?;
+    // The file's audio may have changed, so its analysis is out of date
+    reset_analysis(conn, sample.id)?;
+    if let Some(embedded) = embedded {
+        store_embedded_data(conn, sample.id, &embedded)?;
+    }
+    update_search_index(conn, sample.id)?;
+    summary.updated += 1;
+    report(ImportEvent::Indexed(Box::new(sample)));
+    Ok(())
+}
+
+/// Inserts a newly found file, or reuses the row of a vanished sample if the
+/// file is that sample after a move; see `is_same_file`.
+fn store_new_file(
+    conn: &Connection,
+    probed: ProbedFile,
+    vanished: &mut Vec<Sample>,
+    summary: &mut ScanSummary,
+    report: &mut dyn FnMut(ImportEvent),
+) -> rusqlite::Result<()> {
+    let ProbedFile {
+        sample: mut file_meta,
+        embedded,
+    } = probed;
+
+    // Vanished samples with a hash are only matched by content
+    if file_meta.content_hash.is_none()
+        && vanished
+            .iter()
+            .any(|old| old.size == file_meta.size && old.content_hash.is_some())
+    {
+        file_meta.content_hash = hash_file(Path::new(&file_meta.path)).ok();
+    }
+
+    if let Some(idx) = vanished
+        .iter()
+        .position(|old| is_same_file(old, &file_meta))
+    {
+        let old = vanished.swap_remove(idx);
+        file_meta.id = old.id;
+        file_meta.keep_user_fields(&old);
+        file_meta.keep_analysis(&old);
+        file_meta.content_hash = file_meta.content_hash.or(old.content_hash);
+        update_sample(conn, &file_meta)?;
+        summary.moved += 1;
+    } else {
+        file_meta.id = insert_sample(conn, &file_meta)?;
+        summary.added += 1;
+    }
+    store_embedded_data(conn, file_meta.id, &embedded)?;
+    update_search_index(conn, file_meta.id)?;
+    report(ImportEvent::Indexed(Box::new(file_meta)));
+    Ok(())
+}
+
+fn store_embedded_data(
+    conn: &Connection,
+    sample_id: isize,
+    embedded: &EmbeddedData,
+) -> rusqlite::Result<()> {
+    replace_sample_metadata(conn, sample_id, &embedded.metadata)?;
+    replace_sample_cues(conn, sample_id, &embedded.cues)
+}
+
+fn mark_missing(
+    conn: &Connection,
+    vanished: &[Sample],
+    summary: &mut ScanSummary,
+    report: &mut dyn FnMut(ImportEvent),
+) -> rusqlite::Result<()> {
+    for sample in vanished.iter().filter(|sample| !sample.missing) {
+        set_sample_missing(conn, sample.id, true)?;
+        summary.missing += 1;
+        report(ImportEvent::Missing(sample.id));
+    }
+    Ok(())
+}
+
+fn probe_new_file(
+    path: &Path,
+    root_id: isize,
+    options: ScanOptions,
+) -> Result<ProbedFile, Box<dyn std::error::Error>> {
+    let mut probed = process_file(path)?;
+    probed.sample.root_id = Some(root_id);
+    if options.hash_contents {
+        probed.sample.content_hash = Some(hash_file(path)?);
+    }
+    Ok(probed)
+}
+
+type RefreshedSample = (Sample, Option<EmbeddedData>);
+
+/// Returns the updated sample if the stored row is out of date, along with the
+/// file's embedded data if it had to be re-probed, or `None` if nothing changed.
+fn refresh_sample(
+    path: &Path,
+    stored: Sample,
+    root_id: isize,
+    options: ScanOptions,
+) -> Result<Option<RefreshedSample>, Box<dyn std::error::Error>> {
+    let (size, modified) = file_stamp(path)?;
+    let content_hash = if options.hash_contents {
+        Some(hash_file(path)?)
+    } else {
+        None
+    };
+
+    let unchanged = size == stored.size
+        && modified == stored.modified
+        && (content_hash.is_none() || content_hash == stored.content_hash);
+    if unchanged {
+        if stored.missing || stored.root_id != Some(root_id) {
+            let sample = Sample {
+                root_id: Some(root_id),
+                missing: false,
+                ..stored
+            };
+            return Ok(Some((sample, None)));
+        }
+        return Ok(None);
+    }
+
+    let ProbedFile {
+        sample: mut file_meta,
+        embedded,
+    } = process_file(path)?;
+    file_meta.id = stored.id;
+    file_meta.keep_user_fields(&stored);
+    file_meta.root_id = Some(root_id);
+    file_meta.content_hash = content_hash;
+    Ok(Some((file_meta, Some(embedded))))
+}
+
+/// Whether `new` is the vanished sample `old` after a move. Content hashes
+/// decide when there are any. Without them, size and mtime alone would match
+/// any two same-sized one-shots unpacked from the same archive, so the file
+/// name has to match as well.
+fn is_same_file(old: &Sample, new: &Sample) -> bool {
+    if old.size != new.size {
+        return false;
+    }
+    match (&old.content_hash, &new.content_hash) {
+        (Some(old_hash), Some(new_hash)) => old_hash == new_hash,
+        (Some(_), None) => false,
+        // Moves within a filesystem keep the mtime
+        (None, _) => {
+            old.modified == new.modified
+                && Path::new(&old.path).file_name() == Path::new(&new.path).file_name()
+        }
+    }
+}
+
+pub fn file_stamp(path: &Path) -> std::io::Result<(u64, i64)> {
+    let metadata = std::fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0);
+    Ok((metadata.len(), modified))
+}
+
+/// 64-bit FNV-1a over the raw file bytes.
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buf = [0u8; 64 * 1024];
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    loop {
+        let read = reader.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        for &byte in &buf[..read] {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    Ok(format!("{:016x}", hash))
+}
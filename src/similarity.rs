@@ -0,0 +1,330 @@
+use std::f32::consts::PI;
+use std::ops::Range;
+
+use crate::classify::Features;
+use crate::loudness::Loudness;
+use crate::spectrum::{Stft, full_frame_starts};
+
+/// Only the start of long samples is looked at, as in classification.
+const MAX_SECONDS: f32 = 10.0;
+/// Spectral frame length and hop, in samples.
+const FRAME_SIZE: usize = 2048;
+const FRAME_HOP: usize = 1024;
+/// Frames this much quieter than the loudest one (-40 dB) are tail and left
+/// out of the timbre statistics.
+const MIN_RELATIVE_ENERGY: f32 = 1e-4;
+/// Mel filter bank the cepstrum is computed from, spanning `MIN_MEL_FREQUENCY`
+/// to `MAX_MEL_FREQUENCY` Hz or the Nyquist frequency if that's lower.
+const MEL_BANDS: usize = 40;
+const MIN_MEL_FREQUENCY: f32 = 20.0;
+const MAX_MEL_FREQUENCY: f32 = 16000.0;
+/// Cepstral coefficients kept, not counting the zeroth, which is just level.
+const MFCC_COUNT: usize = 12;
+
+/// Layout of a feature vector: groups of entries and how much each group
+/// counts towards the distance between two samples. A group's weight is
+/// shared between its entries, so long groups don't drown out short ones.
+const FEATURE_GROUPS: [(Range<usize>, f32); 5] = [
+    // Timbre: MFCC means, then their standard deviations
+    (0..MFCC_COUNT, 3.0),
+    (MFCC_COUNT..2 * MFCC_COUNT, 1.5),
+    // Spectral shape: centroid, rolloff, flatness, low end and zero crossings
+    (2 * MFCC_COUNT..2 * MFCC_COUNT + 5, 2.0),
+    // Envelope: duration, attack and decay
+    (2 * MFCC_COUNT + 5..2 * MFCC_COUNT + 8, 2.0),
+    // Integrated loudness
+    (2 * MFCC_COUNT + 8..2 * MFCC_COUNT + 9, 0.5),
+];
+pub const FEATURE_VECTOR_LEN: usize = 2 * MFCC_COUNT + 9;
+
+/// Builds the vector similar-sound search compares samples by from mono
+/// audio, its classification features and its loudness. `None` for silence.
+pub fn feature_vector(
+    samples: &[f32],
+    sample_rate: u32,
+    features: &Features,
+    loudness: Option<Loudness>,
+) -> Option<Vec<f32>> {
+    let loudness = loudness?;
+    let (means, deviations) = mfcc_statistics(samples, sample_rate)?;
+
+    let mut vector = Vec::with_capacity(FEATURE_VECTOR_LEN);
+    vector.extend(means);
+    vector.extend(deviations);
+    // Frequencies and times are compared on a log scale, as they're heard
+    vector.extend([
+        features.centroid.max(1.0).ln(),
+        features.rolloff.max(1.0).ln(),
+        features.flatness,
+        features.low_ratio,
+        features.zero_crossing_rate.ln_1p(),
+        features.duration.max(0.01).ln(),
+        (features.attack_time + 0.001).ln(),
+        (features.decay_time + 0.01).ln(),
+        loudness.integrated,
+    ]);
+    Some(vector)
+}
+
+/// Ranks samples by how close they sound to `reference`, nearest first, as
+/// `(sample_id, distance)` pairs. Every entry is first scaled by its spread
+/// over `vectors`, so a decibel and a cepstral coefficient weigh the same.
+pub fn rank_by_similarity(reference: &[f32], vectors: &[(isize, Vec<f32>)]) -> Vec<(isize, f32)> {
+    let vectors: Vec<&(isize, Vec<f32>)> = vectors
+        .iter()
+        .filter(|(_, vector)| vector.len() == reference.len())
+        .collect();
+    if vectors.is_empty() || reference.len() != FEATURE_VECTOR_LEN {
+        return Vec::new();
+    }
+
+    let count = vectors.len() as f32;
+    let mut weights = vec![0.0f32; FEATURE_VECTOR_LEN];
+    for (range, group_weight) in FEATURE_GROUPS {
+        let share = group_weight / range.len() as f32;
+        for i in range {
+            let mean = vectors.iter().map(|(_, v)| v[i]).sum::<f32>() / count;
+            let variance = vectors
+                .iter()
+                .map(|(_, v)| (v[i] - mean) * (v[i] - mean))
+                .sum::<f32>()
+                / count;
+            // Entries that are the same everywhere tell samples apart by noise
+            if variance > f32::EPSILON {
+                weights[i] = share / variance;
+            }
+        }
+    }
+
+    let mut ranked: Vec<(isize, f32)> = vectors
+        .iter()
+        .map(|(sample_id, vector)| {
+            let distance = reference
+                .iter()
+                .zip(vector)
+                .zip(&weights)
+                .map(|((a, b), weight)| weight * (a - b) * (a - b))
+                .sum::<f32>()
+                .sqrt();
+            (*sample_id, distance)
+        })
+        .collect();
+    ranked.sort_by(|a, b| a.1.total_cmp(&b.1));
+    ranked
+}
+
+/// Means and standard deviations of the mel-frequency cepstral coefficients
+/// over the audible frames.
+fn mfcc_statistics(samples: &[f32], sample_rate: u32) -> Option<(Vec<f32>, Vec<f32>)> {
+    if sample_rate == 0 || samples.is_empty() {
+        return None;
+    }
+    let rate = sample_rate as f32;
+    let samples = &samples[..samples.len().min((rate * MAX_SECONDS) as usize)];
+
+    let mut stft = Stft::new(FRAME_SIZE);
+    let filters = mel_filters(rate);
+
+    // Band energies of every frame, kept until the loudest frame is known
+    let mut frames: Vec<(f32, [f32; MEL_BANDS])> = Vec::new();
+    for start in full_frame_starts(samples, FRAME_HOP) {
+        let spectrum = stft.spectrum(samples, start);
+        let mut bands = [0.0f32; MEL_BANDS];
+        for (band, filter) in bands.iter_mut().zip(&filters) {
+            *band = filter
+                .iter()
+                .map(|&(bin, weight)| weight * spectrum[bin].norm_sqr())
+                .sum();
+        }
+        frames.push((bands.iter().sum(), bands));
+    }
+
+    let loudest = frames.iter().map(|&(energy, _)| energy).fold(0.0, f32::max);
+    if loudest <= 1e-9 {
+        return None;
+    }
+    let coefficients: Vec<[f32; MFCC_COUNT]> = frames
+        .iter()
+        .filter(|&&(energy, _)| energy >= loudest * MIN_RELATIVE_ENERGY)
+        .map(|(_, bands)| cepstrum(bands))
+        .collect();
+
+    let count = coefficients.len() as f32;
+    let means: Vec<f32> = (0..MFCC_COUNT)
+        .map(|k| coefficients.iter().map(|c| c[k]).sum::<f32>() / count)
+        .collect();
+    let deviations = (0..MFCC_COUNT)
+        .map(|k| {
+            let variance = coefficients
+                .iter()
+                .map(|c| (c[k] - means[k]) * (c[k] - means[k]))
+                .sum::<f32>()
+                / count;
+            variance.sqrt()
+        })
+        .collect();
+    Some((means, deviations))
+}
+
+/// Triangular filters evenly spaced on the mel scale, each a list of FFT
+/// bins and their weights.
+fn mel_filters(rate: f32) -> Vec<Vec<(usize, f32)>> {
+    let mel = |frequency: f32| 2595.0 * (1.0 + frequency / 700.0).log10();
+    let frequency = |mel: f32| 700.0 * (10f32.powf(mel / 2595.0) - 1.0);
+
+    let low = mel(MIN_MEL_FREQUENCY);
+    let high = mel(MAX_MEL_FREQUENCY.min(rate / 2.0));
+    let edges: Vec<f32> = (0..MEL_BANDS + 2)
+        .map(|i| frequency(low + (high - low) * i as f32 / (MEL_BANDS + 1) as f32))
+        .collect();
+
+    let bin_width = rate / FRAME_SIZE as f32;
+    (0..MEL_BANDS)
+        .map(|band| {
+            let (left, center, right) = (edges[band], edges[band + 1], edges[band + 2]);
+            (1..FRAME_SIZE / 2)
+                .filter_map(|bin| {
+                    let f = bin as f32 * bin_width;
+                    let weight = if f <= center {
+                        (f - left) / (center - left)
+                    } else {
+                        (right - f) / (right - center)
+                    };
+                    (weight > 0.0).then_some((bin, weight))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The DCT of the log band energies, skipping the zeroth coefficient.
+fn cepstrum(bands: &[f32; MEL_BANDS]) -> [f32; MFCC_COUNT] {
+    let log_bands = bands.map(|energy| (energy + 1e-10).ln());
+    let scale = (2.0 / MEL_BANDS as f32).sqrt();
+    std::array::from_fn(|i| {
+        let k = (i + 1) as f32;
+        scale
+            * log_bands
+                .iter()
+                .enumerate()
+                .map(|(m, value)| value * (PI * k * (m as f32 + 0.5) / MEL_BANDS as f32).cos())
+                .sum::<f32>()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loudness::measure_loudness;
+
+    /// Deterministic pseudo-random vectors of the right length.
+    fn vectors(count: usize) -> Vec<(isize, Vec<f32>)> {
+        let mut state = 7u32;
+        (0..count)
+            .map(|id| {
+                let vector = (0..FEATURE_VECTOR_LEN)
+                    .map(|_| {
+                        state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                        (state >> 8) as f32 / (1 << 24) as f32
+                    })
+                    .collect();
+                (id as isize, vector)
+            })
+            .collect()
+    }
+
+    fn vector_of(samples: &[f32]) -> Option<Vec<f32>> {
+        let features = Features::extract(samples, 44100)?;
+        let loudness = measure_loudness(&[samples.to_vec()], 44100);
+        feature_vector(samples, 44100, &features, loudness)
+    }
+
+    /// A decaying tone with a touch of noise.
+    fn tone(frequency: f32, noise: f32) -> Vec<f32> {
+        let mut state = 3u32;
+        (0..22050)
+            .map(|i| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let random = (state >> 8) as f32 / (1 << 23) as f32 - 1.0;
+                let t = i as f32 / 44100.0;
+                (-8.0 * t).exp() * ((2.0 * PI * frequency * t).sin() + noise * random) * 0.5
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ranks_the_same_vector_first() {
+        let vectors = vectors(20);
+        for (id, reference) in &vectors {
+            let ranked = rank_by_similarity(reference, &vectors);
+            assert_eq!(ranked.len(), vectors.len());
+            assert_eq!(ranked[0], (*id, 0.0));
+            assert!(ranked.windows(2).all(|w| w[0].1 <= w[1].1));
+        }
+    }
+
+    #[test]
+    fn skips_vectors_of_other_lengths() {
+        let mut vectors = vectors(5);
+        vectors[2].1.pop();
+        let ranked = rank_by_similarity(&vectors[0].1, &vectors);
+        assert_eq!(ranked.len(), 4);
+        assert!(ranked.iter().all(|&(id, _)| id != 2));
+
+        assert!(rank_by_similarity(&vectors[2].1, &vectors).is_empty());
+        assert!(rank_by_similarity(&vectors[0].1, &[]).is_empty());
+    }
+
+    #[test]
+    fn ignores_entries_that_never_vary() {
+        let mut vectors = vectors(10);
+        for (_, vector) in &mut vectors {
+            vector[3] = 1.0;
+        }
+        let mut reference = vectors[4].1.clone();
+        let expected = rank_by_similarity(&reference, &vectors);
+        reference[3] = 100.0;
+        assert_eq!(rank_by_similarity(&reference, &vectors), expected);
+    }
+
+    #[test]
+    fn scales_entries_by_their_spread() {
+        let vectors = vectors(10);
+        let expected = rank_by_similarity(&vectors[0].1, &vectors);
+
+        // Measuring one entry in other units changes nothing
+        let mut scaled = vectors.clone();
+        for (_, vector) in &mut scaled {
+            vector[FEATURE_VECTOR_LEN - 1] *= 1000.0;
+        }
+        let ranked = rank_by_similarity(&scaled[0].1, &scaled);
+        for ((id, distance), (expected_id, expected_distance)) in ranked.iter().zip(&expected) {
+            assert_eq!(id, expected_id);
+            assert!((distance - expected_distance).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn finds_similar_sounds() {
+        let sounds = [
+            tone(200.0, 0.0),
+            tone(210.0, 0.0),
+            tone(3000.0, 0.0),
+            tone(200.0, 4.0),
+        ];
+        let vectors: Vec<(isize, Vec<f32>)> = sounds
+            .iter()
+            .enumerate()
+            .map(|(id, samples)| (id as isize, vector_of(samples).unwrap()))
+            .collect();
+        assert!(vectors.iter().all(|(_, v)| v.len() == FEATURE_VECTOR_LEN));
+
+        let ranked: Vec<isize> = rank_by_similarity(&vectors[0].1, &vectors)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(ranked[..2], [0, 1]);
+
+        assert_eq!(vector_of(&vec![0.0; 22050]), None);
+    }
+}
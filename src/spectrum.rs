@@ -0,0 +1,100 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+
+/// A periodic Hann window of `size` samples.
+pub fn hann(size: usize) -> Vec<f32> {
+    (0..size)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos())
+        .collect()
+}
+
+/// Short-time Fourier transform: the spectra of Hann-windowed frames of
+/// audio, for analyses that follow how the spectrum changes over a file.
+pub struct Stft {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+}
+
+impl Stft {
+    pub fn new(frame_size: usize) -> Self {
+        Self {
+            fft: FftPlanner::new().plan_fft_forward(frame_size),
+            window: hann(frame_size),
+            buffer: vec![Complex::new(0.0, 0.0); frame_size],
+        }
+    }
+
+    /// The spectrum of the frame starting at sample `start`, zero-padded past
+    /// the end of `samples`. Bins past the middle mirror the ones before it.
+    pub fn spectrum(&mut self, samples: &[f32], start: usize) -> &[Complex<f32>] {
+        for (i, value) in self.buffer.iter_mut().enumerate() {
+            let sample = samples.get(start + i).copied().unwrap_or(0.0);
+            *value = Complex::new(sample * self.window[i], 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+        &self.buffer
+    }
+}
+
+/// Starts of the frames `hop` samples apart that cover `samples`, the last
+/// ones running past its end.
+pub fn frame_starts(samples: &[f32], hop: usize) -> impl Iterator<Item = usize> {
+    (0..samples.len()).step_by(hop)
+}
+
+/// Starts of the frames `hop` samples apart whose first hop lies within
+/// `samples`, so none is mostly padding. There is always at least one.
+pub fn full_frame_starts(samples: &[f32], hop: usize) -> impl Iterator<Item = usize> {
+    (0..=samples.len().saturating_sub(hop)).step_by(hop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_frame_starts() {
+        let samples = vec![0.0; 3000];
+        assert_eq!(
+            frame_starts(&samples, 1024).collect::<Vec<_>>(),
+            vec![0, 1024, 2048]
+        );
+        assert_eq!(
+            full_frame_starts(&samples, 1024).collect::<Vec<_>>(),
+            vec![0, 1024]
+        );
+        assert_eq!(
+            full_frame_starts(&samples[..100], 1024).collect::<Vec<_>>(),
+            vec![0]
+        );
+        assert_eq!(frame_starts(&[], 1024).count(), 0);
+    }
+
+    #[test]
+    fn finds_a_tone() {
+        let size = 256;
+        let window = hann(size);
+        assert_eq!(window[0], 0.0);
+        assert!((window[size / 2] - 1.0).abs() < 1e-6);
+
+        // A tone right on bin 16, then silence
+        let samples: Vec<f32> = (0..size)
+            .map(|i| (2.0 * PI * 16.0 * i as f32 / size as f32).sin())
+            .collect();
+        let mut stft = Stft::new(size);
+        let spectrum = stft.spectrum(&samples, 0);
+        let peak = (0..size / 2)
+            .max_by(|&a, &b| spectrum[a].norm().total_cmp(&spectrum[b].norm()))
+            .unwrap();
+        assert_eq!(peak, 16);
+        assert!(
+            stft.spectrum(&samples, size)
+                .iter()
+                .all(|v| v.norm() == 0.0)
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use crate::spectrum::{Stft, frame_starts};
+
+/// Spacing of the onset envelope, in seconds.
+const HOP_SECONDS: f64 = 0.01;
+/// Tempo range considered by the autocorrelation, in BPM.
+const MIN_BPM: f64 = 60.0;
+const MAX_BPM: f64 = 200.0;
+/// Octave errors are resolved towards this tempo.
+const PREFERRED_BPM: f64 = 120.0;
+/// Anything shorter is treated as a one-shot with no tempo.
+const MIN_SECONDS: f64 = 1.5;
+
+/// Estimates the tempo of mono audio from the autocorrelation of its onset
+/// envelope. Returns the BPM and a confidence between 0 and 1, or `None` for
+/// audio too short or too quiet to have a tempo.
+///
+/// `loop_frames` is the length of the loop region, if the file has one; a
+/// tempo that nearly fits a whole number of bars into it is snapped to fit.
+pub fn detect_tempo(
+    samples: &[f32],
+    sample_rate: u32,
+    loop_frames: Option<u64>,
+) -> Option<(f64, f32)> {
+    if sample_rate == 0 || (samples.len() as f64) < MIN_SECONDS * sample_rate as f64 {
+        return None;
+    }
+
+    let hop = ((sample_rate as f64 * HOP_SECONDS).round() as usize).max(1);
+    let hop_seconds = hop as f64 / sample_rate as f64;
+    let envelope = onset_envelope(samples, hop);
+
+    let min_lag = (60.0 / (MAX_BPM * hop_seconds)).floor() as usize;
+    let max_lag = ((60.0 / (MIN_BPM * hop_seconds)).ceil() as usize).min(envelope.len() / 2);
+    if min_lag < 1 || max_lag <= min_lag + 1 {
+        return None;
+    }
+
+    let correlation = autocorrelation(&envelope, max_lag + 1);
+    if correlation[0] <= f32::EPSILON {
+        return None;
+    }
+
+    // A mild log-Gaussian prior around PREFERRED_BPM picks between a tempo
+    // and its half or double, which correlate almost equally well
+    let weighted = |lag: usize| {
+        let bpm = 60.0 / (lag as f64 * hop_seconds);
+        let octaves = (bpm / PREFERRED_BPM).log2();
+        correlation[lag] as f64 * (-0.5 * octaves * octaves).exp()
+    };
+    let best_lag = (min_lag..=max_lag).max_by(|&a, &b| weighted(a).total_cmp(&weighted(b)))?;
+
+    // Parabolic interpolation between neighbouring lags for a fractional lag
+    let mut lag = best_lag as f64;
+    if best_lag > min_lag && best_lag < max_lag {
+        let (before, peak, after) = (
+            correlation[best_lag - 1] as f64,
+            correlation[best_lag] as f64,
+            correlation[best_lag + 1] as f64,
+        );
+        let curvature = before - 2.0 * peak + after;
+        if curvature < 0.0 {
+            lag += 0.5 * (before - after) / curvature;
+        }
+    }
+    let mut bpm = 60.0 / (lag * hop_seconds);
+
+    // How far the peak stands out from the typical correlation, relative to
+    // the signal's own energy: close to 1 for a steady beat, 0 for noise
+    let range = &correlation[min_lag..=max_lag];
+    let mean = range.iter().sum::<f32>() / range.len() as f32;
+    let confidence = ((correlation[best_lag] - mean) / (correlation[0] - mean).max(f32::EPSILON))
+        .clamp(0.0, 1.0);
+
+    let duration = loop_frames.unwrap_or(samples.len() as u64) as f64 / sample_rate as f64;
+    if let Some(snapped) = snap_to_bars(bpm, duration) {
+        bpm = snapped;
+    }
+
+    Some((bpm, confidence))
+}
+
+/// Loops are cut to whole 4/4 bars (or a beat or two), so a tempo within 2%
+/// of fitting up to 16 bars into the loop exactly is almost certainly that tempo.
+fn snap_to_bars(bpm: f64, duration: f64) -> Option<f64> {
+    let whole_beats = (bpm * duration / 60.0).round();
+    let fits_bars = whole_beats <= 2.0 || (whole_beats as u32).is_multiple_of(4);
+    if !(1.0..=64.0).contains(&whole_beats) || !fits_bars {
+        return None;
+    }
+    let snapped = whole_beats * 60.0 / duration;
+    ((snapped - bpm).abs() / bpm < 0.02).then_some(snapped)
+}
+
+/// Spectral flux: how much the log-magnitude spectrum grows from one frame to
+/// the next, with the local average removed so only onsets stand out.
+fn onset_envelope(samples: &[f32], hop: usize) -> Vec<f32> {
+    let frame_size = (hop * 4).next_power_of_two();
+    let mut stft = Stft::new(frame_size);
+
+    let bins = frame_size / 2;
+    let mut previous = vec![0.0f32; bins];
+    let mut flux = Vec::with_capacity(samples.len() / hop + 1);
+
+    for start in frame_starts(samples, hop) {
+        let spectrum = stft.spectrum(samples, start);
+        let mut total = 0.0;
+        for (bin, value) in spectrum[..bins].iter().enumerate() {
+            let magnitude = (1.0 + 100.0 * value.norm()).ln();
+            total += (magnitude - previous[bin]).max(0.0);
+            previous[bin] = magnitude;
+        }
+        flux.push(total);
+    }
+
+    // The first frame has nothing to rise from
+    if let Some(first) = flux.first_mut() {
+        *first = 0.0;
+    }
+
+    // Subtract a moving average of about half a second and keep the rises
+    let radius = ((0.25 / HOP_SECONDS) as usize).max(1);
+    let rises: Vec<f32> = (0..flux.len())
+        .map(|i| {
+            let window = &flux[i.saturating_sub(radius)..(i + radius + 1).min(flux.len())];
+            let mean = window.iter().sum::<f32>() / window.len() as f32;
+            (flux[i] - mean).max(0.0)
+        })
+        .collect();
+
+    // Onsets are only a frame or two wide; spreading them over ~30 ms keeps
+    // timing jitter from splitting autocorrelation peaks across lags
+    let weights = [1.0, 2.0, 3.0, 4.0, 3.0, 2.0, 1.0];
+    (0..rises.len())
+        .map(|i| {
+            weights
+                .iter()
+                .enumerate()
+                .filter_map(|(k, weight)| Some(rises.get((i + k).checked_sub(3)?)? * weight))
+                .sum::<f32>()
+                / 16.0
+        })
+        .collect()
+}
+
+/// Autocorrelation for lags `0..lags`, normalized by the number of overlapping
+/// values so long lags aren't penalized.
+fn autocorrelation(values: &[f32], lags: usize) -> Vec<f32> {
+    (0..lags)
+        .map(|lag| {
+            let overlap = values.len() - lag;
+            let sum: f32 = values[..overlap]
+                .iter()
+                .zip(&values[lag..])
+                .map(|(a, b)| a * b)
+                .sum();
+            sum / overlap as f32
+        })
+        .collect()
+}
+
+/// Reads a tempo hint from a file name such as `funk_124bpm.wav`,
+/// `Loop 92.5 BPM.wav` or `bpm_140_hats.wav`.
+pub fn tempo_from_filename(name: &str) -> Option<f64> {
+    let lower = name.to_lowercase();
+    let is_separator = |c: char| matches!(c, ' ' | '_' | '-' | '.');
+
+    for (idx, _) in lower.match_indices("bpm") {
+        // Digits right before "bpm", possibly separated by spaces or underscores
+        let before = lower[..idx].trim_end_matches(is_separator);
+        let number_start = before
+            .char_indices()
+            .rfind(|&(_, c)| !c.is_ascii_digit() && c != '.')
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let number = before[number_start..].trim_matches('.');
+
+        // Otherwise digits right after it
+        let number = if number.is_empty() {
+            let after = lower[idx + 3..].trim_start_matches(is_separator);
+            let end = after
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(after.len());
+            after[..end].trim_matches('.')
+        } else {
+            number
+        };
+
+        if let Ok(bpm) = number.parse::<f64>()
+            && (40.0..=300.0).contains(&bpm)
+        {
+            return Some(bpm);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_tempo_before_or_after_bpm() {
+        assert_eq!(tempo_from_filename("funk_124bpm.wav"), Some(124.0));
+        assert_eq!(tempo_from_filename("Loop 92.5 BPM.wav"), Some(92.5));
+        assert_eq!(tempo_from_filename("bpm_140_hats.wav"), Some(140.0));
+        assert_eq!(tempo_from_filename("kick_bpm.wav"), None);
+        assert_eq!(tempo_from_filename("pad 1000bpm.wav"), None);
+    }
+
+    #[test]
+    fn handles_non_ascii_names() {
+        assert_eq!(tempo_from_filename("café bpm 120.wav"), Some(120.0));
+        assert_eq!(tempo_from_filename("café120bpm.wav"), Some(120.0));
+        assert_eq!(tempo_from_filename("ドラム_98bpm.wav"), Some(98.0));
+        assert_eq!(tempo_from_filename("ÄÖÜ bpm.wav"), None);
+    }
+}
@@ -1,35 +1,146 @@
 use crate::SampleDuckApp;
+use crate::app::HASH_CONTENTS_SETTING;
+use crate::db::{
+    SampleMark, delete_library_root, insert_library_root, set_setting, update_library_root,
+};
+use crate::mixing::ChannelState;
+use crate::music::{camelot, note_name, pitch_name};
+use crate::query::LibraryScope;
+use crate::sample::{Collection, ColorLabel, Sample, SampleColumn, Tag};
 use egui::{Color32, Sense, Shape, Stroke, Ui, pos2, vec2};
 use egui_extras::{Column, TableBuilder};
 
+/// Detected tempos, keys and pitches below these confidences are shown dimmed.
+const LOW_BPM_CONFIDENCE: f32 = 0.3;
+const LOW_KEY_CONFIDENCE: f32 = 0.6;
+const LOW_PITCH_CONFIDENCE: f32 = 0.6;
+
 impl eframe::App for SampleDuckApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_import();
+        self.poll_watcher();
+        self.poll_analysis();
+        self.sort_changed_samples();
+
+        egui::SidePanel::left("library_sidebar")
+            .resizable(true)
+            .default_width(180.0)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    self.library_sidebar(ui);
+                    ui.separator();
+                    self.collections_sidebar(ui);
+                    ui.separator();
+                    self.tag_sidebar(ui);
+                });
+            });
+
         egui::CentralPanel::default().show(ctx, |ui| {
-            //keymap
-            if ui.input(|i| i.key_pressed(egui::Key::J) || i.key_pressed(egui::Key::ArrowDown)) {
-                self.select_next_sample();
-            }
-            if ui.input(|i| i.key_pressed(egui::Key::K) || i.key_pressed(egui::Key::ArrowUp)) {
-                self.select_prev_sample();
-            }
-            if ui.input(|i| i.key_pressed(egui::Key::Space)) {
-                self.audio_player.toggle_play_state();
+            //keymap, unless a search or filter box is being typed into
+            if !ctx.wants_keyboard_input() {
+                self.handle_shortcuts(ui);
             }
 
             ui.heading("Sample Duck");
             ui.separator();
 
             ui.vertical(|ui| {
+                self.library_roots_view(ui);
+                self.import_progress_view(ui);
+                self.analysis_progress_view(ui);
+                self.search_view(ui);
                 self.details_view(ui);
-                egui::ScrollArea::both().show(ui, |ui| {
+                // The table scrolls vertically itself so it can skip rows out of view
+                egui::ScrollArea::horizontal().show(ui, |ui| {
                     self.sample_list(ui);
                 });
             });
         });
+
+        self.duplicates_window(ctx);
     }
 }
 
+/// A sidebar or context menu edit to a collection, applied once the menus
+/// are done borrowing the app.
+enum CollectionAction {
+    Create {
+        parent_id: Option<isize>,
+        is_folder: bool,
+    },
+    /// Creates a collection holding the selected samples
+    CreateFromSelection,
+    CreateSmart {
+        parent_id: Option<isize>,
+        query: String,
+    },
+    Rename(isize, String),
+    SetQuery(isize, String),
+    Move(isize, Option<isize>),
+    Delete(isize),
+    AddSelection(isize),
+    RemoveSelection(isize),
+}
+
 impl SampleDuckApp {
+    fn handle_shortcuts(&mut self, ui: &mut Ui) {
+        // Shift extends the selection instead of moving it; Alt moves the
+        // sample within the browsed collection
+        let extend = ui.input(|i| i.modifiers.shift);
+        let reorder = ui.input(|i| i.modifiers.alt);
+        if ui.input(|i| i.key_pressed(egui::Key::J) || i.key_pressed(egui::Key::ArrowDown)) {
+            if reorder {
+                self.move_selection_in_collection(false);
+            } else {
+                self.select_next_sample(extend);
+            }
+        }
+        if ui.input(|i| i.key_pressed(egui::Key::K) || i.key_pressed(egui::Key::ArrowUp)) {
+            if reorder {
+                self.move_selection_in_collection(true);
+            } else {
+                self.select_prev_sample(extend);
+            }
+        }
+        if ui.input(|i| i.key_pressed(egui::Key::T)) && !self.selected_sample_ids().is_empty() {
+            self.tag_editor_open = true;
+        }
+
+        if self.selected_sample_ids().is_empty() {
+            return;
+        }
+        if ui.input(|i| i.key_pressed(egui::Key::F)) {
+            let favorite = !self.selected_sample.as_ref().is_some_and(|s| s.favorite);
+            self.mark_selection(SampleMark::Favorite(favorite));
+        }
+        // Digits set the rating; with Ctrl/Cmd they set the color label, 0 clearing either
+        const DIGITS: [egui::Key; 8] = [
+            egui::Key::Num0,
+            egui::Key::Num1,
+            egui::Key::Num2,
+            egui::Key::Num3,
+            egui::Key::Num4,
+            egui::Key::Num5,
+            egui::Key::Num6,
+            egui::Key::Num7,
+        ];
+        let command = ui.input(|i| i.modifiers.command);
+        for (digit, key) in DIGITS.into_iter().enumerate() {
+            if !ui.input(|i| i.key_pressed(key)) {
+                continue;
+            }
+            if command {
+                let label = digit.checked_sub(1).map(|idx| ColorLabel::ALL[idx]);
+                self.mark_selection(SampleMark::ColorLabel(label));
+            } else if digit <= 5 {
+                self.mark_selection(SampleMark::Rating(digit as u8));
+            }
+        }
+        if ui.input(|i| i.key_pressed(egui::Key::Space)) {
+            self.audio_player.toggle_play_state();
+        }
+    }
+
     fn sample_list(&mut self, ui: &mut Ui) {
         let available_height = ui.available_height();
 
@@ -37,82 +148,1202 @@ impl SampleDuckApp {
             .striped(true)
             .resizable(true)
             .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
-            .column(Column::auto())
-            .column(Column::auto())
-            .column(Column::auto())
-            .column(Column::auto())
-            .column(Column::auto())
-            .column(Column::auto())
+            .columns(Column::auto(), SampleColumn::ALL.len())
             .min_scrolled_height(0.0)
             .max_scroll_height(available_height);
 
         table = table.sense(egui::Sense::click());
 
+        if self.scroll_to_selected {
+            self.scroll_to_selected = false;
+            if let Some(row) = self.selected_sample_idx {
+                table = table.scroll_to_row(row, None);
+            }
+        }
+
+        let mut clicked_column = None;
+        let mut clicked_row = None;
+        let mut filters_changed = false;
+        let mut collection_action = None;
+        let mut reorder = None;
+        let collection_ordered = self.is_collection_ordered();
+        let column_sorted = !collection_ordered && !self.is_similarity_ordered();
+        let mut find_similar = false;
+
         table
-            .header(20.0, |mut header| {
-                header.col(|ui| {
-                    ui.strong("Name");
-                });
-                header.col(|ui| {
-                    ui.strong("Path");
+            .header(44.0, |mut header| {
+                for column in SampleColumn::ALL {
+                    header.col(|ui| {
+                        ui.vertical(|ui| {
+                            let title = if column == self.sort_column && column_sorted {
+                                let arrow = if self.sort_ascending { "⏶" } else { "⏷" };
+                                format!("{} {}", column.title(), arrow)
+                            } else {
+                                column.title().to_string()
+                            };
+                            let label = egui::Label::new(egui::RichText::new(title).strong())
+                                .sense(Sense::click());
+                            if ui.add(label).clicked() {
+                                clicked_column = Some(column);
+                            }
+
+                            let filter = self.column_filters.entry(column).or_default();
+                            let response = ui.add(
+                                egui::TextEdit::singleline(filter)
+                                    .hint_text("Filter")
+                                    .desired_width(80.0),
+                            );
+                            if response.changed() {
+                                filters_changed = true;
+                            }
+                        });
+                    });
+                }
+            })
+            .body(|body| {
+                let focused_id = self.selected_sample.as_ref().map(|s| s.id);
+                // Only the rows scrolled into view are built
+                body.rows(18.0, self.visible_rows.len(), |mut row| {
+                    let row_idx = row.index();
+                    let sample = &self.samples[self.visible_rows[row_idx]];
+                    row.set_selected(
+                        focused_id == Some(sample.id) || self.selected_ids.contains(&sample.id),
+                    );
+                    for column in SampleColumn::ALL {
+                        row.col(|ui| {
+                            if column == SampleColumn::Name && sample.missing {
+                                ui.weak(format!("{} (missing)", sample.name));
+                            } else if column == SampleColumn::ColorLabel {
+                                if let Some(label) = sample.color_label {
+                                    color_swatch(ui, label, 10.0).on_hover_text(label.key());
+                                }
+                            } else if let Some((confidence, low)) =
+                                detected_confidence(column, sample)
+                                && confidence < 1.0
+                            {
+                                // Detected rather than stated by the file; dim the unsure ones
+                                let text = column.cell_text(sample);
+                                let response = if confidence < low {
+                                    ui.weak(text)
+                                } else {
+                                    ui.label(text)
+                                };
+                                response.on_hover_text(format!(
+                                    "Detected, {:.0}% confidence",
+                                    confidence * 100.0
+                                ));
+                            } else {
+                                ui.label(column.cell_text(sample));
+                            }
+                        });
+                    }
+
+                    let response = row.response();
+                    if response.clicked() {
+                        clicked_row = Some((row_idx, response.ctx.input(|i| i.modifiers)));
+                    }
+                    // Right-clicking outside the selection selects that row for the menu
+                    if response.secondary_clicked()
+                        && focused_id != Some(sample.id)
+                        && !self.selected_ids.contains(&sample.id)
+                    {
+                        clicked_row = Some((row_idx, egui::Modifiers::NONE));
+                    }
+                    response.context_menu(|ui| {
+                        if ui.button("Find similar").clicked() {
+                            find_similar = true;
+                            ui.close();
+                        }
+                        ui.menu_button("Add to collection", |ui| {
+                            for (collection, _) in &self.collections {
+                                if !collection.is_folder
+                                    && !collection.is_smart()
+                                    && ui.button(self.collection_path(collection.id)).clicked()
+                                {
+                                    collection_action =
+                                        Some(CollectionAction::AddSelection(collection.id));
+                                    ui.close();
+                                }
+                            }
+                            if ui.button("New collection…").clicked() {
+                                collection_action = Some(CollectionAction::CreateFromSelection);
+                                ui.close();
+                            }
+                        });
+                        if let LibraryScope::Collection(collection_id) = self.scope
+                            && !self.is_browsing_smart_collection()
+                        {
+                            if ui.button("Remove from collection").clicked() {
+                                collection_action =
+                                    Some(CollectionAction::RemoveSelection(collection_id));
+                                ui.close();
+                            }
+                            if collection_ordered {
+                                ui.separator();
+                                if ui.button("Move up (Alt+↑)").clicked() {
+                                    reorder = Some(true);
+                                    ui.close();
+                                }
+                                if ui.button("Move down (Alt+↓)").clicked() {
+                                    reorder = Some(false);
+                                    ui.close();
+                                }
+                            }
+                        }
+                    });
                 });
-                header.col(|ui| {
-                    ui.strong("Format");
+            });
+
+        if let Some(column) = clicked_column {
+            self.sort_by(column);
+        }
+        if filters_changed {
+            self.refresh_visible_rows();
+        }
+        match clicked_row {
+            Some((row, modifiers)) if modifiers.shift => self.extend_selection(row),
+            Some((row, modifiers)) if modifiers.command => self.toggle_selected(row),
+            Some((row, _)) => self.select_sample(row),
+            None => {}
+        }
+        if let Some(up) = reorder {
+            self.move_selection_in_collection(up);
+        }
+        if let Some(action) = collection_action {
+            self.apply_collection_action(action);
+        }
+        if find_similar {
+            self.find_similar();
+        }
+    }
+
+    fn library_roots_view(&mut self, ui: &mut Ui) {
+        let mut changed_root = None;
+        let mut removed_root = None;
+        let mut rescan = false;
+
+        egui::CollapsingHeader::new("Library folders").show(ui, |ui| {
+            for root in self.library_roots.iter_mut() {
+                ui.horizontal(|ui| {
+                    ui.label(root.path.clone());
+                    if ui
+                        .checkbox(&mut root.follow_symlinks, "Follow symlinks")
+                        .changed()
+                    {
+                        changed_root = Some(root.clone());
+                    }
+                    if ui.button("Remove").clicked() {
+                        removed_root = Some(root.id);
+                    }
                 });
-                header.col(|ui| {
-                    ui.strong("Sample Rate");
+
+                let mut patterns = root.ignore_patterns.join(", ");
+                ui.horizontal(|ui| {
+                    ui.label("Ignore:");
+                    if ui.text_edit_singleline(&mut patterns).lost_focus() {
+                        root.ignore_patterns = patterns
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|p| !p.is_empty())
+                            .map(String::from)
+                            .collect();
+                        changed_root = Some(root.clone());
+                    }
                 });
-                header.col(|ui| {
-                    ui.strong("Size");
+            }
+
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.new_root_path);
+                if ui.button("Add folder").clicked() && !self.new_root_path.trim().is_empty() {
+                    match insert_library_root(&self.conn, self.new_root_path.trim(), true, &[]) {
+                        Ok(_) => {
+                            self.new_root_path.clear();
+                            rescan = true;
+                        }
+                        Err(error) => println!("Error: {}", error),
+                    }
+                }
+                if ui.button("Rescan").clicked() {
+                    rescan = true;
+                }
+            });
+
+            ui.horizontal(|ui| {
+                if ui
+                    .checkbox(&mut self.scan_options.hash_contents, "Hash file contents")
+                    .changed()
+                {
+                    let value = if self.scan_options.hash_contents {
+                        "1"
+                    } else {
+                        "0"
+                    };
+                    if let Err(error) = set_setting(&self.conn, HASH_CONTENTS_SETTING, value) {
+                        println!("Error: {}", error);
+                    }
+                }
+                if let Some(summary) = &self.last_scan {
+                    ui.label(format!(
+                        "Last scan: {} added, {} updated, {} moved, {} missing, {} unchanged",
+                        summary.added,
+                        summary.updated,
+                        summary.moved,
+                        summary.missing,
+                        summary.unchanged
+                    ));
+                }
+            });
+        });
+
+        if let Some(root) = changed_root
+            && let Err(error) = update_library_root(&self.conn, &root)
+        {
+            println!("Error: {}", error);
+        }
+        if let Some(root_id) = removed_root {
+            if let Err(error) = delete_library_root(&self.conn, root_id) {
+                println!("Error: {}", error);
+            }
+            rescan = true;
+        }
+        if rescan {
+            self.rescan_library();
+        }
+    }
+
+    fn search_view(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Search:");
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.search_text)
+                    .hint_text("kick bpm:120-128 compatible:Am format:flac tag:808 -tag:loop")
+                    .desired_width(400.0),
+            );
+            if response.changed() {
+                let text = self.search_text.clone();
+                self.set_search_text(&text);
+            }
+            if !self.search_text.is_empty() && ui.button("Clear").clicked() {
+                self.search_text.clear();
+                self.set_search_text("");
+            }
+            ui.weak(format!("{} samples", self.samples.len()));
+            if matches!(self.scope, LibraryScope::Collection(_))
+                && !self.collection_order
+                && !self.is_browsing_smart_collection()
+                && ui.button("Collection order").clicked()
+            {
+                self.show_collection_order();
+            }
+            if !self.search_text.trim().is_empty()
+                && ui
+                    .button("Save as smart collection")
+                    .on_hover_text("Keep a collection of whatever matches this search")
+                    .clicked()
+            {
+                let query = self.search_text.clone();
+                self.create_smart_collection(None, &query);
+            }
+        });
+
+        let Some(similar) = &self.similar else {
+            return;
+        };
+        let mut clear = false;
+        let mut reorder = false;
+        ui.horizontal(|ui| {
+            ui.label(format!("Sounds like {}", similar.name));
+            if !similar.ordered && ui.button("Nearest first").clicked() {
+                reorder = true;
+            }
+            if ui.button("Clear").clicked() {
+                clear = true;
+            }
+        });
+        if reorder {
+            self.show_similarity_order();
+        }
+        if clear {
+            self.clear_similar();
+        }
+    }
+
+    fn library_sidebar(&mut self, ui: &mut Ui) {
+        let mut new_scope = None;
+
+        ui.strong("Library");
+        if ui
+            .selectable_label(self.scope == LibraryScope::All, "All samples")
+            .clicked()
+        {
+            new_scope = Some(LibraryScope::All);
+        }
+        if ui
+            .selectable_label(self.scope == LibraryScope::Favorites, "♥ Favorites")
+            .clicked()
+        {
+            new_scope = Some(LibraryScope::Favorites);
+        }
+        if ui
+            .selectable_label(self.duplicates_open, "⧉ Duplicates")
+            .on_hover_text("Review samples that are copies of each other")
+            .clicked()
+        {
+            self.duplicates_open = !self.duplicates_open;
+            if self.duplicates_open {
+                self.find_duplicates();
+            }
+        }
+
+        if let Some(scope) = new_scope {
+            self.set_scope(scope);
+        }
+    }
+
+    fn duplicates_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.duplicates_open;
+        let mut refresh = false;
+        let mut audition = None;
+        let mut hide = None;
+        let mut delete = None;
+        let mut unhide = None;
+        let mut deleting = self.deleting_duplicates;
+
+        egui::Window::new("Duplicates")
+            .open(&mut open)
+            .default_size([560.0, 420.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("Refresh").clicked() {
+                        refresh = true;
+                    }
+                    if ui
+                        .checkbox(&mut self.show_resolved_duplicates, "Show resolved")
+                        .on_hover_text("Also list sets whose duplicates are all hidden")
+                        .changed()
+                    {
+                        refresh = true;
+                    }
+                    ui.weak(format!("{} sets", self.duplicates.len()));
                 });
-            })
-            .body(|mut body| {
-                for (idx, sample) in self.samples.clone().iter().enumerate() {
-                    let row_height = 18.0;
-                    body.row(row_height, |mut row| {
-                        row.set_selected(self.selected_sample.id == sample.id);
-                        row.col(|ui| {
-                            ui.label(sample.name.clone());
-                        });
-                        row.col(|ui| {
-                            ui.label(sample.path.clone());
+                if self.duplicates.is_empty() {
+                    ui.weak("No duplicates among the analyzed samples");
+                }
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for (index, review) in self.duplicates.iter_mut().enumerate() {
+                        ui.separator();
+                        if review.set.exact {
+                            ui.strong("Identical audio");
+                        } else {
+                            ui.strong(format!(
+                                "Near duplicates, {:.0}% alike",
+                                review.set.similarity * 100.0
+                            ));
+                        }
+
+                        let mut keep = review.keep;
+                        for sample in &review.samples {
+                            ui.horizontal(|ui| {
+                                ui.radio_value(&mut keep, sample.id, "Keep");
+                                if ui.link(&sample.name).on_hover_text("Play").clicked() {
+                                    audition = Some(sample.clone());
+                                }
+                                ui.weak(&sample.path);
+                                if let Some(other) = review.alias_of(sample) {
+                                    ui.weak(format!("(same file as {})", other.name))
+                                        .on_hover_text(
+                                            "Reached through a symlink or hardlink, so it isn't deleted",
+                                        );
+                                }
+                                if sample.hidden {
+                                    ui.weak("(hidden)");
+                                    if ui.small_button("Unhide").clicked() {
+                                        unhide = Some(sample.id);
+                                    }
+                                }
+                            });
+                        }
+                        review.keep = keep;
+
+                        let deletable = review.deletable().count();
+                        ui.horizontal(|ui| {
+                            if deleting == Some(index) {
+                                ui.colored_label(
+                                    ui.visuals().warn_fg_color,
+                                    format!("Delete {} files from disk?", deletable),
+                                );
+                                if ui.button("Delete").clicked() {
+                                    delete = Some(index);
+                                }
+                                if ui.button("Cancel").clicked() {
+                                    deleting = None;
+                                }
+                            } else {
+                                if ui
+                                    .button("Hide others")
+                                    .on_hover_text(
+                                        "Keep them in the library but out of the browser",
+                                    )
+                                    .clicked()
+                                {
+                                    hide = Some(index);
+                                }
+                                if ui
+                                    .add_enabled(deletable > 0, egui::Button::new("Delete others…"))
+                                    .clicked()
+                                {
+                                    deleting = Some(index);
+                                }
+                            }
                         });
-                        row.col(|ui| {
-                            ui.label(sample.format.to_string());
+                    }
+                });
+            });
+
+        self.duplicates_open = open;
+        self.deleting_duplicates = deleting;
+        if let Some(sample) = audition {
+            self.audition(sample);
+        }
+        if let Some(sample_id) = unhide {
+            self.unhide_sample(sample_id);
+        }
+        if let Some(index) = hide {
+            self.hide_duplicates(index);
+        }
+        if let Some(index) = delete {
+            self.delete_duplicates(index);
+        }
+        if refresh {
+            self.find_duplicates();
+        }
+    }
+
+    fn collections_sidebar(&mut self, ui: &mut Ui) {
+        let mut new_scope = None;
+        let mut actions = Vec::new();
+
+        ui.horizontal(|ui| {
+            ui.strong("Collections");
+            ui.menu_button("+", |ui| {
+                if ui.button("New collection").clicked() {
+                    actions.push(CollectionAction::Create {
+                        parent_id: None,
+                        is_folder: false,
+                    });
+                    ui.close();
+                }
+                if ui.button("New smart collection").clicked() {
+                    actions.push(CollectionAction::CreateSmart {
+                        parent_id: None,
+                        query: self.search_text.clone(),
+                    });
+                    ui.close();
+                }
+                if ui.button("New folder").clicked() {
+                    actions.push(CollectionAction::Create {
+                        parent_id: None,
+                        is_folder: true,
+                    });
+                    ui.close();
+                }
+            });
+        });
+        if self.collections.is_empty() {
+            ui.weak("No collections yet. Right-click samples to add them to one.");
+        }
+
+        self.collection_tree(ui, None, &mut new_scope, &mut actions);
+
+        for action in actions {
+            self.apply_collection_action(action);
+        }
+        if let Some(scope) = new_scope {
+            self.set_scope(scope);
+        }
+    }
+
+    /// Lists the collections and folders directly inside `parent_id`, with
+    /// folders expanding to their contents.
+    fn collection_tree(
+        &mut self,
+        ui: &mut Ui,
+        parent_id: Option<isize>,
+        new_scope: &mut Option<LibraryScope>,
+        actions: &mut Vec<CollectionAction>,
+    ) {
+        let children: Vec<(Collection, usize)> = self
+            .collections
+            .iter()
+            .filter(|(collection, _)| collection.parent_id == parent_id)
+            .cloned()
+            .collect();
+
+        for (collection, count) in children {
+            if let Some((collection_id, name)) = &mut self.renaming_collection
+                && *collection_id == collection.id
+            {
+                let response = ui.text_edit_singleline(name);
+                if response.lost_focus() {
+                    // Enter renames; clicking away or Escape keeps the old name
+                    let name = if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        name.clone()
+                    } else {
+                        String::new()
+                    };
+                    self.renaming_collection = None;
+                    actions.push(CollectionAction::Rename(collection.id, name));
+                } else if !response.has_focus() {
+                    response.request_focus();
+                }
+                continue;
+            }
+
+            let response = if collection.is_folder {
+                egui::CollapsingHeader::new(&collection.name)
+                    .id_salt(("collection_folder", collection.id))
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        self.collection_tree(ui, Some(collection.id), new_scope, actions);
+                    })
+                    .header_response
+            } else {
+                let selected = self.scope == LibraryScope::Collection(collection.id);
+                let mut response = match &collection.query {
+                    Some(query) => ui
+                        .selectable_label(selected, format!("🔍 {} ({})", collection.name, count))
+                        .on_hover_text(query),
+                    None => {
+                        ui.selectable_label(selected, format!("{} ({})", collection.name, count))
+                    }
+                };
+                if response.clicked() {
+                    *new_scope = Some(LibraryScope::Collection(collection.id));
+                }
+
+                if let Some((collection_id, query)) = &mut self.editing_query
+                    && *collection_id == collection.id
+                {
+                    let editor = ui.add(
+                        egui::TextEdit::singleline(query)
+                            .hint_text("tag:snare AND rating>=3 AND duration<1s"),
+                    );
+                    if editor.lost_focus() {
+                        // Enter saves the query; clicking away or Escape discards the edit
+                        if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                            actions.push(CollectionAction::SetQuery(collection.id, query.clone()));
+                        }
+                        self.editing_query = None;
+                    } else if !editor.has_focus() {
+                        editor.request_focus();
+                    }
+                    response = response.union(editor);
+                }
+                response
+            };
+            if response.double_clicked() && !collection.is_folder {
+                self.renaming_collection = Some((collection.id, collection.name.clone()));
+            }
+
+            response.context_menu(|ui| {
+                if collection.is_folder {
+                    if ui.button("New collection").clicked() {
+                        actions.push(CollectionAction::Create {
+                            parent_id: Some(collection.id),
+                            is_folder: false,
                         });
-                        row.col(|ui| {
-                            ui.label(sample.sample_rate.to_string());
+                        ui.close();
+                    }
+                    if ui.button("New smart collection").clicked() {
+                        actions.push(CollectionAction::CreateSmart {
+                            parent_id: Some(collection.id),
+                            query: self.search_text.clone(),
                         });
-                        row.col(|ui| {
-                            ui.label(sample.size.to_string());
+                        ui.close();
+                    }
+                    if ui.button("New folder").clicked() {
+                        actions.push(CollectionAction::Create {
+                            parent_id: Some(collection.id),
+                            is_folder: true,
                         });
+                        ui.close();
+                    }
+                    ui.separator();
+                } else if let Some(query) = &collection.query {
+                    if ui.button("Edit query").clicked() {
+                        self.editing_query = Some((collection.id, query.clone()));
+                        ui.close();
+                    }
+                } else if ui.button("Add selected samples").clicked() {
+                    actions.push(CollectionAction::AddSelection(collection.id));
+                    ui.close();
+                }
+                if ui.button("Rename").clicked() {
+                    self.renaming_collection = Some((collection.id, collection.name.clone()));
+                    ui.close();
+                }
+                ui.menu_button("Move to", |ui| {
+                    if collection.parent_id.is_some() && ui.button("Top level").clicked() {
+                        actions.push(CollectionAction::Move(collection.id, None));
+                        ui.close();
+                    }
+                    for (folder, _) in &self.collections {
+                        // A folder can't move into itself or one of its subfolders
+                        if !folder.is_folder
+                            || collection.parent_id == Some(folder.id)
+                            || self.is_within_folder(folder.id, collection.id)
+                        {
+                            continue;
+                        }
+                        if ui.button(self.collection_path(folder.id)).clicked() {
+                            actions.push(CollectionAction::Move(collection.id, Some(folder.id)));
+                            ui.close();
+                        }
+                    }
+                });
+                if ui.button("Delete").clicked() {
+                    actions.push(CollectionAction::Delete(collection.id));
+                    ui.close();
+                }
+            });
+        }
+    }
 
-                        self.click_sample(idx, &row.response());
-                    });
+    fn apply_collection_action(&mut self, action: CollectionAction) {
+        match action {
+            CollectionAction::Create {
+                parent_id,
+                is_folder,
+            } => {
+                self.create_collection(parent_id, is_folder);
+            }
+            CollectionAction::CreateFromSelection => {
+                if let Some(collection_id) = self.create_collection(None, false) {
+                    self.add_selection_to_collection(collection_id);
+                }
+            }
+            CollectionAction::CreateSmart { parent_id, query } => {
+                self.create_smart_collection(parent_id, &query)
+            }
+            CollectionAction::Rename(collection_id, name) => {
+                self.rename_collection(collection_id, &name)
+            }
+            CollectionAction::SetQuery(collection_id, query) => {
+                self.set_collection_query(collection_id, &query)
+            }
+            CollectionAction::Move(collection_id, parent_id) => {
+                self.move_collection(collection_id, parent_id)
+            }
+            CollectionAction::Delete(collection_id) => self.delete_collection(collection_id),
+            CollectionAction::AddSelection(collection_id) => {
+                self.add_selection_to_collection(collection_id)
+            }
+            CollectionAction::RemoveSelection(collection_id) => {
+                self.remove_selection_from_collection(collection_id)
+            }
+        }
+    }
+
+    fn tag_sidebar(&mut self, ui: &mut Ui) {
+        let mut new_scope = None;
+        let mut rename = None;
+        let mut delete = None;
+
+        ui.strong("Tags");
+        if self.tags.is_empty() {
+            ui.weak("No tags yet. Press T to tag the selected samples.");
+        }
+
+        for (tag, count) in &self.tags {
+            if let Some((tag_id, name)) = &mut self.renaming_tag
+                && *tag_id == tag.id
+            {
+                let response = ui.text_edit_singleline(name);
+                if response.lost_focus() {
+                    // Enter renames; clicking away or Escape cancels
+                    if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        rename = Some((tag.id, name.clone()));
+                    } else {
+                        rename = Some((tag.id, String::new()));
+                    }
+                } else if !response.has_focus() {
+                    response.request_focus();
+                }
+                continue;
+            }
+
+            let selected = self.scope == LibraryScope::Tag(tag.id);
+            let response = ui
+                .selectable_label(selected, format!("{} ({})", tag.name, count))
+                .on_hover_text("Double-click to rename; renaming to an existing tag merges them");
+            if response.clicked() {
+                new_scope = Some(LibraryScope::Tag(tag.id));
+            }
+            if response.double_clicked() {
+                self.renaming_tag = Some((tag.id, tag.name.clone()));
+            }
+            response.context_menu(|ui| {
+                if ui.button("Rename").clicked() {
+                    self.renaming_tag = Some((tag.id, tag.name.clone()));
+                    ui.close();
+                }
+                if ui.button("Delete").clicked() {
+                    delete = Some(tag.id);
+                    ui.close();
+                }
+            });
+        }
+
+        if let Some((tag_id, name)) = rename {
+            self.renaming_tag = None;
+            self.rename_tag(tag_id, &name);
+        }
+        if let Some(tag_id) = delete {
+            self.delete_tag(tag_id);
+        }
+        if let Some(scope) = new_scope {
+            self.set_scope(scope);
+        }
+    }
+
+    /// Favorite, rating and color label of the selected sample; changes apply
+    /// to the whole multi-selection.
+    /// Solo and mute buttons for each channel of a multichannel sample.
+    fn channels_view(&mut self, ui: &mut Ui) {
+        let labels = self.audio_player.source_layout.channel_labels();
+        if labels.len() < 2 {
+            return;
+        }
+        let mut change = None;
+
+        ui.horizontal_wrapped(|ui| {
+            ui.weak("Channels");
+            for (channel, label) in labels.into_iter().enumerate() {
+                let state = self.audio_player.channel_states[channel];
+                ui.separator();
+                ui.label(label);
+                if ui
+                    .selectable_label(state == ChannelState::Soloed, "S")
+                    .on_hover_text(format!("Solo {}", label))
+                    .clicked()
+                {
+                    let soloed = state != ChannelState::Soloed;
+                    change = Some((channel, soloed.then_some(ChannelState::Soloed)));
+                }
+                if ui
+                    .selectable_label(state == ChannelState::Muted, "M")
+                    .on_hover_text(format!("Mute {}", label))
+                    .clicked()
+                {
+                    let muted = state != ChannelState::Muted;
+                    change = Some((channel, muted.then_some(ChannelState::Muted)));
+                }
+            }
+        });
+
+        if let Some((channel, state)) = change {
+            self.audio_player
+                .set_channel_state(channel, state.unwrap_or_default());
+        }
+    }
+
+    fn marks_view(&mut self, ui: &mut Ui) {
+        let Some(sample) = &self.selected_sample else {
+            return;
+        };
+        let (favorite, rating, color_label) = (sample.favorite, sample.rating, sample.color_label);
+        let mut mark = None;
+
+        ui.horizontal(|ui| {
+            if ui
+                .selectable_label(favorite, "♥")
+                .on_hover_text("Favorite (F)")
+                .clicked()
+            {
+                mark = Some(SampleMark::Favorite(!favorite));
+            }
+
+            ui.separator();
+            for stars in 1..=5u8 {
+                let star = if stars <= rating { "★" } else { "☆" };
+                let response = ui
+                    .add(egui::Label::new(star).sense(Sense::click()))
+                    .on_hover_text(format!("{} stars ({})", stars, stars));
+                if response.clicked() {
+                    // Clicking the current rating clears it
+                    let new_rating = if stars == rating { 0 } else { stars };
+                    mark = Some(SampleMark::Rating(new_rating));
+                }
+            }
+
+            ui.separator();
+            for (idx, label) in ColorLabel::ALL.into_iter().enumerate() {
+                let size = if color_label == Some(label) {
+                    16.0
+                } else {
+                    12.0
+                };
+                let response = color_swatch(ui, label, size).on_hover_text(format!(
+                    "{} (Ctrl+{})",
+                    label.key(),
+                    idx + 1
+                ));
+                if response.clicked() {
+                    let new_label = (color_label != Some(label)).then_some(label);
+                    mark = Some(SampleMark::ColorLabel(new_label));
+                }
+            }
+        });
+
+        if let Some(mark) = mark {
+            self.mark_selection(mark);
+        }
+    }
+
+    /// Tags of the selected sample plus the tag editor, which applies to the
+    /// whole multi-selection.
+    fn tags_view(&mut self, ui: &mut Ui) {
+        let selection_count = self.selected_sample_ids().len();
+        if selection_count == 0 {
+            return;
+        }
+
+        let mut add = None;
+        let mut remove = None;
+
+        ui.horizontal_wrapped(|ui| {
+            if selection_count > 1 {
+                ui.label(format!("Tags ({} selected):", selection_count));
+            } else {
+                ui.label("Tags:");
+            }
+            for tag in &self.selected_tags {
+                // Auto-tags are set in italics so they don't pass for the user's own
+                let (text, hover) = match tag.auto_confidence {
+                    Some(confidence) => (
+                        egui::RichText::new(format!("{} ×", tag.name)).italics(),
+                        format!(
+                            "Classified automatically, {:.0}% confidence. \
+                             Add the tag by hand to keep it; click to remove",
+                            confidence * 100.0
+                        ),
+                    ),
+                    None => (
+                        egui::RichText::new(format!("{} ×", tag.name)),
+                        "Remove from the selected samples".to_string(),
+                    ),
+                };
+                if ui.small_button(text).on_hover_text(hover).clicked() {
+                    remove = Some(tag.id);
+                }
+            }
+
+            if !self.tag_editor_open {
+                if ui.small_button("+ Tag").on_hover_text("T").clicked() {
+                    self.tag_editor_open = true;
+                }
+                return;
+            }
+
+            let mut output = egui::TextEdit::singleline(&mut self.tag_input)
+                .hint_text("Tag name, Tab completes")
+                .desired_width(160.0)
+                // Keep Tab for completion instead of moving focus
+                .lock_focus(true)
+                .show(ui);
+            let response = &output.response;
+
+            if response.lost_focus() {
+                if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    // Stay open so several tags can be typed in a row
+                    add = Some(self.tag_input.clone());
+                } else {
+                    self.tag_editor_open = false;
+                    self.tag_input.clear();
+                }
+            } else if !response.has_focus() {
+                response.request_focus();
+            }
+
+            if response.has_focus()
+                && ui.input(|i| i.key_pressed(egui::Key::Tab))
+                && let Some((tag, _)) = self.tag_suggestions().first()
+            {
+                self.tag_input = tag.name.clone();
+                let end = egui::text::CCursor::new(self.tag_input.chars().count());
+                output
+                    .state
+                    .cursor
+                    .set_char_range(Some(egui::text::CCursorRange::one(end)));
+                output.state.store(ui.ctx(), output.response.id);
+            }
+        });
+
+        if self.tag_editor_open && !self.tag_input.trim().is_empty() {
+            ui.horizontal_wrapped(|ui| {
+                for (tag, count) in self.tag_suggestions() {
+                    if ui
+                        .small_button(format!("{} ({})", tag.name, count))
+                        .clicked()
+                    {
+                        add = Some(tag.name.clone());
+                    }
                 }
             });
+        }
+
+        if let Some(name) = add {
+            self.tag_input.clear();
+            self.tag_selection(&name);
+        }
+        if let Some(tag_id) = remove {
+            self.untag_selection(tag_id);
+        }
+    }
+
+    /// Existing tags starting with what has been typed into the tag editor.
+    fn tag_suggestions(&self) -> Vec<&(Tag, usize)> {
+        let input = self.tag_input.trim().to_lowercase();
+        self.tags
+            .iter()
+            .filter(|(tag, _)| {
+                let name = tag.name.to_lowercase();
+                name.starts_with(&input) && name != input
+            })
+            .take(8)
+            .collect()
+    }
+
+    fn import_progress_view(&mut self, ui: &mut Ui) {
+        if !self.is_importing()
+            && self.import_progress.errors.is_empty()
+            && self.import_progress.failed.is_none()
+        {
+            return;
+        }
+
+        let progress = &self.import_progress;
+        let mut cancel = false;
+
+        if self.import_worker.is_some() {
+            ui.ctx()
+                .request_repaint_after(std::time::Duration::from_millis(100));
+
+            ui.horizontal(|ui| {
+                match progress.total {
+                    Some(total) => {
+                        let fraction = if total == 0 {
+                            1.0
+                        } else {
+                            progress.done as f32 / total as f32
+                        };
+                        ui.add(
+                            egui::ProgressBar::new(fraction)
+                                .desired_width(300.0)
+                                .text(format!("{} / {} files", progress.done, total)),
+                        );
+                    }
+                    None => {
+                        ui.spinner();
+                        ui.label(format!("Scanning folders… {} files found", progress.found));
+                    }
+                }
+                if ui.button("Cancel").clicked() {
+                    cancel = true;
+                }
+            });
+
+            if let Some(path) = &progress.current_path {
+                ui.weak(path.to_string_lossy());
+            }
+        } else if let Some(error) = &progress.failed {
+            ui.colored_label(
+                ui.visuals().error_fg_color,
+                format!("Import failed: {}", error),
+            );
+        } else if progress.cancelled {
+            ui.label("Import cancelled");
+        }
+
+        if !progress.errors.is_empty() {
+            egui::CollapsingHeader::new(format!("Import errors ({})", progress.errors.len())).show(
+                ui,
+                |ui| {
+                    egui::ScrollArea::vertical()
+                        .max_height(120.0)
+                        .show(ui, |ui| {
+                            for (path, message) in &progress.errors {
+                                ui.label(format!("{}: {}", path.display(), message));
+                            }
+                        });
+                },
+            );
+        }
+
+        if cancel {
+            self.cancel_import();
+        }
+    }
+
+    fn analysis_progress_view(&mut self, ui: &mut Ui) {
+        let Some(worker) = &self.analysis_worker else {
+            return;
+        };
+        let progress = &self.analysis_progress;
+        if progress.total == 0 {
+            return;
+        }
+
+        ui.ctx()
+            .request_repaint_after(std::time::Duration::from_millis(100));
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::ProgressBar::new(progress.done as f32 / progress.total as f32)
+                    .desired_width(300.0)
+                    .text(format!(
+                        "Analyzing audio… {} / {}",
+                        progress.done, progress.total
+                    )),
+            );
+            if ui.button("Stop").clicked() {
+                worker.cancel();
+            }
+        });
     }
 
     fn details_view(&mut self, ui: &mut Ui) {
         ui.ctx()
             .request_repaint_after(std::time::Duration::from_millis(16));
 
-        ui.label(self.selected_sample.name.clone());
+        ui.horizontal(|ui| {
+            match &self.selected_sample {
+                Some(sample) => {
+                    ui.label(sample.name.clone());
+                    if self.audio_player.is_loaded() {
+                        let duration = self.audio_player.get_duration_seconds();
+                        ui.weak(format!(
+                            "{:.2}s / {:.2}s",
+                            self.audio_player.get_position_percentage() * duration,
+                            duration
+                        ));
+                    }
+                    if let Some(note) = sample.root_note {
+                        ui.weak(format!("Root {}", note_name(note)));
+                    }
+                    if let Some(frequency) = sample.pitch
+                        && let Some(name) = pitch_name(frequency)
+                    {
+                        let response = ui.weak(format!("Pitch {} ({:.1} Hz)", name, frequency));
+                        if let Some(confidence) = sample.pitch_confidence {
+                            response.on_hover_text(format!(
+                                "Detected, {:.0}% confidence",
+                                confidence * 100.0
+                            ));
+                        }
+                    }
+                    if let Some(bpm) = sample.bpm.or(sample.tempo) {
+                        let response = ui.weak(format!("{:.1} BPM", bpm));
+                        if let Some(confidence) = sample.bpm_confidence
+                            && confidence < 1.0
+                        {
+                            response.on_hover_text(format!(
+                                "Detected, {:.0}% confidence",
+                                confidence * 100.0
+                            ));
+                        }
+                    }
+                    if let Some(loudness) = sample.loudness {
+                        ui.weak(format!("{:.1} LUFS", loudness.integrated))
+                            .on_hover_text(format!(
+                                "Short-term max {:.1} LUFS, true peak {:.1} dBTP, RMS {:.1} dBFS",
+                                loudness.short_term, loudness.true_peak, loudness.rms
+                            ));
+                    }
+                    if let Some(key) = &sample.estimated_key {
+                        let text = match camelot(key) {
+                            Some(camelot) => format!("Key {} ({})", key, camelot),
+                            None => format!("Key {}", key),
+                        };
+                        let response = ui.weak(text);
+                        if let Some(confidence) = sample.key_confidence
+                            && confidence < 1.0
+                        {
+                            response.on_hover_text(format!(
+                                "Detected, {:.0}% confidence",
+                                confidence * 100.0
+                            ));
+                        }
+                    }
+                }
+                None => {
+                    ui.weak("No sample selected");
+                }
+            }
+            if ui.checkbox(&mut self.loop_playback, "Loop").changed() {
+                self.audio_player.set_loop(self.loop_playback);
+            }
+            let mut normalize = self.normalize_playback;
+            if ui
+                .checkbox(&mut normalize, "Normalize")
+                .on_hover_text("Play every sample at the same loudness")
+                .changed()
+            {
+                self.set_normalize_playback(normalize);
+            }
+            let mut fast_resampling = self.fast_resampling;
+            if ui
+                .checkbox(&mut fast_resampling, "Fast resampling")
+                .on_hover_text(
+                    "Convert sample rates quickly rather than cleanly, for browsing; \
+                     applies to the next sample played",
+                )
+                .changed()
+            {
+                self.set_fast_resampling(fast_resampling);
+            }
+            let xruns = self.audio_player.xrun_count();
+            if xruns > 0 {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("{} dropouts", xruns))
+                    .on_hover_text("Times playback ran out of decoded audio");
+            }
+        });
+
+        self.channels_view(ui);
+        self.marks_view(ui);
+        self.tags_view(ui);
+
+        if !self.selected_metadata.is_empty() {
+            egui::CollapsingHeader::new(format!("Metadata ({})", self.selected_metadata.len()))
+                .id_salt("sample_metadata")
+                .show(ui, |ui| {
+                    egui::Grid::new("sample_metadata_grid")
+                        .num_columns(3)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for tag in &self.selected_metadata {
+                                ui.strong(&tag.key);
+                                ui.label(&tag.value);
+                                ui.weak(&tag.source);
+                                ui.end_row();
+                            }
+                        });
+                });
+        }
 
         let (rect, response) =
             ui.allocate_exact_size(vec2(ui.available_width(), 100.0), Sense::click_and_drag());
 
         // Handle clicks on waveform
-        if response.clicked() || response.dragged() {
-            if let Some(pos) = response.hover_pos() {
-                println!("pos: {}", pos.x);
-                println!("rect.width: {}", rect.width());
-                println!("rect.min.x: {}", rect.min.x);
-                let relative_x = (pos.x - rect.min.x) / rect.width();
-                println!("relative_x: {}", relative_x);
-                self.audio_player
-                    .seek_to_position_percentage(relative_x);
-            }
+        if (response.clicked() || response.dragged())
+            && let Some(pos) = response.hover_pos()
+        {
+            println!("pos: {}", pos.x);
+            println!("rect.width: {}", rect.width());
+            println!("rect.min.x: {}", rect.min.x);
+            let relative_x = (pos.x - rect.min.x) / rect.width();
+            println!("relative_x: {}", relative_x);
+            self.audio_player.seek_to_position_percentage(relative_x);
         }
         let available_width = rect.width();
         let available_height = rect.height();
@@ -124,18 +1355,18 @@ impl SampleDuckApp {
         };
 
         // Draw waveform
-        let points: Vec<Shape> = self
-            .audio_player
-            .peak_samples
+        let peaks = self.audio_player.peak_samples();
+        let position = self.audio_player.get_position_percentage();
+        let points: Vec<Shape> = peaks
             .iter()
             .enumerate()
             .map(|(i, &(min, max))| {
-                let x = i as f32 / self.audio_player.peak_samples.len() as f32;
+                let x = i as f32 / peaks.len() as f32;
                 let min_pos = to_screen(x, min);
                 let max_pos = to_screen(x, max);
 
                 // Different color before/after playhead
-                let color = if x <= self.audio_player.get_position_percentage() {
+                let color = if x <= position {
                     Color32::from_rgb(100, 200, 255) // Bright blue for played part
                 } else {
                     Color32::WHITE // White for unplayed part
@@ -147,6 +1378,48 @@ impl SampleDuckApp {
 
         ui.painter().extend(points);
 
+        // Shade the embedded loop region
+        if let Some((start, end)) = self
+            .selected_sample
+            .as_ref()
+            .and_then(|sample| sample.loop_start.zip(sample.loop_end))
+        {
+            let start_x =
+                rect.min.x + self.audio_player.frame_to_percentage(start) * available_width;
+            let end_x = rect.min.x + self.audio_player.frame_to_percentage(end) * available_width;
+            let loop_rect = egui::Rect::from_x_y_ranges(
+                start_x.max(rect.min.x)..=end_x.min(rect.max.x),
+                rect.y_range(),
+            );
+            ui.painter().rect_filled(
+                loop_rect,
+                0.0,
+                Color32::from_rgba_unmultiplied(100, 255, 150, 30),
+            );
+        }
+
+        // Draw cue markers
+        for cue in &self.selected_cues {
+            let x = rect.min.x + self.audio_player.frame_to_percentage(cue.frame) * available_width;
+            if x > rect.max.x {
+                continue;
+            }
+            let color = Color32::from_rgb(255, 200, 80);
+            ui.painter().line_segment(
+                [pos2(x, rect.min.y), pos2(x, rect.max.y)],
+                Stroke::new(1.0, color),
+            );
+            if let Some(label) = &cue.label {
+                ui.painter().text(
+                    pos2(x + 2.0, rect.min.y),
+                    egui::Align2::LEFT_TOP,
+                    label,
+                    egui::FontId::proportional(11.0),
+                    color,
+                );
+            }
+        }
+
         // Draw position marker
         let playhead_x =
             rect.min.x + (self.audio_player.get_position_percentage() * available_width);
@@ -161,34 +1434,126 @@ impl SampleDuckApp {
         );
     }
 
-    fn select_sample(&mut self, sample_idx: usize) {
-        if self.samples.len() > sample_idx {
-            self.selected_sample_idx = sample_idx;
-            self.selected_sample = self.samples[sample_idx].clone();
-            match self.audio_player.load(&self.selected_sample.path) {
-                Ok(_) => {
-                    self.audio_player.play();
-                }
-                Err(error) => {
-                    println!("Error: {}", error);
-                }
+    /// Selects the sample shown in table row `row` and starts playing it.
+    fn select_sample(&mut self, row: usize) {
+        let Some(&sample_idx) = self.visible_rows.get(row) else {
+            return;
+        };
+
+        self.selected_ids = [self.samples[sample_idx].id].into();
+        self.focus_sample(row);
+    }
+
+    /// Auditions the sample in `row` without changing the multi-selection.
+    fn focus_sample(&mut self, row: usize) {
+        let Some(&sample_idx) = self.visible_rows.get(row) else {
+            return;
+        };
+
+        self.selected_sample_idx = Some(row);
+        self.selected_sample = Some(self.samples[sample_idx].clone());
+        self.load_selected_details();
+        match self.audio_player.load(&self.samples[sample_idx].path) {
+            Ok(_) => {
+                self.audio_player.play();
             }
+            Err(error) => {
+                println!("Error: {}", error);
+            }
+        }
+    }
+
+    /// Adds or removes `row` from the multi-selection (Ctrl/Cmd-click).
+    fn toggle_selected(&mut self, row: usize) {
+        let Some(&sample_idx) = self.visible_rows.get(row) else {
+            return;
+        };
+
+        if self.selected_ids.is_empty()
+            && let Some(focused) = &self.selected_sample
+        {
+            self.selected_ids.insert(focused.id);
+        }
+        let id = self.samples[sample_idx].id;
+        if !self.selected_ids.remove(&id) {
+            self.selected_ids.insert(id);
         }
     }
 
-    fn click_sample(&mut self, sample_idx: usize, row_response: &egui::Response) {
-        if row_response.clicked() {
-            self.select_sample(sample_idx);
+    /// Selects every row between the auditioned sample and `row` (Shift-click).
+    fn extend_selection(&mut self, row: usize) {
+        let anchor = self.selected_sample_idx.unwrap_or(0);
+        let (first, last) = (anchor.min(row), anchor.max(row));
+        self.selected_ids = self.visible_rows[first..=last.min(self.visible_rows.len() - 1)]
+            .iter()
+            .map(|&idx| self.samples[idx].id)
+            .collect();
+    }
+
+    fn select_next_sample(&mut self, extend: bool) {
+        let row = match self.selected_sample_idx {
+            Some(row) if row + 1 < self.visible_rows.len() => row + 1,
+            Some(_) => return,
+            None => 0,
+        };
+        self.step_selection(row, extend);
+    }
+
+    fn select_prev_sample(&mut self, extend: bool) {
+        if let Some(row) = self.selected_sample_idx
+            && row > 0
+        {
+            self.step_selection(row - 1, extend);
         }
     }
 
-    fn select_next_sample(&mut self) {
-        self.select_sample(self.selected_sample_idx + 1);
+    fn step_selection(&mut self, row: usize, extend: bool) {
+        if extend {
+            if self.selected_ids.is_empty()
+                && let Some(focused) = &self.selected_sample
+            {
+                self.selected_ids.insert(focused.id);
+            }
+            if let Some(&sample_idx) = self.visible_rows.get(row) {
+                self.selected_ids.insert(self.samples[sample_idx].id);
+            }
+            self.focus_sample(row);
+        } else {
+            self.select_sample(row);
+        }
+        self.scroll_to_selected = true;
     }
+}
+
+fn label_color(label: ColorLabel) -> Color32 {
+    match label {
+        ColorLabel::Red => Color32::from_rgb(230, 70, 70),
+        ColorLabel::Orange => Color32::from_rgb(240, 150, 50),
+        ColorLabel::Yellow => Color32::from_rgb(235, 210, 60),
+        ColorLabel::Green => Color32::from_rgb(90, 190, 90),
+        ColorLabel::Blue => Color32::from_rgb(80, 140, 235),
+        ColorLabel::Purple => Color32::from_rgb(170, 100, 220),
+        ColorLabel::Gray => Color32::from_rgb(150, 150, 150),
+    }
+}
+
+/// A clickable colored dot for a color label.
+fn color_swatch(ui: &mut Ui, label: ColorLabel, size: f32) -> egui::Response {
+    let (rect, response) = ui.allocate_exact_size(vec2(size, size), Sense::click());
+    ui.painter()
+        .circle_filled(rect.center(), size / 2.0, label_color(label));
+    response
+}
 
-    fn select_prev_sample(&mut self) {
-        if self.selected_sample_idx > 0 {
-            self.select_sample(self.selected_sample_idx - 1);
+/// The confidence of a column's value if the analysis pass detected it, with
+/// the confidence below which it is shown dimmed.
+fn detected_confidence(column: SampleColumn, sample: &Sample) -> Option<(f32, f32)> {
+    match column {
+        SampleColumn::Bpm => sample.bpm_confidence.map(|c| (c, LOW_BPM_CONFIDENCE)),
+        SampleColumn::Key | SampleColumn::Camelot => {
+            sample.key_confidence.map(|c| (c, LOW_KEY_CONFIDENCE))
         }
+        SampleColumn::Pitch => sample.pitch_confidence.map(|c| (c, LOW_PITCH_CONFIDENCE)),
+        _ => None,
     }
 }
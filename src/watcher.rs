@@ -0,0 +1,368 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rusqlite::Connection;
+
+use crate::db::open_db;
+use crate::sample::LibraryRoot;
+use crate::scanner::{ImportEvent, ScanOptions, reconcile_paths};
+
+/// How long the watched folders have to stay quiet before a batch of changes
+/// is indexed, so copying a whole pack is handled in one go.
+const DEBOUNCE: Duration = Duration::from_millis(750);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub enum WatchEvent {
+    Import(ImportEvent),
+    /// The kernel dropped events, so only a full rescan can be trusted
+    RescanNeeded,
+}
+
+/// Watches every library root for created, removed and renamed audio files
+/// and indexes them on a background thread.
+pub struct LibraryWatcher {
+    receiver: Receiver<WatchEvent>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl LibraryWatcher {
+    pub fn spawn(
+        db_path: &str,
+        roots: Vec<LibraryRoot>,
+        options: ScanOptions,
+    ) -> std::io::Result<Self> {
+        let mut backend = backend::Backend::new()?;
+        let (sender, receiver) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let db_path = db_path.to_string();
+        let stop_worker = Arc::clone(&stop);
+        let handle = thread::spawn(move || {
+            // Walking large libraries takes a while, so the caller doesn't wait for it
+            for root in &roots {
+                backend.watch_tree(root);
+            }
+
+            let conn = match open_db(&db_path) {
+                Ok(conn) => conn,
+                Err(error) => {
+                    println!("Error: watcher failed to open database: {}", error);
+                    return;
+                }
+            };
+            if let Err(error) = conn.busy_timeout(Duration::from_secs(5)) {
+                println!("Error: watcher failed to configure database: {}", error);
+            }
+
+            run(&mut backend, &conn, &roots, options, &stop_worker, &sender);
+        });
+
+        Ok(Self {
+            receiver,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// Returns every event that arrived since the last call without blocking.
+    pub fn poll_events(&self) -> Vec<WatchEvent> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl Drop for LibraryWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run(
+    backend: &mut backend::Backend,
+    conn: &Connection,
+    roots: &[LibraryRoot],
+    options: ScanOptions,
+    stop: &AtomicBool,
+    sender: &Sender<WatchEvent>,
+) {
+    let mut pending: HashSet<PathBuf> = HashSet::new();
+    let mut last_event = Instant::now();
+
+    while !stop.load(Ordering::Relaxed) {
+        match backend.read_events(POLL_INTERVAL, roots) {
+            Ok(backend::Changes::Paths(paths)) => {
+                if !paths.is_empty() {
+                    pending.extend(paths);
+                    last_event = Instant::now();
+                }
+            }
+            Ok(backend::Changes::Overflow) => {
+                pending.clear();
+                let _ = sender.send(WatchEvent::RescanNeeded);
+            }
+            Err(error) => {
+                println!("Error: watcher stopped: {}", error);
+                return;
+            }
+        }
+
+        if pending.is_empty() || last_event.elapsed() < DEBOUNCE {
+            continue;
+        }
+
+        let batch: Vec<PathBuf> = pending.drain().collect();
+        let result = reconcile_paths(conn, roots, options, &batch, &mut |event| {
+            let _ = sender.send(WatchEvent::Import(event));
+        });
+        match result {
+            Ok(summary) => {
+                let _ = sender.send(WatchEvent::Import(ImportEvent::Finished {
+                    summary,
+                    cancelled: false,
+                    error: None,
+                }));
+            }
+            Err(error) => println!("Error: failed to index watched changes: {}", error),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod backend {
+    use std::collections::HashMap;
+    use std::ffi::{CString, OsStr};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use walkdir::WalkDir;
+
+    use crate::sample::LibraryRoot;
+    use crate::scanner::{is_ignored, is_path_ignored, root_for_path};
+
+    const WATCH_MASK: u32 = libc::IN_CLOSE_WRITE
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO
+        | libc::IN_DELETE_SELF
+        | libc::IN_ONLYDIR;
+
+    pub enum Changes {
+        Paths(Vec<PathBuf>),
+        Overflow,
+    }
+
+    /// Thin wrapper around an inotify instance watching every folder below
+    /// the library roots.
+    pub struct Backend {
+        fd: libc::c_int,
+        watches: HashMap<libc::c_int, PathBuf>,
+    }
+
+    impl Backend {
+        pub fn new() -> io::Result<Self> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self {
+                fd,
+                watches: HashMap::new(),
+            })
+        }
+
+        /// inotify is not recursive, so every folder gets its own watch.
+        pub fn watch_tree(&mut self, root: &LibraryRoot) {
+            self.watch_dir(root, Path::new(&root.path));
+        }
+
+        /// Watches `dir` and the folders below it, leaving out those the
+        /// root's ignore patterns exclude.
+        fn watch_dir(&mut self, root: &LibraryRoot, dir: &Path) {
+            if is_path_ignored(root, dir) {
+                return;
+            }
+            let root_path = Path::new(&root.path);
+            let walker = WalkDir::new(dir)
+                .follow_links(root.follow_symlinks)
+                .into_iter()
+                .filter_entry(|entry| entry.depth() == 0 || !is_ignored(root, root_path, entry))
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_type().is_dir());
+
+            for entry in walker {
+                if let Err(error) = self.add_watch(entry.path()) {
+                    println!(
+                        "Error: failed to watch {}: {}",
+                        entry.path().display(),
+                        error
+                    );
+                }
+            }
+        }
+
+        fn add_watch(&mut self, path: &Path) -> io::Result<()> {
+            let c_path = CString::new(path.as_os_str().as_bytes())?;
+            let wd = unsafe { libc::inotify_add_watch(self.fd, c_path.as_ptr(), WATCH_MASK) };
+            if wd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.watches.insert(wd, path.to_path_buf());
+            Ok(())
+        }
+
+        /// Stops watching `dir` and the folders below it, such as after it was
+        /// moved away; their watches would report changes under the old path.
+        fn unwatch_tree(&mut self, dir: &Path) {
+            let fd = self.fd;
+            self.watches.retain(|&wd, path| {
+                if !path.starts_with(dir) {
+                    return true;
+                }
+                unsafe { libc::inotify_rm_watch(fd, wd) };
+                false
+            });
+        }
+
+        /// Waits up to `timeout` for events and returns the paths they touched.
+        pub fn read_events(
+            &mut self,
+            timeout: Duration,
+            roots: &[LibraryRoot],
+        ) -> io::Result<Changes> {
+            let mut poll_fd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) };
+            if ready < 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() == io::ErrorKind::Interrupted {
+                    return Ok(Changes::Paths(Vec::new()));
+                }
+                return Err(error);
+            }
+
+            let mut paths = Vec::new();
+            let mut buf = [0u8; 64 * 1024];
+            loop {
+                let read = unsafe {
+                    libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+                };
+                if read < 0 {
+                    let error = io::Error::last_os_error();
+                    if error.kind() == io::ErrorKind::WouldBlock {
+                        break;
+                    }
+                    return Err(error);
+                }
+                if read == 0 {
+                    break;
+                }
+
+                let mut offset = 0;
+                while offset + size_of::<libc::inotify_event>() <= read as usize {
+                    let event = unsafe {
+                        std::ptr::read_unaligned(
+                            buf.as_ptr().add(offset) as *const libc::inotify_event
+                        )
+                    };
+                    let name_start = offset + size_of::<libc::inotify_event>();
+                    let name_bytes = &buf[name_start..name_start + event.len as usize];
+                    let name_len = name_bytes
+                        .iter()
+                        .position(|&b| b == 0)
+                        .unwrap_or(name_bytes.len());
+                    let name = OsStr::from_bytes(&name_bytes[..name_len]);
+                    offset = name_start + event.len as usize;
+
+                    if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                        return Ok(Changes::Overflow);
+                    }
+                    if event.mask & (libc::IN_IGNORED | libc::IN_DELETE_SELF) != 0 {
+                        self.watches.remove(&event.wd);
+                        continue;
+                    }
+
+                    let Some(dir) = self.watches.get(&event.wd) else {
+                        continue;
+                    };
+                    let path = dir.join(name);
+                    let is_dir = event.mask & libc::IN_ISDIR != 0;
+
+                    if is_dir && event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                        if let Some(root) = root_for_path(roots, &path) {
+                            let root = root.clone();
+                            self.watch_dir(&root, &path);
+                        }
+                    } else if is_dir && event.mask & libc::IN_MOVED_FROM != 0 {
+                        // Watched again under its new path if it moved within the library
+                        self.unwatch_tree(&path);
+                    } else if !is_dir && event.mask & libc::IN_CREATE != 0 {
+                        // Wait for IN_CLOSE_WRITE so half-copied files aren't probed
+                        continue;
+                    }
+
+                    paths.push(path);
+                }
+            }
+
+            Ok(Changes::Paths(paths))
+        }
+    }
+
+    impl Drop for Backend {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.fd);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod backend {
+    use std::io;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use crate::sample::LibraryRoot;
+
+    pub enum Changes {
+        Paths(Vec<PathBuf>),
+        Overflow,
+    }
+
+    pub struct Backend;
+
+    impl Backend {
+        pub fn new() -> io::Result<Self> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "library watching is only supported on Linux",
+            ))
+        }
+
+        pub fn watch_tree(&mut self, _root: &LibraryRoot) {}
+
+        pub fn read_events(
+            &mut self,
+            _timeout: Duration,
+            _roots: &[LibraryRoot],
+        ) -> io::Result<Changes> {
+            Ok(Changes::Paths(Vec::new()))
+        }
+    }
+}